@@ -0,0 +1,632 @@
+use crate::core::config::{Config, DeviceSettings};
+use crate::core::journal::JournalEntry;
+use crate::core::profile::{load_profile, Profile};
+use crate::core::queue::{run_job, ExpectedState, Job};
+use crate::core::reconcile::{apply, apply_to_devices, plan_from_device};
+use crate::core::recovery::{is_risky, save_restore_point, RestorePackage};
+use crate::core::sync::{get_devices_list, package_action, CorePackage, Phone, User};
+use crate::core::uad_lists::{load_debloat_lists, PackageState, Removal, UadList};
+use crate::core::utils::fetch_packages;
+use crate::gui::widgets::package_row::PackageRow;
+use iced::futures::executor::block_on;
+use serde::Serialize;
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1; // At least one package action failed
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NO_DEVICE: i32 = 3;
+
+pub const USAGE: &str = "\
+Usage: uad_gui [COMMAND] [OPTIONS] [PACKAGES...]
+
+Starts the GUI when no command is given.
+
+Commands:
+  devices                 List the connected devices
+  list                    List the system packages of the device
+  remove [PACKAGES...]    Uninstall (or disable/suspend) packages
+  restore [PACKAGES...]   Restore (or enable/unsuspend) packages
+  apply <PROFILE>         Bring the device to the state described by a saved profile
+  help                    Print this message
+
+Options:
+  -d, --device <SERIAL>   Target device (required if several devices are connected)
+      --all-devices       Apply the profile to every connected device at once (only for `apply`)
+  -u, --user <ID>         Target user (default: first user of the device)
+  -r, --removal <TIER>    recommended, advanced, expert, unsafe, unlisted or all
+  -l, --list <LIST>       aosp, carrier, google, misc, oem, pending, unlisted, third-party or all
+  -s, --state <STATE>     enabled, disabled, uninstalled, suspended or all (only for `list`)
+      --disable           Disable packages instead of uninstalling them
+      --suspend           Suspend packages instead of uninstalling them (Android 9+)
+      --uninstall         Uninstall packages instead of disabling or suspending them
+      --all-users         Apply the action to all the users of the device
+      --single-user       Only apply the action to the selected user
+      --third-party       Include the user-installed apps (implied by `--list third-party`)
+      --expert            Allow the removal of `unsafe` packages
+      --offline           Do not download the latest debloat lists
+      --dry-run           Only print what `apply` would do
+
+Results are printed to stdout as JSON. Exit codes: 0 = success, 1 = at least one action \
+failed, 2 = invalid usage, 3 = device not found.";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliCommand {
+    Devices,
+    List,
+    Remove(Vec<String>),
+    Restore(Vec<String>),
+    Apply(String),
+    Help,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CliOptions {
+    pub device: Option<String>,
+    pub all_devices: bool,
+    pub user: Option<u16>,
+    pub removal: Option<Removal>,
+    pub list: Option<UadList>,
+    pub state: Option<PackageState>,
+    pub disable_mode: Option<bool>,
+    pub suspend_mode: Option<bool>,
+    pub multi_user_mode: Option<bool>,
+    pub third_party: bool,
+    pub expert: bool,
+    pub offline: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct PackageOutput<'a> {
+    name: &'a str,
+    state: PackageState,
+    list: UadList,
+    removal: Removal,
+    description: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    installer: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    install_date: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct CommandOutput {
+    command: String,
+    success: bool,
+}
+
+#[derive(Serialize)]
+struct ActionOutput {
+    package: String,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    commands: Vec<CommandOutput>,
+}
+
+/// Returns `None` if the arguments don't start with a CLI command (i.e. the GUI should be started).
+pub fn parse_args(args: &[String]) -> Option<Result<(CliCommand, CliOptions), String>> {
+    let command = match args.first().map(String::as_str) {
+        Some("devices") => CliCommand::Devices,
+        Some("list") => CliCommand::List,
+        Some("remove") => CliCommand::Remove(vec![]),
+        Some("restore") => CliCommand::Restore(vec![]),
+        Some("apply") => CliCommand::Apply(String::new()),
+        Some("help" | "-h" | "--help") => CliCommand::Help,
+        _ => return None,
+    };
+    Some(parse_options(command, &args[1..]))
+}
+
+fn parse_options(
+    mut command: CliCommand,
+    args: &[String],
+) -> Result<(CliCommand, CliOptions), String> {
+    let mut options = CliOptions::default();
+    let mut packages = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or(format!("Missing value for `{}`", name))
+        };
+        match arg.as_str() {
+            "-d" | "--device" => options.device = Some(value(arg)?),
+            "--all-devices" => options.all_devices = true,
+            "-u" | "--user" => {
+                let v = value(arg)?;
+                options.user = Some(v.parse().map_err(|_| format!("Invalid user id: {}", v))?);
+            }
+            "-r" | "--removal" => {
+                let v = value(arg)?;
+                options.removal =
+                    Some(parse_enum(&Removal::ALL, &v).ok_or(format!("Unknown removal: {}", v))?);
+            }
+            "-l" | "--list" => {
+                let v = value(arg)?;
+                options.list =
+                    Some(parse_enum(&UadList::ALL, &v).ok_or(format!("Unknown list: {}", v))?);
+            }
+            "-s" | "--state" => {
+                let v = value(arg)?;
+                options.state = Some(
+                    parse_enum(&PackageState::ALL, &v)
+                        .ok_or(format!("Unknown package state: {}", v))?,
+                );
+            }
+            "--disable" => {
+                options.disable_mode = Some(true);
+                options.suspend_mode = Some(false);
+            }
+            "--suspend" => options.suspend_mode = Some(true),
+            "--uninstall" => {
+                options.disable_mode = Some(false);
+                options.suspend_mode = Some(false);
+            }
+            "--all-users" => options.multi_user_mode = Some(true),
+            "--single-user" => options.multi_user_mode = Some(false),
+            "--third-party" => options.third_party = true,
+            "--expert" => options.expert = true,
+            "--offline" => options.offline = true,
+            "--dry-run" => options.dry_run = true,
+            a if a.starts_with('-') => return Err(format!("Unknown option: {}", a)),
+            p => packages.push(p.to_string()),
+        }
+    }
+
+    if options.all_devices && !matches!(command, CliCommand::Apply(_)) {
+        return Err("`--all-devices` is only supported by `apply`".to_string());
+    }
+
+    match &mut command {
+        CliCommand::Remove(p) | CliCommand::Restore(p) => {
+            if packages.is_empty() && options.removal.is_none() {
+                return Err("Either package names or `--removal` must be given".to_string());
+            }
+            *p = packages;
+        }
+        CliCommand::Apply(profile) => match packages.as_slice() {
+            [name] => *profile = name.clone(),
+            _ => return Err("`apply` needs a single profile name".to_string()),
+        },
+        _ => {
+            if !packages.is_empty() {
+                return Err(format!("Unexpected argument: {}", packages[0]));
+            }
+        }
+    }
+    Ok((command, options))
+}
+
+// Matches the first word of the `Display` implementation (e.g. `all` for "All lists")
+fn parse_enum<T: Copy + ToString>(all: &[T], value: &str) -> Option<T> {
+    let value = value.to_lowercase();
+    all.iter().copied().find(|v| {
+        v.to_string()
+            .to_lowercase()
+            .split_whitespace()
+            .next()
+            .is_some_and(|w| w == value)
+    })
+}
+
+pub fn run(command: CliCommand, options: &CliOptions) -> i32 {
+    match command {
+        CliCommand::Help => {
+            println!("{}", USAGE);
+            EXIT_SUCCESS
+        }
+        CliCommand::Devices => {
+            print_json(&block_on(get_devices_list()));
+            EXIT_SUCCESS
+        }
+        CliCommand::List => list(options),
+        CliCommand::Remove(packages) => apply_action(&packages, true, options),
+        CliCommand::Restore(packages) => apply_action(&packages, false, options),
+        CliCommand::Apply(profile) => apply_profile(&profile, options),
+    }
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => error!("Could not serialize output: {}", e),
+    }
+}
+
+fn select_device(options: &CliOptions) -> Result<Phone, i32> {
+    let (devices, not_ready) = ready_devices();
+    if let Some(serial) = &options.device {
+        // The reason has already been printed
+        if not_ready.iter().any(|d| d.adb_id == *serial) {
+            return Err(EXIT_NO_DEVICE);
+        }
+    }
+    let phone = match &options.device {
+        Some(serial) => devices.into_iter().find(|d| d.adb_id == *serial),
+        None if devices.len() > 1 => {
+            eprintln!("Several devices are connected. Use `--device <SERIAL>`");
+            return Err(EXIT_USAGE);
+        }
+        None => devices.into_iter().next(),
+    };
+
+    match phone {
+        Some(phone) => Ok(phone),
+        None => {
+            eprintln!("Device not found");
+            Err(EXIT_NO_DEVICE)
+        }
+    }
+}
+
+/// (ready devices, devices that can't be used yet). The reason is printed for the latter.
+fn ready_devices() -> (Vec<Phone>, Vec<Phone>) {
+    let (ready, not_ready): (Vec<Phone>, Vec<Phone>) = block_on(get_devices_list())
+        .into_iter()
+        .partition(Phone::is_ready);
+    for phone in &not_ready {
+        eprintln!(
+            "{} ({}) is {}: {}",
+            phone.adb_id,
+            phone.model,
+            phone.status,
+            phone.status.guidance()
+        );
+    }
+    (ready, not_ready)
+}
+
+fn select_user(phone: &Phone, options: &CliOptions) -> Result<User, i32> {
+    match options.user {
+        Some(id) => match phone.user_list.iter().find(|u| u.id == id) {
+            Some(user) => Ok(user.clone()),
+            None => {
+                eprintln!("User {} not found on the device", id);
+                Err(EXIT_USAGE)
+            }
+        },
+        None => Ok(phone
+            .user_list
+            .first()
+            .cloned()
+            .unwrap_or_else(|| User::new(0, 0))),
+    }
+}
+
+fn load_packages(phone: &Phone, user: &User, options: &CliOptions) -> Vec<PackageRow> {
+    let uad_lists = match load_debloat_lists(!options.offline).0 {
+        Ok(list) | Err(list) => list,
+    };
+    let third_party = options.third_party || options.list == Some(UadList::ThirdParty);
+    if phone.user_list.len() <= 1 {
+        fetch_packages(&uad_lists, &phone.adb_id, None, third_party)
+    } else {
+        fetch_packages(&uad_lists, &phone.adb_id, Some(user), third_party)
+    }
+}
+
+fn list(options: &CliOptions) -> i32 {
+    let phone = match select_device(options) {
+        Ok(phone) => phone,
+        Err(code) => return code,
+    };
+    let user = match select_user(&phone, options) {
+        Ok(user) => user,
+        Err(code) => return code,
+    };
+    let packages = load_packages(&phone, &user, options);
+    let output: Vec<PackageOutput> = packages
+        .iter()
+        .filter(|p| matches_filters(p, options))
+        .filter(|p| {
+            options
+                .state
+                .is_none_or(|s| s == PackageState::All || p.state == s)
+        })
+        .map(|p| PackageOutput {
+            name: &p.name,
+            state: p.state,
+            list: p.uad_list,
+            removal: p.removal,
+            description: &p.description,
+            installer: p.installer.as_deref(),
+            install_date: p.install_date.as_deref(),
+        })
+        .collect();
+
+    print_json(&output);
+    EXIT_SUCCESS
+}
+
+fn matches_filters(package: &PackageRow, options: &CliOptions) -> bool {
+    options
+        .removal
+        .is_none_or(|r| r == Removal::All || package.removal == r)
+        && options
+            .list
+            .is_none_or(|l| l == UadList::All || package.uad_list == l)
+}
+
+fn apply_action(names: &[String], remove: bool, options: &CliOptions) -> i32 {
+    let phone = match select_device(options) {
+        Ok(phone) => phone,
+        Err(code) => return code,
+    };
+    let user = match select_user(&phone, options) {
+        Ok(user) => user,
+        Err(code) => return code,
+    };
+    let config = Config::load_configuration_file();
+    let mut settings = config
+        .devices
+        .into_iter()
+        .find(|d| d.device_id == phone.adb_id)
+        .unwrap_or_else(|| DeviceSettings::new(&phone));
+    if let Some(disable_mode) = options.disable_mode {
+        // Disabling packages without root is not possible before Android 6.0
+        settings.disable_mode = disable_mode && phone.android_sdk >= 23;
+    }
+    if let Some(suspend_mode) = options.suspend_mode {
+        // `pm suspend` appeared in Android 9
+        settings.suspend_mode = suspend_mode && phone.android_sdk >= 28;
+    }
+    if let Some(multi_user_mode) = options.multi_user_mode {
+        settings.multi_user_mode = multi_user_mode;
+    }
+    let expert_mode = options.expert || config.general.expert_mode;
+
+    // Packages given by name may be user-installed apps
+    let options = &CliOptions {
+        third_party: options.third_party || !names.is_empty(),
+        ..options.clone()
+    };
+    let packages = load_packages(&phone, &user, options);
+    let mut output = vec![];
+
+    for name in names {
+        if !packages.iter().any(|p| p.name == *name) {
+            output.push(ActionOutput {
+                package: name.clone(),
+                success: false,
+                error: Some("Package not found on the device".to_string()),
+                commands: vec![],
+            });
+        }
+    }
+
+    let targets = packages.iter().filter(|p| {
+        let wanted = if names.is_empty() {
+            matches_filters(p, options)
+        } else {
+            names.contains(&p.name)
+        };
+        wanted && (p.state == PackageState::Enabled) == remove
+    });
+
+    for package in targets {
+        if remove && package.removal == Removal::Unsafe && !expert_mode {
+            output.push(ActionOutput {
+                package: package.name.clone(),
+                success: false,
+                error: Some("Unsafe package: use `--expert` to remove it".to_string()),
+                commands: vec![],
+            });
+            continue;
+        }
+
+        let core_package: CorePackage = package.into();
+        let (actions, state) = package_action(&user, &core_package, &phone, &settings);
+        let entry = JournalEntry::new(&phone, &user, &core_package, &settings, &actions);
+        // In case the device doesn't boot anymore
+        if remove && is_risky(package.removal) {
+            save_restore_point(
+                &phone,
+                vec![RestorePackage::new(&phone, package.removal, &entry)],
+            );
+        }
+        // Same as the GUI: the state of the package is checked once the commands are done
+        let users: Vec<usize> = match settings.multi_user_mode {
+            true => phone.user_list.iter().map(|u| u.index).collect(),
+            false => vec![user.index],
+        };
+        let result = block_on(run_job(
+            0,
+            Job {
+                device_id: phone.adb_id.clone(),
+                index: 0,
+                users: ExpectedState::for_users(&phone, &users, state),
+                package: package.name.clone(),
+                removal: package.removal,
+                commands: actions,
+                journal_entry: Some(entry),
+            },
+        ));
+        let error = result.result.err().map(|e| e.to_string());
+        let commands: Vec<CommandOutput> = result
+            .commands
+            .into_iter()
+            .map(|(command, success)| CommandOutput { command, success })
+            .collect();
+
+        output.push(ActionOutput {
+            package: package.name.clone(),
+            success: error.is_none(),
+            error,
+            commands,
+        });
+    }
+
+    print_json(&output);
+    if output.iter().all(|o| o.success) {
+        EXIT_SUCCESS
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+fn apply_profile(name: &str, options: &CliOptions) -> i32 {
+    let profile = match load_profile(name) {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("Could not load profile `{}`: {}", name, e);
+            return EXIT_USAGE;
+        }
+    };
+    if options.all_devices {
+        return apply_profile_to_all_devices(&profile, options);
+    }
+    let phone = match select_device(options) {
+        Ok(phone) => phone,
+        Err(code) => return code,
+    };
+    let plan = match block_on(plan_from_device(profile.clone(), phone.clone())) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    if options.dry_run {
+        print_json(&plan);
+        return EXIT_SUCCESS;
+    }
+
+    let report = block_on(apply(profile, phone, plan));
+    print_json(&report);
+    if report.is_success() {
+        EXIT_SUCCESS
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+fn apply_profile_to_all_devices(profile: &Profile, options: &CliOptions) -> i32 {
+    let (devices, _) = ready_devices();
+    if devices.is_empty() {
+        eprintln!("No device found");
+        return EXIT_NO_DEVICE;
+    }
+    let reports = apply_to_devices(profile, &devices, options.dry_run);
+    print_json(&reports);
+    if reports.iter().all(|r| r.is_success()) {
+        EXIT_SUCCESS
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{install, MockDevice};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_gui_is_default() {
+        assert!(parse_args(&[]).is_none());
+        assert!(parse_args(&args("--self-update-temp /tmp/uad")).is_none());
+    }
+
+    #[test]
+    fn test_parse_remove() {
+        let (command, options) = parse_args(&args("remove --removal recommended -d abc --disable"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(command, CliCommand::Remove(vec![]));
+        assert_eq!(options.removal, Some(Removal::Recommended));
+        assert_eq!(options.device, Some("abc".to_string()));
+        assert_eq!(options.disable_mode, Some(true));
+        assert_eq!(options.suspend_mode, Some(false));
+
+        let (_, options) = parse_args(&args("remove com.foo --suspend"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.suspend_mode, Some(true));
+
+        let (command, _) = parse_args(&args("restore com.android.chrome"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            command,
+            CliCommand::Restore(vec!["com.android.chrome".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_apply() {
+        let (command, options) = parse_args(&args("apply debloat --dry-run"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(command, CliCommand::Apply("debloat".to_string()));
+        assert!(options.dry_run);
+        assert!(parse_args(&args("apply")).unwrap().is_err());
+        assert!(parse_args(&args("apply a b")).unwrap().is_err());
+
+        let (_, options) = parse_args(&args("apply debloat --all-devices"))
+            .unwrap()
+            .unwrap();
+        assert!(options.all_devices);
+        assert!(parse_args(&args("list --all-devices")).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_args(&args("remove")).unwrap().is_err());
+        assert!(parse_args(&args("list --removal nope")).unwrap().is_err());
+        assert!(parse_args(&args("list --user")).unwrap().is_err());
+        assert!(parse_args(&args("list --foo")).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_enum() {
+        assert_eq!(parse_enum(&UadList::ALL, "all"), Some(UadList::All));
+        assert_eq!(parse_enum(&UadList::ALL, "OEM"), Some(UadList::Oem));
+        assert_eq!(
+            parse_enum(&PackageState::ALL, "disabled"),
+            Some(PackageState::Disabled)
+        );
+        assert_eq!(
+            parse_enum(&PackageState::ALL, "suspended"),
+            Some(PackageState::Suspended)
+        );
+    }
+
+    #[test]
+    fn test_remove_failure_for_another_user() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_user(10, "Alice")
+                .with_package("com.foo", PackageState::Enabled)
+                .with_response("pm uninstall --user 10", Err("Failure [DELETE_FAILED]")),
+        );
+        let options = CliOptions {
+            device: Some("mock-device".to_string()),
+            multi_user_mode: Some(true),
+            disable_mode: Some(false),
+            offline: true,
+            ..CliOptions::default()
+        };
+        assert_eq!(
+            run(CliCommand::Remove(vec!["com.foo".to_string()]), &options),
+            EXIT_FAILURE
+        );
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Uninstalled));
+        assert_eq!(device.state("com.foo", 10), Some(PackageState::Enabled));
+
+        device.set_state("com.foo", 0, PackageState::Enabled);
+        let options = CliOptions {
+            multi_user_mode: Some(false),
+            ..options
+        };
+        assert_eq!(
+            run(CliCommand::Remove(vec!["com.foo".to_string()]), &options),
+            EXIT_SUCCESS
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use crate::core::sync::get_android_sdk;
+use crate::core::sync::Phone;
+use crate::core::uad_lists::PackageState;
 use crate::gui::views::settings::Settings;
 use crate::CONFIG_DIR;
 use serde::{Deserialize, Serialize};
@@ -11,12 +12,19 @@ pub struct Config {
     pub general: GeneralSettings,
     #[serde(skip_serializing_if = "Vec::is_empty", default = "Vec::new")]
     pub devices: Vec<DeviceSettings>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default = "Vec::new")]
+    pub wireless_devices: Vec<WirelessDevice>,
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct GeneralSettings {
     pub theme: String,
     pub expert_mode: bool,
+    #[serde(default)]
+    pub native_adb: bool,
+    /// Also list the user-installed apps
+    #[serde(default)]
+    pub third_party: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -24,18 +32,54 @@ pub struct DeviceSettings {
     pub device_id: String,
     pub disable_mode: bool,
     pub multi_user_mode: bool,
+    /// Suspend packages instead of removing them (Android 9+). Has priority over `disable_mode`.
+    #[serde(default)]
+    pub suspend_mode: bool,
 }
 
 impl Default for DeviceSettings {
+    /// Placeholder until a device is selected (see `DeviceSettings::new`)
     fn default() -> Self {
+        Self::new(&Phone::default())
+    }
+}
+
+impl DeviceSettings {
+    /// Settings of a device seen for the first time. The multi-user mode is on from
+    /// Android 5.1: packages can't be removed for a single user before.
+    pub fn new(phone: &Phone) -> Self {
         Self {
-            device_id: "".to_string(),
-            multi_user_mode: get_android_sdk() > 21,
+            device_id: phone.adb_id.clone(),
+            multi_user_mode: phone.android_sdk > 21,
             disable_mode: false,
+            suspend_mode: false,
+        }
+    }
+
+    /// Same settings, removing packages the way `target` says (`Uninstalled`, `Disabled`
+    /// or `Suspended`). `None` keeps the mode of the device.
+    pub fn with_target(&self, target: Option<PackageState>) -> Self {
+        let (disable_mode, suspend_mode) = match target {
+            Some(PackageState::Uninstalled) => (false, false),
+            Some(PackageState::Disabled) => (true, false),
+            Some(PackageState::Suspended) => (false, true),
+            _ => (self.disable_mode, self.suspend_mode),
+        };
+        Self {
+            disable_mode,
+            suspend_mode,
+            ..self.clone()
         }
     }
 }
 
+/// A device connected over Wi-Fi at least once
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct WirelessDevice {
+    pub address: String, // host:port (also the adb serial)
+    pub model: String,
+}
+
 #[dynamic]
 static CONFIG_FILE: PathBuf = CONFIG_DIR.join("config.toml");
 
@@ -57,8 +101,39 @@ impl Config {
                 config.general = settings.general.clone();
             }
         }
-        let toml = toml::to_string(&config).unwrap();
-        fs::write(&*CONFIG_FILE, toml).expect("Could not write config file to disk!");
+        config.save();
+    }
+
+    /// Adds the device to the known wireless devices (or updates it)
+    pub fn save_wireless_device(device: WirelessDevice) -> Vec<WirelessDevice> {
+        let mut config = Self::load_configuration_file();
+        match config
+            .wireless_devices
+            .iter_mut()
+            .find(|d| d.address == device.address)
+        {
+            Some(d) => *d = device,
+            None => config.wireless_devices.push(device),
+        }
+        config.save();
+        config.wireless_devices
+    }
+
+    pub fn forget_wireless_device(address: &str) -> Vec<WirelessDevice> {
+        let mut config = Self::load_configuration_file();
+        config.wireless_devices.retain(|d| d.address != address);
+        config.save();
+        config.wireless_devices
+    }
+
+    // A failed write only loses the last change: UAD keeps running
+    fn save(&self) {
+        let res = toml::to_string(self)
+            .map_err(|e| e.to_string())
+            .and_then(|toml| fs::write(&*CONFIG_FILE, toml).map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            error!("Could not write the config file: {}", e);
+        }
     }
 
     pub fn load_configuration_file() -> Self {
@@ -68,17 +143,34 @@ impl Config {
                 Err(e) => {
                     error!("Invalid config file: `{}`", e);
                     error!("Restoring default config file");
-                    let toml = toml::to_string(&Config::default()).unwrap();
-                    fs::write(&*CONFIG_FILE, toml).expect("Could not write config file to disk!");
-                    Config::default()
+                    let config = Config::default();
+                    config.save();
+                    config
                 }
             },
             Err(_) => {
-                let default_conf = toml::to_string(&Config::default()).unwrap();
-                fs::write(&*CONFIG_FILE, default_conf)
-                    .expect("Could not write config file to disk!");
-                Config::default()
+                let config = Config::default();
+                config.save();
+                config
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::phone;
+
+    #[test]
+    fn test_new_device_settings() {
+        let settings = |android_sdk| DeviceSettings::new(&phone(android_sdk, &[0]));
+        assert_eq!(settings(30).device_id, "mock-device");
+        assert!(settings(30).multi_user_mode);
+        assert!(settings(22).multi_user_mode);
+        assert!(!settings(21).multi_user_mode);
+        assert!(!settings(30).disable_mode && !settings(30).suspend_mode);
+        // No device selected yet
+        assert!(!DeviceSettings::default().multi_user_mode);
+    }
+}
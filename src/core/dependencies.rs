@@ -0,0 +1,134 @@
+use crate::core::uad_lists::Package;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+/// Relationships between the packages of the debloat lists.
+/// A package can be declared on either side (`dependencies` or `neededBy`): both are merged.
+#[derive(Debug, Default, Clone)]
+pub struct DependencyGraph {
+    dependencies: HashMap<String, BTreeSet<String>>,
+    dependents: HashMap<String, BTreeSet<String>>,
+}
+
+impl DependencyGraph {
+    pub fn new(uad_lists: &HashMap<String, Package>) -> Self {
+        let mut graph = Self::default();
+        for (name, package) in uad_lists {
+            for dependency in &package.dependencies {
+                graph.add(name, dependency);
+            }
+            for dependent in &package.needed_by {
+                graph.add(dependent, name);
+            }
+        }
+        graph
+    }
+
+    // `package` needs `dependency`
+    fn add(&mut self, package: &str, dependency: &str) {
+        if package == dependency {
+            return;
+        }
+        self.dependencies
+            .entry(package.to_string())
+            .or_default()
+            .insert(dependency.to_string());
+        self.dependents
+            .entry(dependency.to_string())
+            .or_default()
+            .insert(package.to_string());
+    }
+
+    /// Packages `package` directly depends on
+    pub fn direct_dependencies(&self, package: &str) -> Vec<String> {
+        Self::neighbours(&self.dependencies, package)
+    }
+
+    /// Packages directly depending on `package`
+    pub fn direct_dependents(&self, package: &str) -> Vec<String> {
+        Self::neighbours(&self.dependents, package)
+    }
+
+    /// Every package needed (directly or not) by `package`, closest first
+    pub fn dependencies(&self, package: &str) -> Vec<String> {
+        Self::walk(&self.dependencies, package)
+    }
+
+    /// Every package that needs (directly or not) `package`, closest first
+    pub fn dependents(&self, package: &str) -> Vec<String> {
+        Self::walk(&self.dependents, package)
+    }
+
+    fn neighbours(edges: &HashMap<String, BTreeSet<String>>, package: &str) -> Vec<String> {
+        edges
+            .get(package)
+            .map(|s| s.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Breadth-first search. Visited packages are skipped so cycles are not a problem.
+    fn walk(edges: &HashMap<String, BTreeSet<String>>, package: &str) -> Vec<String> {
+        let mut visited: HashSet<&str> = HashSet::from([package]);
+        let mut queue: VecDeque<&str> = VecDeque::from([package]);
+        let mut found = vec![];
+
+        while let Some(current) = queue.pop_front() {
+            for next in edges.get(current).into_iter().flatten() {
+                if visited.insert(next.as_str()) {
+                    found.push(next.clone());
+                    queue.push_back(next);
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uad_lists(packages: &[(&str, &[&str], &[&str])]) -> HashMap<String, Package> {
+        let json: Vec<String> = packages
+            .iter()
+            .map(|(id, dependencies, needed_by)| {
+                format!(
+                    r#"{{"id": "{}", "list": "Oem", "description": "", "dependencies": {:?},
+                    "neededBy": {:?}, "labels": [], "removal": "Recommended"}}"#,
+                    id, dependencies, needed_by
+                )
+            })
+            .collect();
+        let list: Vec<Package> = serde_json::from_str(&format!("[{}]", json.join(","))).unwrap();
+        list.into_iter().map(|p| (p.id.clone(), p)).collect()
+    }
+
+    #[test]
+    fn test_both_sides_are_merged() {
+        let graph = DependencyGraph::new(&uad_lists(&[
+            ("com.app", &["com.service"], &[]),
+            ("com.framework", &[], &["com.service"]),
+        ]));
+        assert_eq!(graph.direct_dependencies("com.app"), vec!["com.service"]);
+        assert_eq!(graph.direct_dependents("com.service"), vec!["com.app"]);
+        assert_eq!(
+            graph.dependencies("com.app"),
+            vec!["com.service", "com.framework"]
+        );
+        assert_eq!(
+            graph.dependents("com.framework"),
+            vec!["com.service", "com.app"]
+        );
+        assert!(graph.dependents("com.unknown").is_empty());
+    }
+
+    #[test]
+    fn test_cycles() {
+        let graph = DependencyGraph::new(&uad_lists(&[
+            ("com.a", &["com.b"], &[]),
+            ("com.b", &["com.c"], &[]),
+            ("com.c", &["com.a", "com.c"], &[]),
+        ]));
+        assert_eq!(graph.dependencies("com.a"), vec!["com.b", "com.c"]);
+        assert_eq!(graph.dependents("com.a"), vec!["com.c", "com.b"]);
+    }
+}
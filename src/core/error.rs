@@ -0,0 +1,198 @@
+use crate::core::uad_lists::PackageState;
+use regex::Regex;
+use static_init::dynamic;
+use std::fmt;
+
+/// Why an adb command failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdbError {
+    /// The `adb` binary is not in the PATH or the ADB server can't be reached
+    AdbMissing,
+    NoDevice,
+    DeviceOffline,
+    /// The USB debugging prompt has not been accepted on the device
+    Unauthorized,
+    /// `user` is `None` when the package is not installed at all
+    NotInstalled {
+        user: Option<u16>,
+    },
+    PermissionDenied(String),
+    /// Wireless debugging: the device could not be reached or the pairing code was wrong
+    ConnectionFailed(String),
+    /// The commands succeeded but the package is not in the expected state afterwards
+    /// (e.g. `pm uninstall` silently does nothing on some old devices)
+    UnexpectedState {
+        user: Option<u16>,
+        expected: PackageState,
+        actual: PackageState,
+    },
+    /// The commands succeeded but the state of the package could not be read afterwards
+    Unverified {
+        user: Option<u16>,
+        reason: String,
+    },
+    Unknown(String),
+}
+
+impl AdbError {
+    /// Classifies the output of a failed adb command
+    pub fn from_output(output: &str) -> Self {
+        #[dynamic]
+        static NOT_INSTALLED_FOR: Regex = Regex::new(r"not installed for (\d+)").unwrap();
+
+        let output = output.trim();
+        let lowercase = output.to_lowercase();
+        if lowercase.contains("adb was not found")
+            || lowercase.contains("could not connect to the adb server")
+        {
+            Self::AdbMissing
+        } else if lowercase.contains("no devices/emulators found")
+            || (lowercase.contains("device '") && lowercase.contains("' not found"))
+        {
+            Self::NoDevice
+        } else if lowercase.contains("device offline") {
+            Self::DeviceOffline
+        } else if lowercase.contains("unauthorized") {
+            Self::Unauthorized
+        } else if let Some(c) = NOT_INSTALLED_FOR.captures(output) {
+            Self::NotInstalled {
+                user: c[1].parse().ok(),
+            }
+        } else if lowercase.contains("unknown package")
+            || (lowercase.contains("package ") && lowercase.ends_with(" not found"))
+        {
+            Self::NotInstalled { user: None }
+        } else if lowercase.contains("securityexception")
+            || lowercase.contains("permission denied")
+            || lowercase.contains("permission denial")
+        {
+            Self::PermissionDenied(output.to_string())
+        } else if lowercase.contains("failed to connect")
+            || lowercase.contains("cannot connect to")
+            || lowercase.starts_with("failed:")
+        {
+            Self::ConnectionFailed(output.to_string())
+        } else {
+            Self::Unknown(output.to_string())
+        }
+    }
+
+    /// On old devices, adb commands can return the '0' exit code even if there is an error.
+    /// Only lines starting like an error message are considered, so a package named
+    /// `com.foo.ErrorReporter` doesn't make a command fail.
+    pub fn from_success_output(output: &str) -> Option<Self> {
+        output
+            .lines()
+            .map(str::trim_start)
+            .find(|l| {
+                l.starts_with("Error")
+                    || l.starts_with("Failure")
+                    || l.starts_with("Exception")
+                    || l.starts_with("java.lang.SecurityException")
+            })
+            .map(Self::from_output)
+    }
+}
+
+impl fmt::Display for AdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AdbMissing => write!(f, "ADB was not found (or the ADB server is not running)"),
+            Self::NoDevice => write!(f, "No device connected"),
+            Self::DeviceOffline => write!(f, "The device is offline, try to reconnect it"),
+            Self::Unauthorized => write!(
+                f,
+                "The device is unauthorized, accept the USB debugging prompt on the device"
+            ),
+            Self::NotInstalled { user: Some(user) } => {
+                write!(f, "The package is not installed for user {}", user)
+            }
+            Self::NotInstalled { user: None } => write!(f, "The package is not installed"),
+            Self::PermissionDenied(output) => write!(f, "Permission denied: {}", output),
+            Self::ConnectionFailed(output) => write!(f, "Could not connect: {}", output),
+            Self::UnexpectedState {
+                user,
+                expected,
+                actual,
+            } => {
+                write!(f, "The package is {} instead of {}", actual, expected)?;
+                match user {
+                    Some(user) => write!(f, " for user {}", user),
+                    None => Ok(()),
+                }
+            }
+            Self::Unverified { user, reason } => {
+                write!(f, "Could not check the state of the package")?;
+                if let Some(user) = user {
+                    write!(f, " for user {}", user)?;
+                }
+                write!(f, ": {}", reason)
+            }
+            Self::Unknown(output) => write!(f, "Unexpected output: {}", output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        assert_eq!(
+            AdbError::from_output("ADB was not found"),
+            AdbError::AdbMissing
+        );
+        assert_eq!(
+            AdbError::from_output("adb: no devices/emulators found"),
+            AdbError::NoDevice
+        );
+        assert_eq!(
+            AdbError::from_output("adb: device offline"),
+            AdbError::DeviceOffline
+        );
+        assert_eq!(
+            AdbError::from_output(
+                "adb: device unauthorized.\nThis adb server's $ADB_VENDOR_KEYS is not set"
+            ),
+            AdbError::Unauthorized
+        );
+        assert_eq!(
+            AdbError::from_output("Failure [not installed for 10]"),
+            AdbError::NotInstalled { user: Some(10) }
+        );
+        assert_eq!(
+            AdbError::from_output("Error: package com.foo not found"),
+            AdbError::NotInstalled { user: None }
+        );
+        assert!(matches!(
+            AdbError::from_output(
+                "java.lang.SecurityException: Shell cannot change component state for com.foo"
+            ),
+            AdbError::PermissionDenied(_)
+        ));
+        assert_eq!(
+            AdbError::from_output("Failed: Wrong password or connection was dropped."),
+            AdbError::ConnectionFailed(
+                "Failed: Wrong password or connection was dropped.".to_string()
+            )
+        );
+        assert_eq!(
+            AdbError::from_output("Failure [DELETE_FAILED_INTERNAL_ERROR]"),
+            AdbError::Unknown("Failure [DELETE_FAILED_INTERNAL_ERROR]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_success_output() {
+        assert_eq!(
+            AdbError::from_success_output("Package com.foo.ErrorReporter new state: disabled-user"),
+            None
+        );
+        assert_eq!(AdbError::from_success_output("Success"), None);
+        assert_eq!(
+            AdbError::from_success_output("Failure [not installed for 0]"),
+            Some(AdbError::NotInstalled { user: Some(0) })
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use crate::core::config::DeviceSettings;
+use crate::core::sync::{action_handler, package_action, CorePackage, Phone, User};
+use crate::core::uad_lists::PackageState;
+use crate::CONFIG_DIR;
+use serde::{Deserialize, Serialize};
+use static_init::dynamic;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// Every action made during the same run of UAD belongs to the same session
+#[dynamic]
+static SESSION: i64 = chrono::Local::now().timestamp();
+
+// The actions of a device run one after another from its queue, but several devices can be
+// handled at once. The journal file must not be written by several threads at once.
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub session: i64,
+    pub date: String,
+    pub device_id: String,
+    pub package: String,
+    pub user: u16,
+    pub multi_user: bool,
+    pub previous_state: PackageState,
+    pub new_state: PackageState,
+    pub commands: Vec<String>,
+    #[serde(default)]
+    pub rolled_back: bool,
+    #[serde(default)]
+    pub is_rollback: bool,
+}
+
+impl JournalEntry {
+    pub fn new(
+        phone: &Phone,
+        user: &User,
+        package: &CorePackage,
+        settings: &DeviceSettings,
+        commands: &[String],
+    ) -> Self {
+        Self {
+            session: *SESSION,
+            date: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            device_id: phone.adb_id.clone(),
+            package: package.name.clone(),
+            user: user.id,
+            multi_user: settings.multi_user_mode,
+            previous_state: package.state,
+            new_state: package_action(user, package, phone, settings).1,
+            commands: commands.to_vec(),
+            rolled_back: false,
+            is_rollback: false,
+        }
+    }
+
+    /// Commands bringing the package back to `previous_state`
+    pub fn rollback_commands(&self, phone: &Phone) -> Vec<String> {
+        let settings = DeviceSettings {
+            device_id: self.device_id.clone(),
+            disable_mode: self.previous_state == PackageState::Disabled,
+            multi_user_mode: self.multi_user,
+            suspend_mode: self.previous_state == PackageState::Suspended,
+        };
+        let user = phone
+            .user_list
+            .iter()
+            .find(|u| u.id == self.user)
+            .cloned()
+            .unwrap_or_else(|| User::new(self.user, 0));
+        let package = CorePackage {
+            name: self.package.clone(),
+            state: self.new_state,
+            target: None,
+        };
+        action_handler(&user, &package, phone, &settings)
+    }
+
+    /// Journal entry of the rollback of this entry
+    pub fn rollback(&self, commands: &[String]) -> Self {
+        Self {
+            session: *SESSION,
+            date: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            previous_state: self.new_state,
+            new_state: self.previous_state,
+            commands: commands.to_vec(),
+            rolled_back: false,
+            is_rollback: true,
+            ..self.clone()
+        }
+    }
+}
+
+fn journal_file(device_id: &str) -> PathBuf {
+    // Wireless devices have a `:` in their serial
+    let name: String = device_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    CONFIG_DIR.join("journal").join(format!("{}.jsonl", name))
+}
+
+pub fn load_journal(device_id: &str) -> Vec<JournalEntry> {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    read_journal(device_id)
+}
+
+fn read_journal(device_id: &str) -> Vec<JournalEntry> {
+    match fs::read_to_string(journal_file(device_id)) {
+        Ok(s) => s
+            .lines()
+            .filter_map(|l| match serde_json::from_str(l) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    error!("Invalid journal entry: {}", e);
+                    None
+                }
+            })
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+pub fn record_entry(entry: &JournalEntry) {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let file = journal_file(&entry.device_id);
+    let result = fs::create_dir_all(file.parent().unwrap()).and_then(|_| {
+        let mut f = OpenOptions::new().create(true).append(true).open(&file)?;
+        writeln!(f, "{}", serde_json::to_string(entry)?)
+    });
+    if let Err(e) = result {
+        error!("Could not write journal entry to {}: {}", file.display(), e);
+    }
+}
+
+/// Marks the entries at the given indexes (in `load_journal` order) as rolled back
+pub fn mark_rolled_back(device_id: &str, indexes: &[usize]) {
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut journal = read_journal(device_id);
+    for i in indexes {
+        if let Some(entry) = journal.get_mut(*i) {
+            entry.rolled_back = true;
+        }
+    }
+    let content: String = journal
+        .iter()
+        .filter_map(|e| serde_json::to_string(e).ok())
+        .map(|e| e + "\n")
+        .collect();
+    if let Err(e) = fs::write(journal_file(device_id), content) {
+        error!("Could not update journal: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{phone, settings};
+
+    fn entry(state: PackageState, disable_mode: bool) -> JournalEntry {
+        entry_on(30, state, disable_mode)
+    }
+
+    fn entry_on(android_sdk: u8, state: PackageState, disable_mode: bool) -> JournalEntry {
+        // Own journal file
+        let phone = Phone {
+            adb_id: "journal-test".to_string(),
+            ..phone(android_sdk, &[0, 10])
+        };
+        let settings = DeviceSettings {
+            device_id: phone.adb_id.clone(),
+            ..settings(disable_mode, false)
+        };
+        let package = CorePackage {
+            name: "com.foo".to_string(),
+            state,
+            target: None,
+        };
+        JournalEntry::new(&phone, &phone.user_list[1], &package, &settings, &[])
+    }
+
+    #[test]
+    fn test_rollback_commands() {
+        let phone = phone(30, &[0, 10]);
+        let cases = [
+            (PackageState::Enabled, false, "cmd package install-existing"),
+            (PackageState::Enabled, true, "pm enable"),
+            (PackageState::Disabled, true, "pm disable-user"),
+            (PackageState::Uninstalled, false, "pm uninstall"),
+        ];
+        for (state, disable_mode, expected) in cases {
+            assert_eq!(
+                entry(state, disable_mode).rollback_commands(&phone)[0],
+                format!("{} --user 10 com.foo", expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_state_per_sdk() {
+        let cases = [
+            (30, PackageState::Enabled, false, PackageState::Uninstalled),
+            (30, PackageState::Enabled, true, PackageState::Disabled),
+            (26, PackageState::Enabled, true, PackageState::Disabled),
+            // `pm hide` (disable mode is unavailable)
+            (22, PackageState::Enabled, true, PackageState::Uninstalled),
+            (22, PackageState::Uninstalled, false, PackageState::Enabled),
+        ];
+        for (sdk, state, disable_mode, expected) in cases {
+            assert_eq!(
+                entry_on(sdk, state, disable_mode).new_state,
+                expected,
+                "SDK {}",
+                sdk
+            );
+        }
+    }
+
+    #[test]
+    fn test_journal_file() {
+        let first = entry(PackageState::Enabled, false);
+        let second = entry(PackageState::Enabled, true);
+        let _ = fs::remove_file(journal_file(&first.device_id));
+
+        record_entry(&first);
+        record_entry(&second);
+        mark_rolled_back(&first.device_id, &[1]);
+
+        let journal = load_journal(&first.device_id);
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal[0], first);
+        assert!(journal[1].rolled_back);
+        assert_eq!(journal[1].new_state, PackageState::Disabled);
+    }
+}
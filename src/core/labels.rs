@@ -0,0 +1,350 @@
+//! Application labels and launcher icons. Android doesn't expose them through `adb shell`:
+//! the APKs are pulled and read with `aapt2 dump badging` (Android SDK build-tools).
+//! Pulling an APK is slow so the results are cached per device, until the APK changes.
+use crate::core::sync::adb_shell_command;
+use crate::core::transport::transport;
+use crate::CACHE_DIR;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppLabel {
+    pub label: Option<String>,
+    /// PNG file of the launcher icon
+    pub icon: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelBatch {
+    pub device_id: String,
+    pub labels: HashMap<String, AppLabel>,
+    /// Packages whose APK is still to be read
+    pub remaining: usize,
+}
+
+// What is known about a package. Failures are cached too: they would fail again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedLabel {
+    apk: String, // The entry is outdated when the APK moves (e.g. after an update)
+    label: Option<String>,
+    icon: bool,
+}
+
+/// `aapt2` from the PATH or from the build-tools of the Android SDK (the latest ones)
+pub fn find_aapt2() -> Option<PathBuf> {
+    let binary = if cfg!(target_os = "windows") {
+        "aapt2.exe"
+    } else {
+        "aapt2"
+    };
+    if let Some(path) = env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(binary))
+        .find(|path| path.is_file())
+    {
+        return Some(path);
+    }
+    ["ANDROID_HOME", "ANDROID_SDK_ROOT"]
+        .iter()
+        .filter_map(env::var_os)
+        .filter_map(|sdk| {
+            let mut versions: Vec<PathBuf> = fs::read_dir(Path::new(&sdk).join("build-tools"))
+                .ok()?
+                .filter_map(|e| e.ok())
+                .map(|e| e.path().join(binary))
+                .filter(|path| path.is_file())
+                .collect();
+            versions.sort();
+            versions.pop()
+        })
+        .next()
+}
+
+/// Parses the output of `pm list packages -f`, e.g.
+/// `package:/data/app/~~a1b2==/com.foo-c3d4==/base.apk=com.foo`
+pub fn parse_apk_paths(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("package:")?.rsplit_once('='))
+        .map(|(apk, package)| (package.to_string(), apk.to_string()))
+        .collect()
+}
+
+/// Label and icon (path inside the APK) from the output of `aapt2 dump badging`.
+/// The icon is the PNG of the highest density: adaptive icons (XML) can't be displayed.
+pub fn parse_badging(output: &str) -> (Option<String>, Option<String>) {
+    let value = |s: &str| -> Option<String> {
+        let value = s.strip_prefix('\'')?.strip_suffix('\'')?;
+        Some(value.to_string()).filter(|v| !v.is_empty())
+    };
+    let mut label = None;
+    let mut icons: Vec<(u32, String)> = vec![];
+    for line in output.lines() {
+        if let Some(l) = line.strip_prefix("application-label:") {
+            label = value(l);
+        } else if let Some((density, path)) = line
+            .strip_prefix("application-icon-")
+            .and_then(|l| l.split_once(':'))
+        {
+            if let (Ok(density), Some(path)) = (density.parse(), value(path)) {
+                if path.ends_with(".png") {
+                    icons.push((density, path));
+                }
+            }
+        }
+    }
+    icons.sort();
+    (label, icons.pop().map(|(_, path)| path))
+}
+
+/// Extracts `entry` from the APK (a zip archive) into `dest`
+pub fn extract_icon(apk: &Path, entry: &str, dest: &Path) -> Result<(), String> {
+    let file = File::open(apk).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let mut icon = archive.by_name(entry).map_err(|e| e.to_string())?;
+    let mut output = File::create(dest).map_err(|e| e.to_string())?;
+    io::copy(&mut icon, &mut output).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn cache_dir(device_id: &str) -> PathBuf {
+    // Wireless devices have a `:` in their serial
+    let name: String = device_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    CACHE_DIR.join("labels").join(name)
+}
+
+fn icon_file(dir: &Path, package: &str) -> PathBuf {
+    dir.join(format!("{}.png", package))
+}
+
+fn load_cache(dir: &Path) -> HashMap<String, CachedLabel> {
+    fs::read_to_string(dir.join("labels.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_cache(dir: &Path, cache: &HashMap<String, CachedLabel>) -> Result<(), String> {
+    let json = serde_json::to_string(cache).map_err(|e| e.to_string())?;
+    fs::write(dir.join("labels.json"), json).map_err(|e| e.to_string())
+}
+
+fn dump_badging(aapt2: &Path, apk: &Path) -> Result<String, String> {
+    let mut command = Command::new(aapt2);
+    command.args(["dump", "badging"]).arg(apk);
+
+    #[cfg(target_os = "windows")]
+    command.creation_flags(0x08000000); // do not open a cmd window
+
+    let output = command.output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr)
+            .trim_end()
+            .to_string())
+    }
+}
+
+// Pulls the APK of the package and reads its label and icon
+fn read_label(device_id: &str, aapt2: &Path, package: &str, apk: &str, dir: &Path) -> CachedLabel {
+    let local_apk = dir.join("pulled.apk");
+    let result = transport()
+        .pull(device_id, apk, &local_apk)
+        .and_then(|_| dump_badging(aapt2, &local_apk));
+    let mut cached = CachedLabel {
+        apk: apk.to_string(),
+        label: None,
+        icon: false,
+    };
+    match result {
+        Ok(badging) => {
+            let (label, icon) = parse_badging(&badging);
+            cached.label = label;
+            if let Some(icon) = icon {
+                match extract_icon(&local_apk, &icon, &icon_file(dir, package)) {
+                    Ok(_) => cached.icon = true,
+                    Err(e) => warn!("Could not extract the icon of {}: {}", package, e),
+                }
+            }
+        }
+        Err(e) => warn!("Could not read the label of {}: {}", package, e),
+    }
+    let _ = fs::remove_file(local_apk);
+    cached
+}
+
+/// Labels of the packages of the device. At most `limit` APKs are pulled: the caller
+/// asks for another batch while `remaining` isn't 0. Without `aapt2`, only the cached
+/// labels are returned.
+pub async fn fetch_labels(device_id: String, limit: usize) -> LabelBatch {
+    let mut batch = LabelBatch {
+        device_id: device_id.clone(),
+        labels: HashMap::new(),
+        remaining: 0,
+    };
+    // Packages uninstalled for the user are listed too: their APK is still there
+    let apks = match adb_shell_command(&device_id, "pm list packages -f -u") {
+        Ok(output) => parse_apk_paths(&output),
+        Err(e) => {
+            error!("Could not list the APKs of {}: {}", device_id, e);
+            return batch;
+        }
+    };
+    let dir = cache_dir(&device_id);
+    let mut cache = load_cache(&dir);
+    let missing: Vec<&(String, String)> = apks
+        .iter()
+        .filter(|(package, apk)| cache.get(package).filter(|c| &c.apk == apk).is_none())
+        .collect();
+
+    match find_aapt2() {
+        Some(aapt2) if !missing.is_empty() => {
+            if let Err(e) = fs::create_dir_all(&dir) {
+                error!("Could not create {}: {}", dir.display(), e);
+                return batch;
+            }
+            for (package, apk) in missing.iter().take(limit) {
+                let label = read_label(&device_id, &aapt2, package, apk, &dir);
+                cache.insert(package.clone(), label);
+            }
+            if let Err(e) = save_cache(&dir, &cache) {
+                error!("Could not save the labels of {}: {}", device_id, e);
+            }
+            batch.remaining = missing.len().saturating_sub(limit);
+        }
+        Some(_) => {}
+        None if !missing.is_empty() => {
+            warn!("aapt2 was not found: app labels and icons can't be read from the APKs")
+        }
+        None => {}
+    }
+
+    batch.labels = apks
+        .iter()
+        .filter_map(|(package, apk)| {
+            let cached = cache.get(package).filter(|c| &c.apk == apk)?;
+            let label = AppLabel {
+                label: cached.label.clone(),
+                icon: cached.icon.then(|| icon_file(&dir, package)),
+            };
+            Some((package.clone(), label))
+        })
+        .collect();
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{install, MockDevice};
+    use crate::core::uad_lists::PackageState;
+    use iced::futures::executor::block_on;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_apk_paths() {
+        let output = "package:/data/app/~~a1b2==/com.foo-c3d4==/base.apk=com.foo\n\
+            package:/system/app/Bar=Baz/Bar.apk=com.bar";
+        assert_eq!(
+            parse_apk_paths(output),
+            vec![
+                (
+                    "com.foo".to_string(),
+                    "/data/app/~~a1b2==/com.foo-c3d4==/base.apk".to_string()
+                ),
+                (
+                    "com.bar".to_string(),
+                    "/system/app/Bar=Baz/Bar.apk".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_badging() {
+        let output = "package: name='com.foo' versionCode='12' versionName='1.2'\n\
+            application-label:'Foo Bar'\n\
+            application-label-fr:'Fou Bar'\n\
+            application-icon-160:'res/mipmap-mdpi-v4/ic_launcher.png'\n\
+            application-icon-480:'res/mipmap-xxhdpi-v4/ic_launcher.png'\n\
+            application-icon-65534:'res/mipmap-anydpi-v26/ic_launcher.xml'\n\
+            application: label='Foo Bar' icon='res/mipmap-mdpi-v4/ic_launcher.png'";
+        assert_eq!(
+            parse_badging(output),
+            (
+                Some("Foo Bar".to_string()),
+                Some("res/mipmap-xxhdpi-v4/ic_launcher.png".to_string())
+            )
+        );
+        assert_eq!(parse_badging("application-label:''"), (None, None));
+    }
+
+    #[test]
+    fn test_extract_icon() {
+        let apk = env::temp_dir().join("uad_test_icon.apk");
+        let dest = env::temp_dir().join("uad_test_icon.png");
+        let mut zip = zip::ZipWriter::new(File::create(&apk).unwrap());
+        zip.start_file("res/icon.png", zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(b"\x89PNG").unwrap();
+        zip.finish().unwrap();
+
+        extract_icon(&apk, "res/icon.png", &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"\x89PNG");
+        assert!(extract_icon(&apk, "res/missing.png", &dest).is_err());
+        let _ = fs::remove_file(apk);
+        let _ = fs::remove_file(dest);
+    }
+
+    #[test]
+    fn test_fetch_labels_cached() {
+        let (_device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.bar", PackageState::Uninstalled),
+        );
+        let dir = cache_dir("mock-device");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cached = |label: &str| CachedLabel {
+            apk: MockDevice::apk_path("com.foo"),
+            label: Some(label.to_string()),
+            icon: false,
+        };
+        // The APK of com.bar has moved since it was cached
+        let cache = HashMap::from([
+            ("com.foo".to_string(), cached("Foo")),
+            ("com.bar".to_string(), cached("Bar")),
+        ]);
+        save_cache(&dir, &cache).unwrap();
+
+        let batch = block_on(fetch_labels("mock-device".to_string(), 10));
+        assert_eq!(batch.remaining, 0);
+        assert_eq!(
+            batch.labels.get("com.foo"),
+            Some(&AppLabel {
+                label: Some("Foo".to_string()),
+                icon: None
+            })
+        );
+        // Not read again without aapt2, or unreadable (the mock device has no APK to pull)
+        assert_eq!(
+            batch.labels.get("com.bar").and_then(|l| l.label.clone()),
+            None
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+}
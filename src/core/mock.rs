@@ -0,0 +1,560 @@
+//! Scriptable fake device used by the tests. It answers the adb commands used by UAD
+//! and keeps the state of the packages in memory.
+use crate::core::config::DeviceSettings;
+use crate::core::profile::{Profile, ProfileDevice, ProfilePackage, UserScope, PROFILE_VERSION};
+use crate::core::sync::{get_user_list, Phone, User};
+use crate::core::transport::{set_transport, Transport};
+use crate::core::uad_lists::{PackageState, Removal, UadList};
+use crate::gui::widgets::package_row::PackageRow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// The transport is process-wide: tests using a mock device must not run concurrently
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Makes `device` the current transport. The device is used until the guard is dropped.
+pub fn install(device: MockDevice) -> (Arc<MockDevice>, MutexGuard<'static, ()>) {
+    let (mut devices, guard) = install_all(vec![device]);
+    (devices.remove(0), guard)
+}
+
+/// Same as `install` with several devices connected at the same time
+pub fn install_all(devices: Vec<MockDevice>) -> (Vec<Arc<MockDevice>>, MutexGuard<'static, ()>) {
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let devices: Vec<Arc<MockDevice>> = devices.into_iter().map(Arc::new).collect();
+    set_transport(Arc::new(MockHub(devices.clone())));
+    (devices, guard)
+}
+
+/// Phone with the serial of the mock device. `users` are the ids of its users.
+pub fn phone(android_sdk: u8, users: &[u16]) -> Phone {
+    Phone {
+        model: "Pixel".to_string(),
+        android_sdk,
+        user_list: users
+            .iter()
+            .enumerate()
+            .map(|(index, id)| User::new(*id, index))
+            .collect(),
+        adb_id: "mock-device".to_string(),
+        ..Phone::default()
+    }
+}
+
+/// Same as `phone` with the users read from the installed mock device (flags included)
+pub fn connected_phone(android_sdk: u8) -> Phone {
+    Phone {
+        user_list: get_user_list("mock-device"),
+        ..phone(android_sdk, &[])
+    }
+}
+
+pub fn settings(disable_mode: bool, multi_user_mode: bool) -> DeviceSettings {
+    DeviceSettings {
+        device_id: "mock-device".to_string(),
+        disable_mode,
+        multi_user_mode,
+        suspend_mode: false,
+    }
+}
+
+/// Row of a package that is in no UAD list
+pub fn row(name: &str, state: PackageState) -> PackageRow {
+    PackageRow::new(
+        name,
+        state,
+        "",
+        UadList::Unlisted,
+        Removal::Unlisted,
+        false,
+        false,
+    )
+}
+
+/// Profile made on a Pixel (Android 11). `packages` are the names and targeted states.
+pub fn profile(name: &str, scope: UserScope, packages: &[(&str, PackageState)]) -> Profile {
+    Profile {
+        version: PROFILE_VERSION,
+        name: name.to_string(),
+        description: "test".to_string(),
+        created: "2022-11-14 00:00:00".to_string(),
+        device: ProfileDevice {
+            model: "Pixel".to_string(),
+            android_sdk: 30,
+        },
+        user_scope: scope,
+        packages: packages
+            .iter()
+            .map(|(name, target)| ProfilePackage {
+                name: name.to_string(),
+                target: *target,
+            })
+            .collect(),
+    }
+}
+
+// Routes the shell commands to the device whose serial is given, like the ADB server
+struct MockHub(Vec<Arc<MockDevice>>);
+
+impl MockHub {
+    // Output of `adb connect` / `adb disconnect`
+    fn set_connected(&self, address: &str, connected: bool) -> String {
+        let device = self.0.iter().find(|d| d.serial() == address);
+        let mut state = match device {
+            Some(device) => device.state.lock().unwrap(),
+            None if connected => {
+                return format!("failed to connect to '{}': Connection refused", address)
+            }
+            None => return format!("error: no such device '{}'", address),
+        };
+        match (connected, state.status == "disconnected") {
+            (true, true) => {
+                state.status = "device".to_string();
+                format!("connected to {}", address)
+            }
+            (true, false) => format!("already connected to {}", address),
+            (false, _) => {
+                state.status = "disconnected".to_string();
+                format!("disconnected {}", address)
+            }
+        }
+    }
+}
+
+pub struct MockDevice {
+    state: Mutex<MockState>,
+}
+
+struct MockState {
+    serial: String,
+    status: String, // As printed by `adb devices`
+    booting: u8,    // Number of `adb devices` before the device is back after a reboot
+    brand: String,
+    model: String,
+    sdk: u8,
+    users: Vec<(u16, String, u32)>, // id, name, flags (as printed by `pm list users`)
+    packages: BTreeMap<String, PackageState>,
+    third_party: HashMap<String, (String, String)>, // User-installed apps: installer, install date
+    user_states: HashMap<(String, u16), PackageState>,
+    files: HashMap<String, Vec<u8>>, // Served by `adb pull`
+    responses: Vec<(String, Result<String, String>)>,
+    history: Vec<String>,
+}
+
+impl MockDevice {
+    pub fn new(sdk: u8) -> Self {
+        Self {
+            state: Mutex::new(MockState {
+                serial: "mock-device".to_string(),
+                status: "device".to_string(),
+                booting: 0,
+                brand: "google".to_string(),
+                model: "Pixel".to_string(),
+                sdk,
+                users: vec![(0, "Owner".to_string(), 0xc13)],
+                packages: BTreeMap::new(),
+                third_party: HashMap::new(),
+                user_states: HashMap::new(),
+                files: HashMap::new(),
+                responses: vec![],
+                history: vec![],
+            }),
+        }
+    }
+
+    pub fn with_serial(self, serial: &str) -> Self {
+        self.state.lock().unwrap().serial = serial.to_string();
+        self
+    }
+
+    /// e.g `unauthorized` or `offline`. Such a device doesn't answer shell commands.
+    /// `disconnected` devices are not listed until `adb connect` (wireless devices).
+    pub fn with_status(self, status: &str) -> Self {
+        self.state.lock().unwrap().status = status.to_string();
+        self
+    }
+
+    /// Adds a secondary user
+    pub fn with_user(self, id: u16, name: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .users
+            .push((id, name.to_string(), 0x410));
+        self
+    }
+
+    /// Adds a work profile of the system user
+    pub fn with_work_profile(self, id: u16, name: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .users
+            .push((id, name.to_string(), 0x1030));
+        self
+    }
+
+    /// Adds a system package with the same state for every user
+    pub fn with_package(self, name: &str, state: PackageState) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .packages
+            .insert(name.to_string(), state);
+        self
+    }
+
+    /// Adds a user-installed app (`installer` may be `null`)
+    pub fn with_third_party_package(self, name: &str, installer: &str, date: &str) -> Self {
+        let mut state = self.state.lock().unwrap();
+        state
+            .packages
+            .insert(name.to_string(), PackageState::Enabled);
+        state
+            .third_party
+            .insert(name.to_string(), (installer.to_string(), date.to_string()));
+        drop(state);
+        self
+    }
+
+    /// File that can be pulled from the device
+    pub fn with_file(self, path: &str, content: &[u8]) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .files
+            .insert(path.to_string(), content.to_vec());
+        self
+    }
+
+    /// Commands starting with `prefix` get `response` and don't change anything
+    pub fn with_response(self, prefix: &str, response: Result<&str, &str>) -> Self {
+        let response = response.map(String::from).map_err(String::from);
+        self.state
+            .lock()
+            .unwrap()
+            .responses
+            .push((prefix.to_string(), response));
+        self
+    }
+
+    pub fn set_state(&self, package: &str, user: u16, state: PackageState) {
+        self.state
+            .lock()
+            .unwrap()
+            .user_states
+            .insert((package.to_string(), user), state);
+    }
+
+    pub fn state(&self, package: &str, user: u16) -> Option<PackageState> {
+        self.state.lock().unwrap().get(package, user)
+    }
+
+    fn serial(&self) -> String {
+        self.state.lock().unwrap().serial.clone()
+    }
+
+    /// Where the APK of a mock package is (`pm list packages -f`)
+    pub fn apk_path(package: &str) -> String {
+        format!("/system/app/{}/base.apk", package)
+    }
+
+    /// Every shell command received by the device
+    pub fn history(&self) -> Vec<String> {
+        self.state.lock().unwrap().history.clone()
+    }
+}
+
+impl MockState {
+    fn get(&self, package: &str, user: u16) -> Option<PackageState> {
+        self.user_states
+            .get(&(package.to_string(), user))
+            .or_else(|| self.packages.get(package))
+            .copied()
+    }
+
+    fn list_packages(&self, flags: &[&str], user: u16) -> String {
+        // A trailing package name filters the list (`pm list packages -e com.foo`)
+        let filter = flags
+            .last()
+            .filter(|f| !f.starts_with('-') && f.parse::<u16>().is_err());
+        self.packages
+            .keys()
+            .filter(|p| filter.filter(|f| !p.contains(**f)).is_none())
+            .filter(|p| match self.third_party.contains_key(*p) {
+                true => !flags.contains(&"-s"),
+                false => !flags.contains(&"-3"),
+            })
+            .filter(|p| match self.get(p, user) {
+                Some(PackageState::Uninstalled) => flags.contains(&"-u"),
+                Some(PackageState::Enabled | PackageState::Suspended) => !flags.contains(&"-d"),
+                Some(PackageState::Disabled) => !flags.contains(&"-e"),
+                _ => false,
+            })
+            .map(|p| match self.third_party.get(p) {
+                Some((installer, _)) if flags.contains(&"-i") => {
+                    format!("package:{}  installer={}", p, installer)
+                }
+                _ if flags.contains(&"-f") => format!("package:{}={}", MockDevice::apk_path(p), p),
+                _ => format!("package:{}", p),
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    // Output of `dumpsys package packages` (the `grep` is ignored), or of
+    // `dumpsys package <package>` with `only`
+    fn package_dump(&self, only: Option<&str>) -> String {
+        self.packages
+            .keys()
+            .filter(|p| only.filter(|o| *o != p.as_str()).is_none())
+            .map(|p| {
+                let mut dump = format!("  Package [{}] (1a2b3c):", p);
+                if let Some((_, date)) = self.third_party.get(p) {
+                    dump += &format!("\n    firstInstallTime={}", date);
+                }
+                for (id, ..) in &self.users {
+                    let state = self.get(p, *id);
+                    dump += &format!(
+                        "\n    User {}: ceDataInode=0 installed={} hidden=false suspended={} \
+                        enabled={}",
+                        id,
+                        state != Some(PackageState::Uninstalled),
+                        state == Some(PackageState::Suspended),
+                        if state == Some(PackageState::Disabled) {
+                            3
+                        } else {
+                            0
+                        }
+                    );
+                }
+                dump
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn change_state(
+        &mut self,
+        package: &str,
+        user: u16,
+        state: PackageState,
+        output: String,
+    ) -> Result<String, String> {
+        match self.get(package, user) {
+            None => Err(format!("Error: package {} not found", package)),
+            Some(PackageState::Uninstalled) if state != PackageState::Enabled => {
+                Err(format!("Failure [not installed for {}]", user))
+            }
+            Some(_) => {
+                self.user_states.insert((package.to_string(), user), state);
+                Ok(output)
+            }
+        }
+    }
+}
+
+impl Transport for MockHub {
+    fn host(&self, command: &str) -> Result<String, String> {
+        let long = match command.split_whitespace().collect::<Vec<&str>>()[..] {
+            ["devices"] => false,
+            ["devices", "-l"] => true,
+            ["pair", address, code] => {
+                return match code {
+                    "123456" => Ok(format!(
+                        "Successfully paired to {} [guid=adb-mock]",
+                        address
+                    )),
+                    _ => Err("Failed: Wrong password or connection was dropped.".to_string()),
+                }
+            }
+            ["connect", address] => return Ok(self.set_connected(address, true)),
+            ["disconnect", address] => return Ok(self.set_connected(address, false)),
+            _ => return Err(format!("adb: unknown command {}", command)),
+        };
+        Ok(self.0.iter().enumerate().fold(
+            "List of devices attached".to_string(),
+            |acc, (i, device)| {
+                let mut state = device.state.lock().unwrap();
+                if state.booting > 0 {
+                    state.booting -= 1;
+                    if state.booting == 0 {
+                        state.status = "device".to_string();
+                    }
+                }
+                if state.status == "disconnected" {
+                    return acc;
+                }
+                let mut line = format!("{}\n{}\t{}", acc, state.serial, state.status);
+                if long {
+                    line += &format!(" model:{} transport_id:{}", state.model, i + 1);
+                }
+                line
+            },
+        ))
+    }
+
+    fn shell(&self, serial: &str, command: &str) -> Result<String, String> {
+        match self.0.iter().find(|d| d.serial() == serial) {
+            Some(device) => device.shell(command),
+            None => Err(format!("adb: device '{}' not found", serial)),
+        }
+    }
+
+    // A single list with the current devices, then the end of the stream
+    fn track_devices(&self) -> Result<Box<dyn Read + Send>, String> {
+        let devices = self
+            .host("devices")?
+            .replace("List of devices attached\n", "");
+        Ok(Box::new(Cursor::new(format!(
+            "{:04x}{}",
+            devices.len(),
+            devices
+        ))))
+    }
+
+    fn pull(&self, serial: &str, remote: &str, local: &Path) -> Result<(), String> {
+        let device = match self.0.iter().find(|d| d.serial() == serial) {
+            Some(device) => device,
+            None => return Err(format!("adb: device '{}' not found", serial)),
+        };
+        let content = device.state.lock().unwrap().files.get(remote).cloned();
+        match content {
+            Some(content) => std::fs::write(local, content).map_err(|e| e.to_string()),
+            None => Err(format!(
+                "adb: error: failed to stat remote object '{}': No such file or directory",
+                remote
+            )),
+        }
+    }
+}
+
+// Equivalent of `adb shell <command>` on this device
+impl MockDevice {
+    fn shell(&self, command: &str) -> Result<String, String> {
+        let mut state = self.state.lock().unwrap();
+        if state.status != "device" {
+            return Err(format!("adb: device {}", state.status));
+        }
+        state.history.push(command.to_string());
+
+        if let Some((_, response)) = state
+            .responses
+            .iter()
+            .find(|(prefix, _)| command.starts_with(prefix.as_str()))
+        {
+            return response.clone();
+        }
+
+        let args: Vec<&str> = command.split_whitespace().collect();
+        // Commands without `--user` target the system user (and there is no `--user` before Android 5.0)
+        let user = args
+            .iter()
+            .position(|a| *a == "--user")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|u| u.parse().ok())
+            .unwrap_or(0);
+        if !state.users.iter().any(|(id, ..)| *id == user) {
+            return Err(format!("Error: user {} not found", user));
+        }
+        let package = args.last().copied().unwrap_or_default();
+        let sdk = state.sdk;
+
+        match args.as_slice() {
+            ["getprop", "ro.product.brand"] => Ok(state.brand.clone()),
+            ["getprop", "ro.product.model"] => Ok(state.model.clone()),
+            ["getprop", "ro.build.version.sdk"] => Ok(sdk.to_string()),
+            ["pm", "list", "users"] => {
+                Ok(state
+                    .users
+                    .iter()
+                    .fold("Users:".to_string(), |acc, (id, name, flags)| {
+                        format!("{}\n\tUserInfo{{{}:{}:{:x}}} running", acc, id, name, flags)
+                    }))
+            }
+            ["dumpsys", "user"] => {
+                Ok(state
+                    .users
+                    .iter()
+                    .fold("Users:".to_string(), |acc, (id, name, flags)| {
+                        let parent = if flags & 0x1000 != 0 {
+                            " parentId=0"
+                        } else {
+                            ""
+                        };
+                        format!(
+                            "{}\n  UserInfo{{{}:{}:{:x}}} serialNo={} isPrimary={}{}",
+                            acc,
+                            id,
+                            name,
+                            flags,
+                            id,
+                            *id == 0,
+                            parent
+                        )
+                    }))
+            }
+            ["pm", "list", "packages" | "package", flags @ ..] => {
+                Ok(state.list_packages(flags, user))
+            }
+            ["dumpsys", "package", "packages", ..] => Ok(state.package_dump(None)),
+            ["dumpsys", "package", package] => Ok(state.package_dump(Some(*package))),
+            ["pm", "uninstall", ..] => {
+                state.change_state(package, user, PackageState::Uninstalled, "Success".into())
+            }
+            ["pm", "disable-user", ..] if sdk >= 23 => {
+                let output = format!("Package {} new state: disabled-user", package);
+                state.change_state(package, user, PackageState::Disabled, output)
+            }
+            ["pm", "enable", ..] => {
+                let output = format!("Package {} new state: enabled", package);
+                state.change_state(package, user, PackageState::Enabled, output)
+            }
+            ["cmd", "package", "install-existing", ..] if sdk >= 23 => {
+                let output = format!("Package {} installed for user: {}", package, user);
+                state.change_state(package, user, PackageState::Enabled, output)
+            }
+            ["pm", action @ ("hide" | "unhide" | "block" | "unblock"), ..] => {
+                let (target, hidden) = match *action {
+                    "hide" | "block" => (PackageState::Uninstalled, true),
+                    _ => (PackageState::Enabled, false),
+                };
+                let kind = if action.ends_with("hide") {
+                    "hidden"
+                } else {
+                    "blocked"
+                };
+                let output = format!("Package {} new {} state: {}", package, kind, hidden);
+                state.change_state(package, user, target, output)
+            }
+            ["pm", action @ ("suspend" | "unsuspend"), ..] if sdk >= 28 => {
+                let suspended = *action == "suspend";
+                let target = if suspended {
+                    PackageState::Suspended
+                } else {
+                    PackageState::Enabled
+                };
+                let output = format!("Package {} new suspended state: {}", package, suspended);
+                state.change_state(package, user, target, output)
+            }
+            ["pm", "clear", ..] => Ok("Success".to_string()),
+            ["am", "force-stop", ..] | ["setprop", ..] => Ok("".to_string()),
+            ["service", "check", service] => Ok(format!("Service {}: found", service)),
+            ["reboot"] => {
+                state.status = "offline".to_string();
+                state.booting = 3;
+                Ok("".to_string())
+            }
+            ["reboot", target] => {
+                state.status = match *target {
+                    "recovery" | "sideload" => target.to_string(),
+                    _ => "disconnected".to_string(), // fastboot is not adb
+                };
+                Ok("".to_string())
+            }
+            _ => Err(format!("/system/bin/sh: {}: not found", command)),
+        }
+    }
+}
@@ -1,6 +1,20 @@
 pub mod config;
+pub mod dependencies;
+pub mod error;
+pub mod journal;
+pub mod labels;
+#[cfg(test)]
+pub mod mock;
+pub mod package_info;
+pub mod profile;
+pub mod queue;
+pub mod reconcile;
+pub mod recovery;
+pub mod snapshot;
 pub mod sync;
 pub mod theme;
+pub mod transport;
 pub mod uad_lists;
 pub mod update;
 pub mod utils;
+pub mod wireless;
@@ -0,0 +1,340 @@
+//! Details of a package read from `dumpsys`. They are only fetched when a package is
+//! pressed in the list (`dumpsys package` is slow on some devices).
+use crate::core::error::AdbError;
+use crate::core::sync::adb_shell_command;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageDetails {
+    pub version_name: Option<String>,
+    pub version_code: Option<u64>,
+    pub installer: Option<String>,
+    pub first_install_time: Option<String>,
+    pub last_update_time: Option<String>,
+    pub data_dir: Option<String>,
+    /// Bytes. Android computes it about once a day.
+    pub data_size: Option<u64>,
+    pub declared_permissions: Vec<String>,
+    pub device_admin: bool,
+    /// Directory of the APK
+    pub code_path: Option<String>,
+    /// Directory of the factory APK of an updated system app
+    pub system_code_path: Option<String>,
+}
+
+impl PackageDetails {
+    /// Partition the package comes from (e.g. `system`, `product`, `vendor` or `data`)
+    pub fn partition(&self) -> Option<&str> {
+        self.system_code_path
+            .as_ref()
+            .or(self.code_path.as_ref())?
+            .split('/')
+            .find(|s| !s.is_empty())
+    }
+}
+
+impl std::fmt::Display for PackageDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unknown = || "unknown".to_string();
+        let version = match (&self.version_name, self.version_code) {
+            (Some(name), Some(code)) => format!("{} ({})", name, code),
+            (Some(name), None) => name.clone(),
+            (None, Some(code)) => code.to_string(),
+            (None, None) => unknown(),
+        };
+        writeln!(f, "Version: {}", version)?;
+        writeln!(
+            f,
+            "Installer: {}",
+            self.installer.clone().unwrap_or_else(unknown)
+        )?;
+        writeln!(
+            f,
+            "First installed: {} | Last updated: {}",
+            self.first_install_time.clone().unwrap_or_else(unknown),
+            self.last_update_time.clone().unwrap_or_else(unknown)
+        )?;
+        writeln!(
+            f,
+            "Data: {} ({})",
+            self.data_size.map(format_size).unwrap_or_else(unknown),
+            self.data_dir.clone().unwrap_or_else(unknown)
+        )?;
+        write!(
+            f,
+            "APK: {} [{}]",
+            self.code_path.clone().unwrap_or_else(unknown),
+            self.partition().unwrap_or("unknown partition")
+        )?;
+        if let Some(path) = &self.system_code_path {
+            write!(f, " (update of {})", path)?;
+        }
+        if self.device_admin {
+            write!(
+                f,
+                "\nDevice admin: yes (it must be deactivated before its removal)"
+            )?;
+        }
+        if !self.declared_permissions.is_empty() {
+            write!(
+                f,
+                "\nDeclared permissions: {}",
+                self.declared_permissions.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+// Block of `dumpsys package` being read
+#[derive(PartialEq, Eq)]
+enum Block {
+    Other,
+    Package,
+    /// Factory version of an updated system app
+    HiddenSystemPackage,
+}
+
+/// Parses the output of `dumpsys package <package>`. Returns `None` if the package isn't
+/// in it.
+pub fn parse_package_details(package: &str, dump: &str) -> Option<PackageDetails> {
+    let header = format!("Package [{}]", package);
+    let mut details = PackageDetails::default();
+    let mut found = false;
+    let mut section = "";
+    let mut block = Block::Other;
+    // Indentation of the `declared permissions:` line while its items are read
+    let mut permissions_indent = None;
+
+    for line in dump.lines() {
+        let content = line.trim();
+        let indent = line.len() - line.trim_start().len();
+        if content.is_empty() {
+            continue;
+        }
+        if indent == 0 {
+            section = content;
+            block = Block::Other;
+            continue;
+        }
+        if content.starts_with("Package [") {
+            block = match section {
+                _ if !content.starts_with(&header) => Block::Other,
+                "Packages:" => Block::Package,
+                "Hidden system packages:" => Block::HiddenSystemPackage,
+                _ => Block::Other,
+            };
+            found |= block == Block::Package;
+            permissions_indent = None;
+            continue;
+        }
+
+        match block {
+            Block::Other => continue,
+            Block::HiddenSystemPackage => {
+                if let Some(path) = content.strip_prefix("codePath=") {
+                    details.system_code_path = Some(path.to_string());
+                }
+                continue;
+            }
+            Block::Package => {}
+        }
+
+        if let Some(i) = permissions_indent {
+            if indent > i {
+                let permission = content.split(':').next().unwrap_or(content);
+                details.declared_permissions.push(permission.to_string());
+                continue;
+            }
+            permissions_indent = None;
+        }
+        if content == "declared permissions:" {
+            permissions_indent = Some(indent);
+            continue;
+        }
+
+        for (key, value) in [
+            ("versionName=", &mut details.version_name),
+            ("installerPackageName=", &mut details.installer),
+            ("firstInstallTime=", &mut details.first_install_time),
+            ("lastUpdateTime=", &mut details.last_update_time),
+            ("dataDir=", &mut details.data_dir),
+            ("codePath=", &mut details.code_path),
+        ] {
+            // Recent Android versions repeat some values for each user
+            if value.is_none() {
+                if let Some(v) = content.strip_prefix(key) {
+                    *value = Some(v.trim().to_string()).filter(|v| v != "null");
+                }
+            }
+        }
+        // e.g. `versionCode=1540371904 minSdk=26 targetSdk=33`
+        if let Some(code) = content
+            .strip_prefix("versionCode=")
+            .and_then(|v| v.split_whitespace().next())
+        {
+            details.version_code = code.parse().ok();
+        }
+    }
+    found.then_some(details)
+}
+
+/// Size of the data of the package in `dumpsys diskstats`, e.g.
+/// `Package Names: ["com.foo","com.bar"]` and `App Data Sizes: [4096,8192]`
+pub fn parse_data_size(package: &str, diskstats: &str) -> Option<u64> {
+    let array = |name: &str| -> Option<Vec<String>> {
+        let line = diskstats
+            .lines()
+            .find_map(|l| l.trim().strip_prefix(name))?;
+        let content = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+        Some(
+            content
+                .split(',')
+                .map(|s| s.trim().trim_matches('"').to_string())
+                .collect(),
+        )
+    };
+    let index = array("Package Names:")?.iter().position(|p| p == package)?;
+    array("App Data Sizes:")?.get(index)?.parse().ok()
+}
+
+pub async fn get_package_details(
+    device_id: String,
+    package: String,
+) -> Result<PackageDetails, AdbError> {
+    let dump = adb_shell_command(&device_id, &format!("dumpsys package {}", package))?;
+    let mut details =
+        parse_package_details(&package, &dump).ok_or(AdbError::NotInstalled { user: None })?;
+
+    // Both dumps may not be allowed: they are not required
+    details.data_size = adb_shell_command(&device_id, "dumpsys diskstats")
+        .ok()
+        .and_then(|diskstats| parse_data_size(&package, &diskstats));
+    details.device_admin = adb_shell_command(&device_id, "dumpsys device_policy")
+        .map(|dump| dump.contains(&format!("ComponentInfo{{{}/", package)))
+        .unwrap_or(false);
+    Ok(details)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{install, MockDevice};
+    use iced::futures::executor::block_on;
+
+    const DUMP: &str = "\
+Activity Resolver Table:
+  Non-Data Actions:
+      android.intent.action.MAIN:
+        5b2c3e1 com.google.android.youtube/.HomeActivity
+
+Packages:
+  Package [com.google.android.youtube] (5b2c3e1):
+    userId=10123
+    codePath=/data/app/~~a1b2==/com.google.android.youtube-c3d4==
+    versionCode=1540371904 minSdk=26 targetSdk=33
+    versionName=18.05.40
+    dataDir=/data/user/0/com.google.android.youtube
+    timeStamp=2023-02-08 19:15:40
+    firstInstallTime=2008-12-31 16:00:00
+    lastUpdateTime=2023-02-10 12:00:00
+    installerPackageName=com.android.vending
+    declared permissions:
+      com.google.android.youtube.permission.C2D_MESSAGE: prot=signature, INSTALLED
+      com.google.android.youtube.DYNAMIC_RECEIVER: prot=signature, INSTALLED
+    requested permissions:
+      android.permission.INTERNET
+    User 0: ceDataInode=12345 installed=true hidden=false suspended=false
+      firstInstallTime=2023-01-01 00:00:00
+
+Hidden system packages:
+  Package [com.google.android.youtube] (a9b8c7d):
+    codePath=/product/app/YouTube
+    versionName=16.20.35
+";
+
+    #[test]
+    fn test_parse_package_details() {
+        let details = parse_package_details("com.google.android.youtube", DUMP).unwrap();
+
+        assert_eq!(details.version_name.as_deref(), Some("18.05.40"));
+        assert_eq!(details.version_code, Some(1540371904));
+        assert_eq!(details.installer.as_deref(), Some("com.android.vending"));
+        assert_eq!(
+            details.first_install_time.as_deref(),
+            Some("2008-12-31 16:00:00")
+        );
+        assert_eq!(
+            details.last_update_time.as_deref(),
+            Some("2023-02-10 12:00:00")
+        );
+        assert_eq!(
+            details.declared_permissions,
+            vec![
+                "com.google.android.youtube.permission.C2D_MESSAGE",
+                "com.google.android.youtube.DYNAMIC_RECEIVER"
+            ]
+        );
+        assert_eq!(
+            details.system_code_path.as_deref(),
+            Some("/product/app/YouTube")
+        );
+        assert_eq!(details.partition(), Some("product"));
+        assert!(parse_package_details("com.google.android.yt", DUMP).is_none());
+    }
+
+    #[test]
+    fn test_parse_data_size() {
+        let diskstats = "Latency: 1ms [512B Data Write]\n\
+            Package Names: [\"com.foo\",\"com.bar\"]\n\
+            App Sizes: [100,200]\n\
+            App Data Sizes: [4096,8192]\n";
+        assert_eq!(parse_data_size("com.bar", diskstats), Some(8192));
+        assert_eq!(parse_data_size("com.baz", diskstats), None);
+        assert_eq!(format_size(8192), "8.0 KB");
+        assert_eq!(format_size(12), "12 B");
+    }
+
+    #[test]
+    fn test_get_package_details() {
+        let (_device, _guard) = install(
+            MockDevice::new(30)
+                .with_response("dumpsys package com.google", Ok(DUMP))
+                .with_response(
+                    "dumpsys device_policy",
+                    Ok("  admin=ComponentInfo{com.google.android.youtube/.Admin}"),
+                )
+                .with_response("dumpsys package com.unknown", Ok("Unable to find package")),
+        );
+
+        let details = block_on(get_package_details(
+            "mock-device".to_string(),
+            "com.google.android.youtube".to_string(),
+        ))
+        .unwrap();
+        assert!(details.device_admin);
+        assert_eq!(details.data_size, None); // `dumpsys diskstats` failed
+
+        assert_eq!(
+            block_on(get_package_details(
+                "mock-device".to_string(),
+                "com.unknown".to_string()
+            )),
+            Err(AdbError::NotInstalled { user: None })
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use crate::core::config::DeviceSettings;
+use crate::core::sync::{package_action, Phone, User};
+use crate::core::uad_lists::PackageState;
+use crate::gui::widgets::package_row::PackageRow;
+use crate::CONFIG_DIR;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// Bump it when the format changes in a non backward-compatible way
+pub const PROFILE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub created: String,
+    pub device: ProfileDevice,
+    pub user_scope: UserScope,
+    pub packages: Vec<ProfilePackage>,
+}
+
+/// Device the profile was made on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileDevice {
+    pub model: String,
+    pub android_sdk: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UserScope {
+    AllUsers,
+    User(u16),
+}
+
+impl std::fmt::Display for UserScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserScope::AllUsers => write!(f, "all users"),
+            UserScope::User(id) => write!(f, "user {}", id),
+        }
+    }
+}
+
+/// Desired state of a package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfilePackage {
+    pub name: String,
+    pub target: PackageState,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileDiff {
+    pub added: Vec<ProfilePackage>,
+    pub removed: Vec<ProfilePackage>,
+    pub changed: Vec<(String, PackageState, PackageState)>,
+}
+
+impl ProfileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Profile {
+    /// Builds a profile from the selected packages. The target of a package is the state
+    /// it would have after applying the current action on it.
+    pub fn from_selection(
+        name: &str,
+        description: &str,
+        phone: &Phone,
+        user: &User,
+        settings: &DeviceSettings,
+        packages: &[&PackageRow],
+    ) -> Self {
+        Self {
+            version: PROFILE_VERSION,
+            name: name.trim().to_string(),
+            description: description.trim().to_string(),
+            created: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            device: ProfileDevice {
+                model: phone.model.clone(),
+                android_sdk: phone.android_sdk,
+            },
+            user_scope: if settings.multi_user_mode {
+                UserScope::AllUsers
+            } else {
+                UserScope::User(user.id)
+            },
+            packages: packages
+                .iter()
+                .map(|p| ProfilePackage {
+                    name: p.name.clone(),
+                    target: package_action(user, &(*p).into(), phone, settings).1,
+                })
+                .collect(),
+        }
+    }
+
+    /// Changes needed to go from `self` to `other`
+    pub fn diff(&self, other: &Profile) -> ProfileDiff {
+        let mut diff = ProfileDiff::default();
+        for p in &other.packages {
+            match self.packages.iter().find(|s| s.name == p.name) {
+                None => diff.added.push(p.clone()),
+                Some(s) if s.target != p.target => {
+                    diff.changed.push((p.name.clone(), s.target, p.target))
+                }
+                Some(_) => {}
+            }
+        }
+        for s in &self.packages {
+            if !other.packages.iter().any(|p| p.name == s.name) {
+                diff.removed.push(s.clone());
+            }
+        }
+        diff
+    }
+
+    pub fn target(&self, package: &str) -> Option<PackageState> {
+        self.packages
+            .iter()
+            .find(|p| p.name == package)
+            .map(|p| p.target)
+    }
+}
+
+fn profiles_dir() -> PathBuf {
+    CONFIG_DIR.join("profiles")
+}
+
+fn profile_file(name: &str) -> PathBuf {
+    let file_name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    profiles_dir().join(format!("{}.json", file_name))
+}
+
+pub fn save_profile(profile: &Profile) -> Result<(), String> {
+    if profile.name.is_empty() {
+        return Err("A profile needs a name".to_string());
+    }
+    let file = profile_file(&profile.name);
+    // Names are sanitized: `work/phone` and `work_phone` share the same file
+    if let Ok(data) = fs::read_to_string(&file) {
+        match serde_json::from_str::<Profile>(&data) {
+            Ok(existing) if existing.name != profile.name => {
+                return Err(format!(
+                    "Profile `{}` already uses this name. Choose another one.",
+                    existing.name
+                ));
+            }
+            _ => {}
+        }
+    }
+    fs::create_dir_all(profiles_dir()).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(profile).map_err(|e| e.to_string())?;
+    fs::write(file, json).map_err(|e| e.to_string())
+}
+
+pub fn load_profile(name: &str) -> Result<Profile, String> {
+    let data = fs::read_to_string(profile_file(name)).map_err(|e| e.to_string())?;
+    let profile: Profile = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+    if profile.version > PROFILE_VERSION {
+        return Err(format!(
+            "Profile `{}` was made by a newer version of UAD (v{} format)",
+            profile.name, profile.version
+        ));
+    }
+    Ok(profile)
+}
+
+pub fn delete_profile(name: &str) -> Result<(), String> {
+    fs::remove_file(profile_file(name)).map_err(|e| e.to_string())
+}
+
+/// All the valid profiles, sorted by name
+pub fn list_profiles() -> Vec<Profile> {
+    let mut profiles: Vec<Profile> = match fs::read_dir(profiles_dir()) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|e| e == "json"))
+            .filter_map(|entry| {
+                let name = entry.path().file_stem()?.to_string_lossy().to_string();
+                match load_profile(&name) {
+                    Ok(profile) => Some(profile),
+                    Err(e) => {
+                        warn!("Ignoring profile {}: {}", entry.path().display(), e);
+                        None
+                    }
+                }
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    profiles.sort_by_key(|p| p.name.to_lowercase());
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::profile;
+
+    #[test]
+    fn test_diff() {
+        let a = profile(
+            "a",
+            UserScope::AllUsers,
+            &[
+                ("com.foo", PackageState::Uninstalled),
+                ("com.bar", PackageState::Disabled),
+            ],
+        );
+        let b = profile(
+            "b",
+            UserScope::AllUsers,
+            &[
+                ("com.foo", PackageState::Disabled),
+                ("com.baz", PackageState::Uninstalled),
+            ],
+        );
+        let diff = a.diff(&b);
+        assert_eq!(diff.added[0].name, "com.baz");
+        assert_eq!(diff.removed[0].name, "com.bar");
+        assert_eq!(
+            diff.changed,
+            vec![(
+                "com.foo".to_string(),
+                PackageState::Uninstalled,
+                PackageState::Disabled
+            )]
+        );
+        assert!(a.diff(&a).is_empty());
+    }
+
+    #[test]
+    fn test_save_load_delete() {
+        let p = profile(
+            "Test profile/1",
+            UserScope::AllUsers,
+            &[("com.foo", PackageState::Uninstalled)],
+        );
+        save_profile(&p).unwrap();
+        assert_eq!(load_profile(&p.name).unwrap(), p);
+        assert!(list_profiles().contains(&p));
+
+        // Same file name, different profile
+        let other = profile("Test profile_1", UserScope::AllUsers, &[]);
+        assert!(save_profile(&other).is_err());
+        assert_eq!(load_profile(&p.name).unwrap(), p);
+        save_profile(&p).unwrap();
+
+        delete_profile(&p.name).unwrap();
+        assert!(load_profile(&p.name).is_err());
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let mut p = profile("future", UserScope::AllUsers, &[]);
+        p.version = PROFILE_VERSION + 1;
+        save_profile(&p).unwrap();
+        assert!(load_profile("future").is_err());
+        delete_profile("future").unwrap();
+    }
+}
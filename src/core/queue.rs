@@ -0,0 +1,448 @@
+//! Package actions are run one after another, each command of a package in order.
+//! The GUI starts the next job when the previous one is done, which makes pausing and
+//! cancelling possible between two packages.
+use crate::core::error::AdbError;
+use crate::core::journal::JournalEntry;
+use crate::core::sync::{changes_state, get_package_state, Phone, User};
+use crate::core::uad_lists::{PackageState, Removal};
+use crate::core::utils::perform_adb_commands;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub device_id: String,
+    pub index: usize, // phone_packages index
+    /// Users whose package state changes
+    pub users: Vec<ExpectedState>,
+    pub package: String,
+    pub removal: Removal,
+    pub commands: Vec<String>,
+    pub journal_entry: Option<JournalEntry>,
+}
+
+/// State of the package for a user once the job is done. It's checked on the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedState {
+    pub index: usize, // phone_packages index of the user
+    /// `None` on devices without multi-user support (no `--user`)
+    pub user: Option<User>,
+    pub state: PackageState,
+}
+
+impl ExpectedState {
+    /// `state` for each user (`phone_packages` index) the commands were sent for
+    pub fn for_users(phone: &Phone, users: &[usize], state: PackageState) -> Vec<Self> {
+        users
+            .iter()
+            .map(|&index| ExpectedState {
+                index,
+                // Same as the commands (see `action_handler`)
+                user: phone
+                    .user_list
+                    .iter()
+                    .find(|user| user.index == index)
+                    .filter(|_| phone.android_sdk >= 21)
+                    .cloned(),
+                state,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed(AdbError),
+    Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobResult {
+    pub id: usize, // Position of the job in the queue
+    pub index: usize,
+    /// State of the package read on the device after the commands (user index, state).
+    /// Empty if the commands failed. Users whose state could not be read are missing.
+    pub states: Vec<(usize, PackageState)>,
+    /// Commands that have been run and whether they succeeded
+    pub commands: Vec<(String, bool)>,
+    pub duration: Duration,
+    pub result: Result<(), AdbError>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ActionQueue {
+    jobs: Vec<Job>,
+    statuses: Vec<JobStatus>,
+    durations: Vec<Duration>,
+    paused: bool,
+}
+
+impl ActionQueue {
+    pub fn push(&mut self, job: Job) {
+        self.jobs.push(job);
+        self.statuses.push(JobStatus::Pending);
+    }
+
+    /// Marks the next pending job as running and returns it.
+    /// Returns `None` if the queue is paused or if a job is already running.
+    pub fn next_job(&mut self) -> Option<(usize, Job)> {
+        if self.paused || self.current().is_some() {
+            return None;
+        }
+        let id = self
+            .statuses
+            .iter()
+            .position(|s| *s == JobStatus::Pending)?;
+        self.statuses[id] = JobStatus::Running;
+        Some((id, self.jobs[id].clone()))
+    }
+
+    pub fn finish(&mut self, result: &JobResult) {
+        if let Some(status) = self.statuses.get_mut(result.id) {
+            *status = match &result.result {
+                Ok(_) => JobStatus::Done,
+                Err(e) => JobStatus::Failed(e.clone()),
+            };
+            self.durations.push(result.duration);
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Pending jobs are dropped. The running job (if any) can't be interrupted.
+    pub fn cancel(&mut self) {
+        for status in &mut self.statuses {
+            if *status == JobStatus::Pending {
+                *status = JobStatus::Cancelled;
+            }
+        }
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// No job is running or waiting
+    pub fn is_finished(&self) -> bool {
+        !self
+            .statuses
+            .iter()
+            .any(|s| matches!(s, JobStatus::Pending | JobStatus::Running))
+    }
+
+    /// The package is waiting or being processed
+    pub fn is_queued(&self, index: usize) -> bool {
+        self.jobs.iter().zip(&self.statuses).any(|(job, status)| {
+            job.index == index && matches!(status, JobStatus::Pending | JobStatus::Running)
+        })
+    }
+
+    pub fn current(&self) -> Option<&Job> {
+        self.statuses
+            .iter()
+            .position(|s| *s == JobStatus::Running)
+            .map(|i| &self.jobs[i])
+    }
+
+    /// (processed jobs, total jobs)
+    pub fn progress(&self) -> (usize, usize) {
+        let processed = self
+            .statuses
+            .iter()
+            .filter(|s| matches!(s, JobStatus::Done | JobStatus::Failed(_)))
+            .count();
+        let cancelled = self.count(&JobStatus::Cancelled);
+        (processed, self.jobs.len() - cancelled)
+    }
+
+    /// Based on the average duration of the jobs already processed
+    pub fn eta(&self) -> Option<Duration> {
+        if self.durations.is_empty() {
+            return None;
+        }
+        let average = self.durations.iter().sum::<Duration>() / self.durations.len() as u32;
+        let (processed, total) = self.progress();
+        Some(average * (total - processed) as u32)
+    }
+
+    pub fn failures(&self) -> Vec<(&str, &AdbError)> {
+        self.jobs
+            .iter()
+            .zip(&self.statuses)
+            .filter_map(|(job, status)| match status {
+                JobStatus::Failed(e) => Some((job.package.as_str(), e)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn count(&self, status: &JobStatus) -> usize {
+        self.statuses.iter().filter(|s| *s == status).count()
+    }
+}
+
+/// Runs the commands of the job in order. If a command changing the package state fails
+/// (one per user), the job stops there. Otherwise, the state of the package is read
+/// back for every user and the job fails if it's not the expected one or if it can't be
+/// read.
+pub async fn run_job(id: usize, job: Job) -> JobResult {
+    let start = Instant::now();
+    let mut journal_entry = job.journal_entry;
+    let mut result = Ok(());
+
+    let mut commands = vec![];
+    for command in job.commands {
+        let state_change = changes_state(&command);
+        let res = perform_adb_commands(
+            job.device_id.clone(),
+            command.clone(),
+            job.index,
+            job.removal.to_string(),
+            journal_entry.take(),
+        )
+        .await;
+        commands.push((command, res.is_ok()));
+        if let (Err(e), true) = (res, state_change) {
+            result = Err(e);
+            break;
+        }
+    }
+
+    let mut states = vec![];
+    if result.is_ok() {
+        for expected in job.users {
+            let user = expected.user.as_ref().map(|u| u.id);
+            let state =
+                match get_package_state(&job.device_id, &job.package, expected.user.as_ref()) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        // The state shown in the list is left as it was
+                        error!(
+                            "[{}] Could not check the state of {}: {}",
+                            job.removal, job.package, e
+                        );
+                        result = Err(AdbError::Unverified {
+                            user,
+                            reason: e.to_string(),
+                        });
+                        continue;
+                    }
+                };
+            if state != expected.state {
+                error!(
+                    "[{}] {} is {} instead of {}",
+                    job.removal, job.package, state, expected.state
+                );
+                result = Err(AdbError::UnexpectedState {
+                    user,
+                    expected: expected.state,
+                    actual: state,
+                });
+            }
+            states.push((expected.index, state));
+        }
+    }
+
+    JobResult {
+        id,
+        index: job.index,
+        states,
+        commands,
+        duration: start.elapsed(),
+        result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{install, MockDevice};
+    use crate::core::uad_lists::PackageState;
+    use iced::futures::executor::block_on;
+
+    fn job(index: usize, package: &str) -> Job {
+        Job {
+            device_id: "mock-device".to_string(),
+            index,
+            users: vec![ExpectedState {
+                index: 0,
+                user: Some(User::new(0, 0)),
+                state: PackageState::Disabled,
+            }],
+            package: package.to_string(),
+            removal: Removal::Recommended,
+            commands: vec![
+                format!("pm disable-user --user 0 {}", package),
+                format!("am force-stop --user 0 {}", package),
+                format!("pm clear --user 0 {}", package),
+            ],
+            journal_entry: None,
+        }
+    }
+
+    fn queue(packages: &[&str]) -> ActionQueue {
+        let mut queue = ActionQueue::default();
+        for (i, p) in packages.iter().enumerate() {
+            queue.push(job(i, p));
+        }
+        queue
+    }
+
+    // Same loop as the GUI
+    fn run(queue: &mut ActionQueue) {
+        while let Some((id, job)) = queue.next_job() {
+            let result = block_on(run_job(id, job));
+            queue.finish(&result);
+        }
+    }
+
+    #[test]
+    fn test_commands_run_in_order() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.bar", PackageState::Enabled),
+        );
+        let mut queue = queue(&["com.foo", "com.bar"]);
+        run(&mut queue);
+
+        assert!(queue.is_finished());
+        assert_eq!(queue.progress(), (2, 2));
+        assert_eq!(queue.count(&JobStatus::Done), 2);
+        assert_eq!(
+            device.history(),
+            vec![
+                "pm disable-user --user 0 com.foo",
+                "am force-stop --user 0 com.foo",
+                "pm clear --user 0 com.foo",
+                "pm list packages -e --user 0 com.foo",
+                "pm list packages -d --user 0 com.foo",
+                "pm disable-user --user 0 com.bar",
+                "am force-stop --user 0 com.bar",
+                "pm clear --user 0 com.bar",
+                "pm list packages -e --user 0 com.bar",
+                "pm list packages -d --user 0 com.bar",
+            ]
+        );
+        assert!(queue.eta().is_some());
+    }
+
+    #[test]
+    fn test_failure_stops_the_package_only() {
+        let (device, _guard) =
+            install(MockDevice::new(30).with_package("com.bar", PackageState::Enabled));
+        let mut queue = queue(&["com.foo", "com.bar"]);
+        run(&mut queue);
+
+        assert_eq!(queue.failures().len(), 1);
+        assert_eq!(
+            queue.failures()[0],
+            ("com.foo", &AdbError::NotInstalled { user: None })
+        );
+        assert_eq!(device.state("com.bar", 0), Some(PackageState::Disabled));
+        assert_eq!(device.history().len(), 6);
+    }
+
+    #[test]
+    fn test_failure_for_another_user() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_user(10, "Alice")
+                .with_package("com.foo", PackageState::Enabled)
+                .with_response("pm disable-user --user 10", Err("Error: user 10 is locked")),
+        );
+        let mut job = job(0, "com.foo");
+        let commands = job.commands.clone();
+        job.commands
+            .extend(commands.iter().map(|c| c.replace("--user 0", "--user 10")));
+        let result = block_on(run_job(0, job));
+
+        assert!(result.result.is_err());
+        assert!(result.states.is_empty());
+        // The clean up commands of user 10 are not run
+        assert_eq!(device.history().len(), 4);
+        assert_eq!(
+            device.history().last().unwrap(),
+            "pm disable-user --user 10 com.foo"
+        );
+    }
+
+    #[test]
+    fn test_state_is_checked() {
+        // The command reports a success without doing anything
+        let (_device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Enabled)
+                .with_response("pm disable-user", Ok("")),
+        );
+        let result = block_on(run_job(0, job(0, "com.foo")));
+
+        assert_eq!(result.states, vec![(0, PackageState::Enabled)]);
+        assert_eq!(
+            result.result,
+            Err(AdbError::UnexpectedState {
+                user: Some(0),
+                expected: PackageState::Disabled,
+                actual: PackageState::Enabled,
+            })
+        );
+    }
+
+    #[test]
+    fn test_state_check_failure() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Enabled)
+                .with_response("pm list packages", Err("error: closed")),
+        );
+        let result = block_on(run_job(0, job(0, "com.foo")));
+
+        // The package is not assumed to be in the expected state
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Disabled));
+        assert!(result.states.is_empty());
+        assert_eq!(
+            result.result,
+            Err(AdbError::Unverified {
+                user: Some(0),
+                reason: "Unexpected output: error: closed".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_pause_and_cancel() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.bar", PackageState::Enabled)
+                .with_package("com.baz", PackageState::Enabled),
+        );
+        let mut queue = queue(&["com.foo", "com.bar", "com.baz"]);
+
+        let (id, job) = queue.next_job().unwrap();
+        assert!(queue.next_job().is_none()); // One job at a time
+        queue.pause();
+        queue.finish(&block_on(run_job(id, job)));
+        assert!(queue.next_job().is_none());
+        assert!(!queue.is_finished());
+
+        queue.resume();
+        let (id, job) = queue.next_job().unwrap();
+        queue.cancel();
+        queue.finish(&block_on(run_job(id, job)));
+        assert!(queue.next_job().is_none());
+        assert!(queue.is_finished());
+        assert_eq!(queue.progress(), (2, 2));
+        assert_eq!(queue.count(&JobStatus::Cancelled), 1);
+        assert_eq!(device.state("com.baz", 0), Some(PackageState::Enabled));
+    }
+}
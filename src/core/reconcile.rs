@@ -0,0 +1,540 @@
+//! Converges a device to the desired state described by a profile
+use crate::core::config::DeviceSettings;
+use crate::core::journal::JournalEntry;
+use crate::core::profile::{Profile, UserScope};
+use crate::core::snapshot::take_snapshot;
+use crate::core::sync::{action_handler, changes_state, CorePackage, Phone, User};
+use crate::core::uad_lists::PackageState;
+use crate::core::utils::{package_rows, perform_adb_commands};
+use crate::gui::widgets::package_row::PackageRow;
+use iced::futures::executor::block_on;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::thread;
+
+/// A single state transition (e.g `Uninstalled -> Enabled`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Step {
+    pub from: PackageState,
+    pub to: PackageState,
+    pub commands: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    pub package: String,
+    pub user: User,
+    pub current: PackageState,
+    pub target: PackageState,
+    pub steps: Vec<Step>,
+}
+
+impl Change {
+    pub fn commands(&self) -> impl Iterator<Item = &String> {
+        self.steps.iter().flat_map(|s| s.commands.iter())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    /// Packages (per user) already in the targeted state
+    pub in_sync: usize,
+    /// Packages of the profile not found on the device
+    pub missing: Vec<String>,
+    /// Disabling packages needs Android 6.0+
+    pub unsupported: Vec<Change>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangeResult {
+    pub change: Change,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub results: Vec<ChangeResult>,
+    /// Changes still needed after the execution of the plan
+    pub drift: Vec<Change>,
+}
+
+impl Report {
+    pub fn is_success(&self) -> bool {
+        self.drift.is_empty() && self.results.iter().all(|r| r.error.is_none())
+    }
+}
+
+/// Outcome of a profile on one device of a batch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceReport {
+    pub device: String, // adb serial
+    pub model: String,
+    /// Only set for a dry-run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<Plan>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<Report>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DeviceReport {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none() && self.report.as_ref().is_none_or(Report::is_success)
+    }
+}
+
+// `action_handler` only knows how to flip a state. Some transitions need 2 flips.
+fn transitions(current: PackageState, target: PackageState) -> Vec<(PackageState, PackageState)> {
+    use PackageState::*;
+    match (current, target) {
+        (Uninstalled, Disabled | Suspended) | (Disabled, Suspended) | (Suspended, Disabled) => {
+            vec![(current, Enabled), (Enabled, target)]
+        }
+        (c, t) if c == t || t == All || c == All => vec![],
+        (c, t) => vec![(c, t)],
+    }
+}
+
+fn step(user: &User, package: &str, from: PackageState, to: PackageState, phone: &Phone) -> Step {
+    let settings = DeviceSettings {
+        device_id: phone.adb_id.clone(),
+        disable_mode: to == PackageState::Disabled,
+        multi_user_mode: false,
+        suspend_mode: to == PackageState::Suspended,
+    };
+    // A disabled or suspended package is uninstalled the same way as an enabled one
+    let state = match (from, to) {
+        (PackageState::Disabled | PackageState::Suspended, PackageState::Uninstalled) => {
+            PackageState::Enabled
+        }
+        (from, _) => from,
+    };
+    let package = CorePackage {
+        name: package.to_string(),
+        state,
+        target: None,
+    };
+    Step {
+        from,
+        to,
+        commands: action_handler(user, &package, phone, &settings),
+    }
+}
+
+/// Computes the commands needed to bring the device (whose packages are `packages`) to
+/// the state described by `profile`. Only packages not in the targeted state are touched.
+pub fn plan(
+    profile: &Profile,
+    phone: &Phone,
+    packages: &[Vec<PackageRow>],
+) -> Result<Plan, String> {
+    let users = if phone.user_list.is_empty() {
+        // No multi-user support before Android 5.0
+        vec![User::new(0, 0)]
+    } else {
+        match profile.user_scope {
+            UserScope::AllUsers => phone.user_list.clone(),
+            UserScope::User(id) => match phone.user_list.iter().find(|u| u.id == id) {
+                Some(user) => vec![user.clone()],
+                None => return Err(format!("User {} not found on the device", id)),
+            },
+        }
+    };
+
+    let mut plan = Plan::default();
+    for user in &users {
+        let states: HashMap<&str, PackageState> = packages
+            .get(user.index)
+            .or_else(|| packages.first())
+            .map(|rows| rows.iter().map(|p| (p.name.as_str(), p.state)).collect())
+            .unwrap_or_default();
+
+        for p in &profile.packages {
+            let current = match states.get(p.name.as_str()) {
+                Some(state) => *state,
+                None => {
+                    if !plan.missing.contains(&p.name) {
+                        plan.missing.push(p.name.clone());
+                    }
+                    continue;
+                }
+            };
+            let steps: Vec<Step> = transitions(current, p.target)
+                .into_iter()
+                .map(|(from, to)| step(user, &p.name, from, to, phone))
+                .collect();
+            if steps.is_empty() {
+                plan.in_sync += 1;
+                continue;
+            }
+            let change = Change {
+                package: p.name.clone(),
+                user: user.clone(),
+                current,
+                target: p.target,
+                steps,
+            };
+            if (p.target == PackageState::Disabled && phone.android_sdk < 23)
+                || (p.target == PackageState::Suspended && phone.android_sdk < 28)
+            {
+                plan.unsupported.push(change);
+            } else {
+                plan.changes.push(change);
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// Reads the state of the packages from the device and computes the plan.
+/// The package cache of the GUI is left untouched.
+pub async fn plan_from_device(profile: Profile, phone: Phone) -> Result<Plan, String> {
+    // A profile can contain user-installed apps
+    let packages: Vec<Vec<PackageRow>> = take_snapshot(&phone, true)
+        .packages
+        .iter()
+        .map(|packages| package_rows(&HashMap::new(), packages))
+        .collect();
+    plan(&profile, &phone, &packages)
+}
+
+/// Runs the plan one change after another. Every state transition is journaled.
+pub async fn execute(plan: &Plan, phone: &Phone) -> Vec<ChangeResult> {
+    let mut results = vec![];
+    for change in &plan.changes {
+        let mut error = None;
+        'steps: for step in &change.steps {
+            let package = CorePackage {
+                name: change.package.clone(),
+                state: step.from,
+                target: None,
+            };
+            let settings = DeviceSettings {
+                device_id: phone.adb_id.clone(),
+                disable_mode: step.to == PackageState::Disabled,
+                multi_user_mode: false,
+                suspend_mode: step.to == PackageState::Suspended,
+            };
+            let mut journal_entry = Some(JournalEntry {
+                new_state: step.to,
+                ..JournalEntry::new(phone, &change.user, &package, &settings, &step.commands)
+            });
+            for command in &step.commands {
+                let res = perform_adb_commands(
+                    phone.adb_id.clone(),
+                    command.clone(),
+                    0,
+                    "Profile".to_string(),
+                    journal_entry.take(),
+                )
+                .await;
+                if let (Err(e), true) = (res, changes_state(command)) {
+                    error = Some(format!("`{}` failed: {}", command, e));
+                    break 'steps;
+                }
+            }
+        }
+        results.push(ChangeResult {
+            change: change.clone(),
+            error,
+        });
+    }
+    results
+}
+
+/// Executes the plan and checks what still differs from the profile afterwards
+pub async fn apply(profile: Profile, phone: Phone, plan: Plan) -> Report {
+    let results = execute(&plan, &phone).await;
+    let drift = match plan_from_device(profile, phone).await {
+        Ok(p) => p.changes,
+        Err(e) => {
+            error!("Could not check the device state: {}", e);
+            plan.changes
+        }
+    };
+    Report { results, drift }
+}
+
+/// Plans (and applies unless `dry_run`) `profile` on every device at the same time.
+/// Every adb command carries the serial of its device so the devices don't interfere.
+pub fn apply_to_devices(profile: &Profile, phones: &[Phone], dry_run: bool) -> Vec<DeviceReport> {
+    thread::scope(|s| {
+        let handles: Vec<_> = phones
+            .iter()
+            .map(|phone| {
+                s.spawn(move || {
+                    let mut device_report = DeviceReport {
+                        device: phone.adb_id.clone(),
+                        model: phone.model.clone(),
+                        plan: None,
+                        report: None,
+                        error: None,
+                    };
+                    match block_on(plan_from_device(profile.clone(), phone.clone())) {
+                        Ok(plan) if dry_run => device_report.plan = Some(plan),
+                        Ok(plan) => {
+                            device_report.report =
+                                Some(block_on(apply(profile.clone(), phone.clone(), plan)));
+                        }
+                        Err(e) => device_report.error = Some(e),
+                    }
+                    device_report
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{connected_phone, install, install_all, profile, MockDevice};
+    use crate::core::snapshot::load_snapshot;
+    use crate::core::sync::get_devices_list;
+
+    #[test]
+    fn test_plan() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_work_profile(10, "Work profile")
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.bar", PackageState::Uninstalled)
+                .with_package("com.baz", PackageState::Disabled),
+        );
+        device.set_state("com.foo", 10, PackageState::Uninstalled);
+        let profile = profile(
+            "test",
+            UserScope::AllUsers,
+            &[
+                ("com.foo", PackageState::Uninstalled),
+                ("com.bar", PackageState::Disabled),
+                ("com.baz", PackageState::Disabled),
+                ("com.missing", PackageState::Uninstalled),
+            ],
+        );
+
+        let plan = block_on(plan_from_device(profile, connected_phone(30))).unwrap();
+        assert_eq!(plan.in_sync, 3);
+        assert_eq!(plan.missing, vec!["com.missing".to_string()]);
+        let commands: Vec<&String> = plan.changes.iter().flat_map(|c| c.commands()).collect();
+        assert_eq!(
+            commands,
+            vec![
+                "pm uninstall --user 0 com.foo",
+                "cmd package install-existing --user 0 com.bar",
+                "pm disable-user --user 0 com.bar",
+                "am force-stop --user 0 com.bar",
+                "pm clear --user 0 com.bar",
+                "cmd package install-existing --user 10 com.bar",
+                "pm disable-user --user 10 com.bar",
+                "am force-stop --user 10 com.bar",
+                "pm clear --user 10 com.bar",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_does_not_cache_packages() {
+        let (_device, _guard) = install(
+            MockDevice::new(30)
+                .with_serial("reconcile-dry-run")
+                .with_package("com.foo", PackageState::Enabled),
+        );
+        let phone = block_on(get_devices_list()).remove(0);
+        let profile = profile(
+            "test",
+            UserScope::User(0),
+            &[("com.foo", PackageState::Uninstalled)],
+        );
+
+        let plan = block_on(plan_from_device(profile, phone.clone())).unwrap();
+        assert_eq!(plan.changes.len(), 1);
+        assert!(load_snapshot(&phone, true).is_none());
+    }
+
+    #[test]
+    fn test_apply() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Disabled)
+                .with_package("com.bar", PackageState::Uninstalled)
+                .with_package("com.baz", PackageState::Enabled),
+        );
+        let profile = profile(
+            "test",
+            UserScope::User(0),
+            &[
+                ("com.foo", PackageState::Uninstalled),
+                ("com.bar", PackageState::Enabled),
+                ("com.baz", PackageState::Disabled),
+            ],
+        );
+        let plan = block_on(plan_from_device(profile.clone(), connected_phone(30))).unwrap();
+        assert_eq!(plan.changes.len(), 3);
+
+        let report = block_on(apply(profile.clone(), connected_phone(30), plan));
+        assert!(report.results.iter().all(|r| r.error.is_none()));
+        assert!(report.drift.is_empty());
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Uninstalled));
+        assert_eq!(device.state("com.bar", 0), Some(PackageState::Enabled));
+        assert_eq!(device.state("com.baz", 0), Some(PackageState::Disabled));
+
+        // Nothing left to do
+        let plan = block_on(plan_from_device(profile, connected_phone(30))).unwrap();
+        assert!(plan.changes.is_empty());
+        assert_eq!(plan.in_sync, 3);
+    }
+
+    #[test]
+    fn test_apply_suspended() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Disabled)
+                .with_package("com.bar", PackageState::Enabled)
+                .with_package("com.baz", PackageState::Suspended),
+        );
+        let profile = profile(
+            "test",
+            UserScope::User(0),
+            &[
+                ("com.foo", PackageState::Suspended),
+                ("com.bar", PackageState::Suspended),
+                ("com.baz", PackageState::Uninstalled),
+            ],
+        );
+        let plan = block_on(plan_from_device(profile.clone(), connected_phone(30))).unwrap();
+        let commands: Vec<&String> = plan.changes.iter().flat_map(|c| c.commands()).collect();
+        assert_eq!(
+            commands,
+            vec![
+                "pm enable --user 0 com.foo",
+                "pm suspend --user 0 com.foo",
+                "pm suspend --user 0 com.bar",
+                "pm uninstall --user 0 com.baz",
+            ]
+        );
+
+        let report = block_on(apply(profile, connected_phone(30), plan));
+        assert!(report.drift.is_empty());
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Suspended));
+        assert_eq!(device.state("com.bar", 0), Some(PackageState::Suspended));
+        assert_eq!(device.state("com.baz", 0), Some(PackageState::Uninstalled));
+    }
+
+    #[test]
+    fn test_drift() {
+        let (_device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Enabled)
+                .with_response("pm uninstall", Ok("Failure [DELETE_FAILED_INTERNAL_ERROR]")),
+        );
+        let profile = profile(
+            "test",
+            UserScope::User(0),
+            &[("com.foo", PackageState::Uninstalled)],
+        );
+        let plan = block_on(plan_from_device(profile.clone(), connected_phone(30))).unwrap();
+
+        let report = block_on(apply(profile, connected_phone(30), plan));
+        assert!(report.results[0].error.is_some());
+        assert_eq!(report.drift[0].package, "com.foo");
+    }
+
+    #[test]
+    fn test_drift_of_another_user() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_work_profile(10, "Work profile")
+                .with_package("com.foo", PackageState::Enabled)
+                .with_response("pm disable-user --user 10", Err("Exception occurred"))
+                .with_response("am force-stop", Err("Exception occurred")),
+        );
+        let profile = profile(
+            "test",
+            UserScope::AllUsers,
+            &[("com.foo", PackageState::Disabled)],
+        );
+        let plan = block_on(plan_from_device(profile.clone(), connected_phone(30))).unwrap();
+        assert_eq!(plan.changes.len(), 2);
+
+        // A failed clean-up doesn't fail the change
+        let report = block_on(apply(profile, connected_phone(30), plan));
+        assert!(report.results[0].error.is_none());
+        assert!(report.results[1].error.is_some());
+        assert!(!report.is_success());
+        assert_eq!(report.drift.len(), 1);
+        assert_eq!(report.drift[0].user.id, 10);
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Disabled));
+    }
+
+    #[test]
+    fn test_apply_to_devices() {
+        let (devices, _guard) = install_all(vec![
+            MockDevice::new(30)
+                .with_serial("phone-1")
+                .with_package("com.foo", PackageState::Enabled),
+            MockDevice::new(30)
+                .with_serial("phone-2")
+                .with_package("com.foo", PackageState::Uninstalled),
+            MockDevice::new(30).with_serial("phone-3"),
+        ]);
+        let phones = block_on(get_devices_list());
+        assert_eq!(phones.len(), 3);
+        let profile = profile(
+            "test",
+            UserScope::User(0),
+            &[("com.foo", PackageState::Disabled)],
+        );
+
+        let reports = apply_to_devices(&profile, &phones, true);
+        assert_eq!(reports[0].plan.as_ref().unwrap().changes.len(), 1);
+        assert!(devices[0].history().iter().all(|c| !c.contains("com.foo")));
+
+        let reports = apply_to_devices(&profile, &phones, false);
+        assert_eq!(
+            reports
+                .iter()
+                .map(|r| r.device.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["phone-1", "phone-2", "phone-3"]
+        );
+        assert!(reports[0].is_success());
+        assert!(reports[1].is_success());
+        assert_eq!(reports[2].report.as_ref().unwrap().results.len(), 0);
+        assert_eq!(devices[0].state("com.foo", 0), Some(PackageState::Disabled));
+        assert_eq!(devices[1].state("com.foo", 0), Some(PackageState::Disabled));
+        assert!(devices[1]
+            .history()
+            .contains(&"cmd package install-existing --user 0 com.foo".to_string()));
+        assert!(!devices[0]
+            .history()
+            .contains(&"cmd package install-existing --user 0 com.foo".to_string()));
+    }
+
+    #[test]
+    fn test_unsupported_and_unknown_user() {
+        let (_device, _guard) =
+            install(MockDevice::new(21).with_package("com.foo", PackageState::Enabled));
+        let plan = block_on(plan_from_device(
+            profile(
+                "test",
+                UserScope::AllUsers,
+                &[("com.foo", PackageState::Disabled)],
+            ),
+            connected_phone(21),
+        ))
+        .unwrap();
+        assert!(plan.changes.is_empty());
+        assert_eq!(plan.unsupported[0].package, "com.foo");
+
+        assert!(block_on(plan_from_device(
+            profile("test", UserScope::User(10), &[]),
+            connected_phone(21)
+        ))
+        .is_err());
+    }
+}
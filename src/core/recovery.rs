@@ -0,0 +1,319 @@
+//! Restore points. The commands bringing back `Expert` and `Unsafe` packages are saved
+//! before they are removed. If the device bootloops, they are run as soon as
+//! `adb shell` is usable again (the package manager starts before the end of the boot).
+use crate::core::error::AdbError;
+use crate::core::journal::JournalEntry;
+use crate::core::sync::{adb_shell_command, find_device, DeviceStatus, Phone};
+use crate::core::uad_lists::Removal;
+use crate::core::utils::{shell_script, PlannedAction};
+use crate::CONFIG_DIR;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestorePoint {
+    pub device_id: String,
+    pub model: String,
+    pub android_sdk: u8,
+    pub date: String,
+    pub packages: Vec<RestorePackage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestorePackage {
+    pub package: String,
+    pub removal: Removal,
+    /// Inverse of the removal commands (see `JournalEntry::rollback_commands`)
+    pub commands: Vec<String>,
+}
+
+impl RestorePackage {
+    /// `entry` is the journal entry of the removal
+    pub fn new(phone: &Phone, removal: Removal, entry: &JournalEntry) -> Self {
+        Self {
+            package: entry.package.clone(),
+            removal,
+            commands: entry.rollback_commands(phone),
+        }
+    }
+}
+
+impl RestorePoint {
+    /// Same commands as a standalone script (only `adb` is needed to run it)
+    pub fn script(&self) -> String {
+        let phone = Phone {
+            model: self.model.clone(),
+            android_sdk: self.android_sdk,
+            adb_id: self.device_id.clone(),
+            ..Phone::default()
+        };
+        let actions: Vec<PlannedAction> = self
+            .packages
+            .iter()
+            .map(|p| PlannedAction {
+                index: 0,
+                package: p.package.clone(),
+                removal: p.removal,
+                commands: p.commands.clone(),
+            })
+            .collect();
+        shell_script(&phone, "Restore point", &actions)
+    }
+}
+
+/// Removals that can bootloop the device
+pub fn is_risky(removal: Removal) -> bool {
+    matches!(removal, Removal::Expert | Removal::Unsafe)
+}
+
+pub fn restore_points_dir() -> PathBuf {
+    CONFIG_DIR.join("recovery")
+}
+
+fn restore_point_file(device_id: &str, extension: &str) -> PathBuf {
+    // Wireless devices have a `:` in their serial
+    let name: String = device_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    restore_points_dir().join(format!("{}.{}", name, extension))
+}
+
+pub fn load_restore_point(device_id: &str) -> Option<RestorePoint> {
+    let content = fs::read_to_string(restore_point_file(device_id, "json")).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(point) => Some(point),
+        Err(e) => {
+            error!("Invalid restore point for {}: {}", device_id, e);
+            None
+        }
+    }
+}
+
+pub fn list_restore_points() -> Vec<RestorePoint> {
+    let mut points: Vec<RestorePoint> = match fs::read_dir(restore_points_dir()) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|e| fs::read_to_string(e.path()).ok())
+            .filter_map(|content| serde_json::from_str(&content).ok())
+            .collect(),
+        Err(_) => vec![],
+    };
+    points.sort_by(|a, b| b.date.cmp(&a.date));
+    points
+}
+
+// The JSON file is read by UAD, the script is for the user
+fn write_restore_point(point: &RestorePoint) -> Result<(), String> {
+    fs::create_dir_all(restore_points_dir()).map_err(|e| e.to_string())?;
+    let json = serde_json::to_string_pretty(point).map_err(|e| e.to_string())?;
+    fs::write(restore_point_file(&point.device_id, "json"), json).map_err(|e| e.to_string())?;
+    fs::write(restore_point_file(&point.device_id, "sh"), point.script()).map_err(|e| e.to_string())
+}
+
+pub fn delete_restore_point(device_id: &str) {
+    for extension in ["json", "sh"] {
+        let _ = fs::remove_file(restore_point_file(device_id, extension));
+    }
+}
+
+/// Adds `packages` to the restore point of the device (a package already there is replaced).
+/// Must be called before the packages are removed.
+pub fn save_restore_point(phone: &Phone, packages: Vec<RestorePackage>) {
+    let mut point = load_restore_point(&phone.adb_id).unwrap_or(RestorePoint {
+        device_id: phone.adb_id.clone(),
+        model: phone.model.clone(),
+        android_sdk: phone.android_sdk,
+        date: String::new(),
+        packages: vec![],
+    });
+    point.date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    for package in packages {
+        point.packages.retain(|p| p.package != package.package);
+        point.packages.push(package);
+    }
+    match write_restore_point(&point) {
+        Ok(_) => info!(
+            "Restore point of {} saved ({} packages)",
+            point.device_id,
+            point.packages.len()
+        ),
+        Err(e) => error!(
+            "Could not save the restore point of {}: {}",
+            point.device_id, e
+        ),
+    }
+}
+
+/// Removes the packages that have been restored. The restore point is deleted once empty.
+pub fn remove_from_restore_point(device_id: &str, restored: &[String]) -> Option<RestorePoint> {
+    let mut point = load_restore_point(device_id)?;
+    point.packages.retain(|p| !restored.contains(&p.package));
+    if point.packages.is_empty() {
+        delete_restore_point(device_id);
+        return None;
+    }
+    if let Err(e) = write_restore_point(&point) {
+        error!("Could not update the restore point of {}: {}", device_id, e);
+    }
+    Some(point)
+}
+
+/// Where the device stands during a recovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryState {
+    /// The device is not listed by ADB (e.g. it's rebooting)
+    Waiting,
+    /// ADB sees the device but `adb shell` can't be used (e.g. `unauthorized` or `recovery`)
+    NotReady(DeviceStatus),
+    /// `adb shell` works but the package manager is not started yet
+    Booting,
+    /// The commands have been run
+    Ready,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecoveryAttempt {
+    pub state: RecoveryState,
+    pub results: Vec<(String, Result<(), AdbError>)>,
+}
+
+/// Waits `delay` then tries to run the commands of `packages`. A recovery is a loop of
+/// attempts: the packages that failed are retried on the next one.
+pub async fn attempt_recovery(
+    device_id: String,
+    packages: Vec<RestorePackage>,
+    delay: Duration,
+) -> RecoveryAttempt {
+    thread::sleep(delay);
+    let waiting = |state| RecoveryAttempt {
+        state,
+        results: vec![],
+    };
+
+    let status = match find_device(&device_id) {
+        Some(device) => device.status,
+        None => return waiting(RecoveryState::Waiting),
+    };
+    match adb_shell_command(&device_id, "service check package") {
+        Ok(o) if o.contains(": found") => {}
+        Ok(_) => return waiting(RecoveryState::Booting),
+        Err(_) if status == DeviceStatus::Device => return waiting(RecoveryState::Booting),
+        Err(_) => return waiting(RecoveryState::NotReady(status)),
+    }
+
+    let results = packages
+        .into_iter()
+        .map(|p| {
+            // The first command brings the package back. The other ones are optional.
+            let mut result = Ok(());
+            for (i, command) in p.commands.iter().enumerate() {
+                match adb_shell_command(&device_id, command) {
+                    Ok(o) => info!("[Recovery] {} -> {}", command, o),
+                    Err(e) => {
+                        error!("[Recovery] {} -> {}", command, e);
+                        if i == 0 {
+                            result = Err(e);
+                            break;
+                        }
+                    }
+                }
+            }
+            (p.package, result)
+        })
+        .collect();
+    RecoveryAttempt {
+        state: RecoveryState::Ready,
+        results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{install, MockDevice};
+    use crate::core::uad_lists::PackageState;
+    use iced::futures::executor::block_on;
+
+    fn restore_package(package: &str, removal: Removal) -> RestorePackage {
+        RestorePackage {
+            package: package.to_string(),
+            removal,
+            commands: vec![format!("cmd package install-existing --user 0 {}", package)],
+        }
+    }
+
+    #[test]
+    fn test_restore_point() {
+        let phone = Phone {
+            model: "Pixel".to_string(),
+            android_sdk: 30,
+            adb_id: "192.168.1.20:5555".to_string(),
+            ..Phone::default()
+        };
+        delete_restore_point(&phone.adb_id);
+
+        save_restore_point(&phone, vec![restore_package("com.foo", Removal::Expert)]);
+        save_restore_point(
+            &phone,
+            vec![
+                restore_package("com.foo", Removal::Expert),
+                restore_package("com.bar", Removal::Unsafe),
+            ],
+        );
+        let point = load_restore_point(&phone.adb_id).unwrap();
+        assert_eq!(point.packages.len(), 2);
+        assert!(point
+            .script()
+            .contains("$ADB shell cmd package install-existing --user 0 com.bar"));
+        assert!(list_restore_points().contains(&point));
+
+        let point = remove_from_restore_point(&phone.adb_id, &["com.foo".to_string()]).unwrap();
+        assert_eq!(
+            point.packages,
+            vec![restore_package("com.bar", Removal::Unsafe)]
+        );
+        assert!(remove_from_restore_point(&phone.adb_id, &["com.bar".to_string()]).is_none());
+        assert!(load_restore_point(&phone.adb_id).is_none());
+    }
+
+    #[test]
+    fn test_attempt_recovery() {
+        let packages = vec![
+            restore_package("com.foo", Removal::Unsafe),
+            restore_package("com.unknown", Removal::Expert),
+        ];
+        let attempt = |device| {
+            let (device, _guard) = install(device);
+            let attempt = block_on(attempt_recovery(
+                "mock-device".to_string(),
+                packages.clone(),
+                Duration::ZERO,
+            ));
+            (device, attempt)
+        };
+
+        let (_, early_boot) = attempt(
+            MockDevice::new(30)
+                .with_response("service check package", Ok("Service package: not found")),
+        );
+        assert_eq!(early_boot.state, RecoveryState::Booting);
+
+        let (_, locked) = attempt(MockDevice::new(30).with_status("unauthorized"));
+        assert_eq!(
+            locked.state,
+            RecoveryState::NotReady(DeviceStatus::Unauthorized)
+        );
+
+        let (device, booted) =
+            attempt(MockDevice::new(30).with_package("com.foo", PackageState::Uninstalled));
+        assert_eq!(booted.state, RecoveryState::Ready);
+        assert!(booted.results[0].1.is_ok());
+        assert!(booted.results[1].1.is_err());
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Enabled));
+    }
+}
@@ -0,0 +1,321 @@
+//! Packages of a device, read in a few adb calls. The state of each package for every user
+//! comes from a single `dumpsys package` instead of three `pm list packages` per user.
+//! The last snapshot of each device is cached: the list is shown right away and
+//! reconciled once the device has answered.
+use crate::core::sync::{
+    adb_shell_command, get_install_dates, hashset_system_packages, hashset_third_party_packages,
+    list_all_system_packages, list_third_party_packages, parse_install_dates, Phone, User,
+};
+use crate::core::uad_lists::PackageState;
+use crate::CACHE_DIR;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use static_init::dynamic;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+/// A package as reported by the device (the UAD lists are not applied)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DevicePackage {
+    pub name: String,
+    pub state: PackageState,
+    /// Installed by the user (or pushed by the carrier/OEM after the first boot)
+    pub third_party: bool,
+    pub installer: Option<String>,
+    pub install_date: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageSnapshot {
+    /// Ids of the user list of the device when the snapshot was taken
+    pub users: Vec<u16>,
+    pub third_party: bool,
+    /// Packages of every user (indexed like the user list)
+    pub packages: Vec<Vec<DevicePackage>>,
+}
+
+// Suspended packages are listed as enabled too
+fn listed_state(
+    name: &str,
+    enabled: &HashSet<String>,
+    disabled: &HashSet<String>,
+    suspended: &HashSet<String>,
+) -> PackageState {
+    if suspended.contains(name) {
+        PackageState::Suspended
+    } else if enabled.contains(name) {
+        PackageState::Enabled
+    } else if disabled.contains(name) {
+        PackageState::Disabled
+    } else {
+        PackageState::Uninstalled
+    }
+}
+
+/// Packages of the user read with `pm list packages` (one call per state)
+pub fn list_packages(
+    device_id: &str,
+    user_id: Option<&User>,
+    third_party: bool,
+) -> Vec<DevicePackage> {
+    let all_system_packages = list_all_system_packages(device_id, user_id); // installed and uninstalled packages
+    let enabled = hashset_system_packages(device_id, PackageState::Enabled, user_id);
+    let disabled = hashset_system_packages(device_id, PackageState::Disabled, user_id);
+    // Both system and user-installed packages
+    let suspended = hashset_system_packages(device_id, PackageState::Suspended, user_id);
+
+    let mut packages: Vec<DevicePackage> = all_system_packages
+        .lines()
+        .map(|name| DevicePackage {
+            name: name.to_string(),
+            state: listed_state(name, &enabled, &disabled, &suspended),
+            third_party: false,
+            installer: None,
+            install_date: None,
+        })
+        .collect();
+    if third_party {
+        let enabled = hashset_third_party_packages(device_id, PackageState::Enabled, user_id);
+        let disabled = hashset_third_party_packages(device_id, PackageState::Disabled, user_id);
+        let dates = get_install_dates(device_id);
+        packages.extend(
+            list_third_party_packages(device_id, user_id)
+                .into_iter()
+                .map(|p| DevicePackage {
+                    state: listed_state(&p.name, &enabled, &disabled, &suspended),
+                    third_party: true,
+                    install_date: dates.get(&p.name).cloned(),
+                    installer: p.installer,
+                    name: p.name,
+                }),
+        );
+    }
+    packages
+}
+
+/// State of every package for each user, from `dumpsys package packages`, e.g.
+/// `User 10: ceDataInode=0 installed=true hidden=false suspended=false stopped=false
+/// notLaunched=false enabled=3`. Android 4.4 has `blocked` instead of `hidden`.
+/// Older versions have no user lines: the result is empty.
+pub fn parse_package_states(dump: &str) -> HashMap<String, HashMap<u16, PackageState>> {
+    #[dynamic]
+    static RE: Regex = Regex::new(r"Package \[(.+)\]|User ([0-9]+):(.*)").unwrap();
+
+    let mut states: HashMap<String, HashMap<u16, PackageState>> = HashMap::new();
+    let mut package = None;
+    for c in RE.captures_iter(dump) {
+        match (c.get(1), c.get(2), c.get(3)) {
+            (Some(name), ..) => package = Some(name.as_str().to_string()),
+            (_, Some(id), Some(fields)) => {
+                let (p, id) = match (&package, id.as_str().parse()) {
+                    (Some(p), Ok(id)) => (p, id),
+                    _ => continue,
+                };
+                let field = |key: &str| {
+                    fields
+                        .as_str()
+                        .split_whitespace()
+                        .find_map(|f| f.strip_prefix(key)?.strip_prefix('='))
+                };
+                let state = if field("installed") == Some("false")
+                    || field("hidden") == Some("true")
+                    || field("blocked") == Some("true")
+                {
+                    PackageState::Uninstalled
+                } else if field("suspended") == Some("true") {
+                    PackageState::Suspended
+                } else if matches!(field("enabled"), Some("2" | "3" | "4")) {
+                    // disabled, disabled-user or disabled-until-used
+                    PackageState::Disabled
+                } else {
+                    PackageState::Enabled
+                };
+                // Factory versions of updated system apps come after the packages
+                states
+                    .entry(p.clone())
+                    .or_default()
+                    .entry(id)
+                    .or_insert(state);
+            }
+            _ => {}
+        }
+    }
+    states
+}
+
+/// Packages of every user of the device. The adb calls are run concurrently.
+pub fn take_snapshot(phone: &Phone, third_party: bool) -> PackageSnapshot {
+    let device_id = phone.adb_id.as_str();
+    let users: Vec<Option<&User>> = if phone.user_list.len() <= 1 {
+        vec![None]
+    } else {
+        phone.user_list.iter().map(Some).collect()
+    };
+
+    let (system_packages, third_party_packages, dump) = thread::scope(|s| {
+        let system_packages = s.spawn(|| list_all_system_packages(device_id, None));
+        let third_party_packages = s.spawn(|| match third_party {
+            true => list_third_party_packages(device_id, None),
+            false => vec![],
+        });
+        let dump = adb_shell_command(
+            device_id,
+            "dumpsys package packages | grep -E 'Package \\[|User [0-9]+:|firstInstallTime='",
+        )
+        .unwrap_or_default();
+        (
+            system_packages.join().unwrap_or_default(),
+            third_party_packages.join().unwrap_or_default(),
+            dump,
+        )
+    });
+    let states = parse_package_states(&dump);
+
+    let packages = if states.is_empty() {
+        // The dump can't be used: the packages of each user are listed (at the same time)
+        thread::scope(|s| {
+            let handles: Vec<_> = users
+                .iter()
+                .map(|user| s.spawn(move || list_packages(device_id, *user, third_party)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_default())
+                .collect()
+        })
+    } else {
+        let dates = parse_install_dates(&dump);
+        users
+            .iter()
+            .map(|user| {
+                let id = user.map_or(0, |u| u.id);
+                let state = |name: &str| {
+                    states
+                        .get(name)
+                        .and_then(|s| s.get(&id))
+                        .copied()
+                        .unwrap_or(PackageState::Uninstalled)
+                };
+                system_packages
+                    .lines()
+                    .map(|name| DevicePackage {
+                        name: name.to_string(),
+                        state: state(name),
+                        third_party: false,
+                        installer: None,
+                        install_date: None,
+                    })
+                    .chain(third_party_packages.iter().map(|p| DevicePackage {
+                        name: p.name.clone(),
+                        state: state(&p.name),
+                        third_party: true,
+                        installer: p.installer.clone(),
+                        install_date: dates.get(&p.name).cloned(),
+                    }))
+                    .collect()
+            })
+            .collect()
+    };
+
+    PackageSnapshot {
+        users: phone.user_list.iter().map(|u| u.id).collect(),
+        third_party,
+        packages,
+    }
+}
+
+fn snapshot_file(device_id: &str) -> PathBuf {
+    // Wireless devices have a `:` in their serial
+    let name: String = device_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    CACHE_DIR.join("packages").join(format!("{}.json", name))
+}
+
+pub fn save_snapshot(device_id: &str, snapshot: &PackageSnapshot) {
+    let file = snapshot_file(device_id);
+    let res = fs::create_dir_all(CACHE_DIR.join("packages"))
+        .map_err(|e| e.to_string())
+        .and_then(|_| serde_json::to_string(snapshot).map_err(|e| e.to_string()))
+        .and_then(|json| fs::write(&file, json).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        warn!("Could not cache the packages of {}: {}", device_id, e);
+    }
+}
+
+/// Last snapshot of the device. It's ignored if the users or the `third_party` setting
+/// have changed since.
+pub fn load_snapshot(phone: &Phone, third_party: bool) -> Option<PackageSnapshot> {
+    let content = fs::read_to_string(snapshot_file(&phone.adb_id)).ok()?;
+    let snapshot: PackageSnapshot = serde_json::from_str(&content).ok()?;
+    let users: Vec<u16> = phone.user_list.iter().map(|u| u.id).collect();
+    (snapshot.users == users && snapshot.third_party == third_party).then_some(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{install, phone, MockDevice};
+
+    #[test]
+    fn test_parse_package_states() {
+        let dump = "\
+  Package [com.foo] (1a2b3c):
+    User 0: ceDataInode=1 installed=true hidden=false suspended=false stopped=false notLaunched=false enabled=3
+    User 10: ceDataInode=2 installed=false hidden=false suspended=false stopped=true notLaunched=true enabled=0
+  Package [com.bar] (4d5e6f):
+    User 0: ceDataInode=3 installed=true hidden=false suspended=true stopped=false notLaunched=false enabled=0
+  Package [com.old] (7a8b9c):
+    User 0:  installed=true blocked=true stopped=false notLaunched=false enabled=0
+Hidden system packages:
+  Package [com.foo] (0a1b2c):
+    User 0: ceDataInode=0 installed=true hidden=false suspended=false stopped=false notLaunched=false enabled=0
+";
+        let states = parse_package_states(dump);
+        assert_eq!(states["com.foo"][&0], PackageState::Disabled);
+        assert_eq!(states["com.foo"][&10], PackageState::Uninstalled);
+        assert_eq!(states["com.bar"][&0], PackageState::Suspended);
+        assert_eq!(states["com.old"][&0], PackageState::Uninstalled);
+        assert!(parse_package_states("  Package [com.foo] (1a2b3c):\n    enabled=0").is_empty());
+    }
+
+    #[test]
+    fn test_take_snapshot() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_work_profile(10, "Work profile")
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.bar", PackageState::Disabled)
+                .with_third_party_package("org.app", "com.android.vending", "2023-01-02 10:00:00"),
+        );
+        device.set_state("com.foo", 10, PackageState::Suspended);
+        let phone = phone(30, &[0, 10]);
+        let snapshot = take_snapshot(&phone, true);
+
+        // Same packages and states as with `pm list packages` (3 calls per user)
+        for (i, user) in phone.user_list.iter().enumerate() {
+            let mut packages = list_packages("mock-device", Some(user), true);
+            let mut from_dump = snapshot.packages[i].clone();
+            packages.sort_by(|a, b| a.name.cmp(&b.name));
+            from_dump.sort_by(|a, b| a.name.cmp(&b.name));
+            assert_eq!(from_dump, packages);
+        }
+        let foo = |user: usize| {
+            snapshot.packages[user]
+                .iter()
+                .find(|p| p.name == "com.foo")
+                .unwrap()
+                .state
+        };
+        assert_eq!(foo(0), PackageState::Enabled);
+        assert_eq!(foo(1), PackageState::Suspended);
+
+        save_snapshot(&phone.adb_id, &snapshot);
+        assert_eq!(load_snapshot(&phone, true), Some(snapshot));
+        assert_eq!(load_snapshot(&phone, false), None);
+        assert_eq!(load_snapshot(&self::phone(30, &[0]), true), None);
+    }
+}
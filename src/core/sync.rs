@@ -1,25 +1,25 @@
 use crate::core::config::DeviceSettings;
+use crate::core::error::AdbError;
+use crate::core::transport::{read_frame, transport};
 use crate::core::uad_lists::PackageState;
 use crate::gui::widgets::package_row::PackageRow;
 use regex::Regex;
 use retry::{delay::Fixed, retry, OperationResult};
+use serde::Serialize;
 use static_init::dynamic;
-use std::collections::HashSet;
-use std::env;
-use std::process::Command;
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::Duration;
 
-#[cfg(target_os = "windows")]
-use std::os::windows::process::CommandExt;
-
-#[dynamic]
-static RE: Regex = Regex::new(r"\n(\S+)\s+device").unwrap();
-
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Phone {
     pub model: String,
     pub android_sdk: u8,
     pub user_list: Vec<User>,
     pub adb_id: String,
+    /// Only devices in the `Device` state can be used. The others are listed to tell
+    /// the user what to do.
+    pub status: DeviceStatus,
 }
 
 impl Default for Phone {
@@ -29,90 +29,328 @@ impl Default for Phone {
             android_sdk: 0,
             user_list: vec![],
             adb_id: "".to_string(),
+            status: DeviceStatus::Device,
         }
     }
 }
 
+impl Phone {
+    pub fn is_ready(&self) -> bool {
+        self.status == DeviceStatus::Device
+    }
+}
+
+/// State of a device as reported by `adb devices`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum DeviceStatus {
+    Device,
+    Unauthorized,
+    Authorizing,
+    Offline,
+    Connecting,
+    Recovery,
+    Sideload,
+    Bootloader,
+    NoPermissions,
+    Unknown(String),
+}
+
+impl DeviceStatus {
+    fn parse(status: &str) -> Self {
+        match status {
+            "device" => Self::Device,
+            "unauthorized" => Self::Unauthorized,
+            "authorizing" => Self::Authorizing,
+            "offline" => Self::Offline,
+            "connecting" => Self::Connecting,
+            "recovery" => Self::Recovery,
+            "sideload" => Self::Sideload,
+            "bootloader" => Self::Bootloader,
+            s if s.starts_with("no permissions") => Self::NoPermissions,
+            s => Self::Unknown(s.to_string()),
+        }
+    }
+
+    /// What the user has to do to make the device usable
+    pub fn guidance(&self) -> String {
+        match self {
+            Self::Device => "".to_string(),
+            Self::Unauthorized => {
+                "Unlock the phone and accept the RSA key prompt (\"Allow USB debugging?\")"
+                    .to_string()
+            }
+            Self::Authorizing => "Waiting for the phone to authorize this computer...".to_string(),
+            Self::Offline => {
+                "Unplug and replug the phone, or restart ADB (`adb kill-server`)".to_string()
+            }
+            Self::Connecting => "Connecting to the phone...".to_string(),
+            Self::Recovery | Self::Sideload | Self::Bootloader => {
+                format!("The phone is in {} mode: reboot it to Android", self)
+            }
+            Self::NoPermissions => "No permission to access the USB device: check your udev rules \
+                (Linux) or switch the USB mode to file transfer"
+                .to_string(),
+            Self::Unknown(_) => "Unplug and replug the phone".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Device => write!(f, "ready"),
+            Self::Unauthorized => write!(f, "unauthorized"),
+            Self::Authorizing => write!(f, "authorizing"),
+            Self::Offline => write!(f, "offline"),
+            Self::Connecting => write!(f, "connecting"),
+            Self::Recovery => write!(f, "recovery"),
+            Self::Sideload => write!(f, "sideload"),
+            Self::Bootloader => write!(f, "bootloader"),
+            Self::NoPermissions => write!(f, "no permissions"),
+            Self::Unknown(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// A line of `adb devices -l`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdbDevice {
+    pub serial: String,
+    pub status: DeviceStatus,
+    pub product: Option<String>,
+    pub model: Option<String>,
+    pub device: Option<String>,
+    pub transport_id: Option<u32>,
+}
+
+/// Parses the output of `adb devices -l`, e.g:
+/// `emulator-5554 device product:sdk_phone model:Pixel_6 device:oriole transport_id:1`
+pub fn parse_devices(output: &str) -> Vec<AdbDevice> {
+    #[dynamic]
+    static RE: Regex =
+        Regex::new(r"^(\S+)\s+(no permissions[^\[]*(?:\[[^\]]*\])?|\S+)(.*)$").unwrap();
+
+    output
+        .lines()
+        .filter(|l| {
+            !l.trim().is_empty() && !l.starts_with("List of devices") && !l.starts_with('*')
+        })
+        .filter_map(|l| RE.captures(l.trim()))
+        .map(|c| {
+            let mut device = AdbDevice {
+                serial: c[1].to_string(),
+                status: DeviceStatus::parse(&c[2]),
+                product: None,
+                model: None,
+                device: None,
+                transport_id: None,
+            };
+            for (key, value) in c[3].split_whitespace().filter_map(|a| a.split_once(':')) {
+                match key {
+                    "product" => device.product = Some(value.to_string()),
+                    // adb replaces the spaces of the model with underscores
+                    "model" => device.model = Some(value.replace('_', " ")),
+                    "device" => device.device = Some(value.to_string()),
+                    "transport_id" => device.transport_id = value.parse().ok(),
+                    _ => {}
+                }
+            }
+            device
+        })
+        .collect()
+}
+
 impl std::fmt::Display for Phone {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.model,)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+// `UserInfo` flags (android.content.pm.UserInfo)
+const FLAG_GUEST: u32 = 0x4;
+const FLAG_MANAGED_PROFILE: u32 = 0x20;
+const FLAG_PROFILE: u32 = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
 pub struct User {
     pub id: u16,
     pub index: usize,
+    pub name: String,
+    pub flags: u32,
+    /// User owning the profile (profiles only)
+    pub parent: Option<u16>,
+    pub running: bool,
+}
+
+impl User {
+    pub fn new(id: u16, index: usize) -> Self {
+        Self {
+            id,
+            index,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_work_profile(&self) -> bool {
+        self.flags & FLAG_MANAGED_PROFILE != 0
+    }
+
+    /// Work profiles and other profiles (e.g. clone apps) of a full user
+    pub fn is_profile(&self) -> bool {
+        self.flags & (FLAG_MANAGED_PROFILE | FLAG_PROFILE) != 0
+    }
+
+    pub fn is_guest(&self) -> bool {
+        self.flags & FLAG_GUEST != 0
+    }
 }
 
 impl std::fmt::Display for User {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "user {}", self.id)
+        write!(f, "user {}", self.id)?;
+        if !self.name.is_empty() {
+            write!(f, ": {}", self.name)?;
+        }
+        let mut tags = vec![];
+        if self.is_work_profile() {
+            tags.push("work profile".to_string());
+        } else if self.is_profile() {
+            tags.push("profile".to_string());
+        }
+        if self.is_guest() {
+            tags.push("guest".to_string());
+        }
+        if let Some(parent) = self.parent {
+            tags.push(format!("of user {}", parent));
+        }
+        if !self.running && !self.name.is_empty() {
+            tags.push("stopped".to_string());
+        }
+        if !tags.is_empty() {
+            write!(f, " ({})", tags.join(", "))?;
+        }
+        Ok(())
     }
 }
 
-pub fn adb_shell_command(shell: bool, args: &str) -> Result<String, String> {
-    let adb_command = match shell {
-        true => vec!["shell", args],
-        false => vec![args],
-    };
+/// Users affected by an action
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum UserTarget {
+    /// The selected user (every user with the multi-user mode)
+    #[default]
+    Selected,
+    WorkProfiles,
+    /// Users chosen one by one (IDs)
+    Users(Vec<u16>),
+}
+
+impl UserTarget {
+    pub const ALL: [UserTarget; 3] = [
+        UserTarget::Selected,
+        UserTarget::WorkProfiles,
+        UserTarget::Users(Vec::new()),
+    ];
+
+    /// `user` is the selected user
+    pub fn users(&self, user: &User, phone: &Phone, settings: &DeviceSettings) -> Vec<User> {
+        match self {
+            Self::Selected if settings.multi_user_mode => phone.user_list.clone(),
+            Self::Selected => vec![user.clone()],
+            Self::WorkProfiles => phone
+                .user_list
+                .iter()
+                .filter(|u| u.is_work_profile())
+                .cloned()
+                .collect(),
+            Self::Users(ids) => phone
+                .user_list
+                .iter()
+                .filter(|u| ids.contains(&u.id))
+                .cloned()
+                .collect(),
+        }
+    }
+}
 
-    #[cfg(target_os = "windows")]
-    let output = Command::new("adb")
-        .args(adb_command)
-        .creation_flags(0x08000000) // do not open a cmd window
-        .output();
+impl std::fmt::Display for UserTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Selected => "Selected user",
+            Self::WorkProfiles => "Work profiles",
+            Self::Users(_) => "Chosen users",
+        };
+        write!(f, "{}", s)
+    }
+}
 
-    #[cfg(target_os = "macos")]
-    let output = Command::new("adb").args(adb_command).output();
+/// Runs `adb -s <device_id> shell <args>`
+pub fn adb_shell_command(device_id: &str, args: &str) -> Result<String, AdbError> {
+    check_output(transport().shell(device_id, args))
+}
 
-    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-    let output = Command::new("adb").args(adb_command).output();
+/// Runs `adb <args>` (commands not bound to a device, e.g `devices`)
+pub fn adb_host_command(args: &str) -> Result<String, AdbError> {
+    check_output(transport().host(args))
+}
 
+fn check_output(output: Result<String, String>) -> Result<String, AdbError> {
     match output {
-        Err(e) => {
-            error!("ADB: {}", e);
-            Err("ADB was not found".to_string())
-        }
-        Ok(o) => {
-            if !o.status.success() {
-                let stdout = String::from_utf8(o.stdout).unwrap().trim_end().to_string();
-                let stderr = String::from_utf8(o.stderr).unwrap().trim_end().to_string();
-
-                // ADB does really weird things. Some errors are not redirected to stderr
-                let err = if stdout.is_empty() { stderr } else { stdout };
-                Err(err)
-            } else {
-                Ok(String::from_utf8(o.stdout).unwrap().trim_end().to_string())
-            }
-        }
+        Ok(o) => match AdbError::from_success_output(&o) {
+            Some(err) => Err(err),
+            None => Ok(o),
+        },
+        Err(err) => Err(AdbError::from_output(&err)),
     }
 }
 
-pub fn list_all_system_packages(user_id: Option<&User>) -> String {
+pub fn list_all_system_packages(device_id: &str, user_id: Option<&User>) -> String {
     let action = match user_id {
         Some(user_id) => format!("pm list packages -s -u --user {}", user_id.id),
         None => "pm list packages -s -u".to_string(),
     };
 
-    adb_shell_command(true, &action)
+    adb_shell_command(device_id, &action)
         .unwrap_or_else(|_| "".to_string())
         .replace("package:", "")
 }
 
-pub fn hashset_system_packages(state: PackageState, user_id: Option<&User>) -> HashSet<String> {
+pub fn hashset_system_packages(
+    device_id: &str,
+    state: PackageState,
+    user_id: Option<&User>,
+) -> HashSet<String> {
+    hashset_packages(device_id, "-s", state, user_id)
+}
+
+pub fn hashset_third_party_packages(
+    device_id: &str,
+    state: PackageState,
+    user_id: Option<&User>,
+) -> HashSet<String> {
+    hashset_packages(device_id, "-3", state, user_id)
+}
+
+// `kind` is `-s` (system packages) or `-3` (user-installed apps)
+fn hashset_packages(
+    device_id: &str,
+    kind: &str,
+    state: PackageState,
+    user_id: Option<&User>,
+) -> HashSet<String> {
     let user = match user_id {
         Some(user_id) => format!(" --user {}", user_id.id),
         None => "".to_string(),
     };
 
     let action = match state {
-        PackageState::Enabled => format!("pm list packages -s -e{}", user),
-        PackageState::Disabled => format!("pm list package -s -d{}", user),
+        PackageState::Enabled => format!("pm list packages {} -e{}", kind, user),
+        PackageState::Disabled => format!("pm list package {} -d{}", kind, user),
+        // Suspended packages are listed as enabled: `kind` can't be applied
+        PackageState::Suspended => return hashset_suspended_packages(device_id, user_id),
         _ => "".to_string(), // You probably don't need to use this function for anything else
     };
 
-    adb_shell_command(true, &action)
+    adb_shell_command(device_id, &action)
         .unwrap_or_default()
         .replace("package:", "")
         .lines()
@@ -120,10 +358,150 @@ pub fn hashset_system_packages(state: PackageState, user_id: Option<&User>) -> H
         .collect()
 }
 
+/// Packages suspended for the user (both system and user-installed ones). `pm` can't
+/// list them: they are read from `dumpsys`, e.g.
+/// `User 0: ceDataInode=123 installed=true hidden=false suspended=true ...`
+fn hashset_suspended_packages(device_id: &str, user_id: Option<&User>) -> HashSet<String> {
+    match adb_shell_command(
+        device_id,
+        "dumpsys package packages | grep -E 'Package \\[|suspended=true'",
+    ) {
+        Ok(output) => parse_suspended_packages(&output, user_id),
+        Err(_) => HashSet::new(),
+    }
+}
+
+fn parse_suspended_packages(output: &str, user_id: Option<&User>) -> HashSet<String> {
+    #[dynamic]
+    static RE: Regex = Regex::new(r"Package \[(.+)\]|User ([0-9]+):.* suspended=true").unwrap();
+    let user = user_id.map_or(0, |u| u.id);
+
+    let mut suspended = HashSet::new();
+    let mut package = None;
+    for c in RE.captures_iter(output) {
+        match (c.get(1), c.get(2)) {
+            (Some(name), _) => package = Some(name.as_str().to_string()),
+            (_, Some(id)) if id.as_str().parse() == Ok(user) => {
+                if let Some(p) = &package {
+                    suspended.insert(p.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    suspended
+}
+
+/// Current state of the package for the user, read from the device. Commands can report a
+/// success without changing anything (e.g. `pm uninstall` on old devices): the state
+/// after an action is checked with it.
+pub fn get_package_state(
+    device_id: &str,
+    package: &str,
+    user_id: Option<&User>,
+) -> Result<PackageState, AdbError> {
+    let user = match user_id {
+        Some(user_id) => format!(" --user {}", user_id.id),
+        None => "".to_string(),
+    };
+    // The package name is a filter: `com.foo` also lists `com.foo.bar`
+    let is_listed = |flag: &str| -> Result<bool, AdbError> {
+        let output = adb_shell_command(
+            device_id,
+            &format!("pm list packages {}{} {}", flag, user, package),
+        )?;
+        Ok(output
+            .lines()
+            .any(|l| l.trim().strip_prefix("package:") == Some(package)))
+    };
+
+    if is_listed("-e")? {
+        // Suspended packages are listed as enabled
+        let dump = adb_shell_command(device_id, &format!("dumpsys package {}", package))?;
+        match parse_suspended_packages(&dump, user_id).contains(package) {
+            true => Ok(PackageState::Suspended),
+            false => Ok(PackageState::Enabled),
+        }
+    } else if is_listed("-d")? {
+        Ok(PackageState::Disabled)
+    } else {
+        // Including a user-installed app removed for every user (it's not listed anymore)
+        Ok(PackageState::Uninstalled)
+    }
+}
+
+/// A package installed by the user (or pushed by the carrier/OEM after the first boot)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThirdPartyPackage {
+    pub name: String,
+    /// Package name of the installer (e.g. `com.android.vending` for the Play Store)
+    pub installer: Option<String>,
+}
+
+/// Third-party packages, including the ones uninstalled for the user but kept for another one
+pub fn list_third_party_packages(
+    device_id: &str,
+    user_id: Option<&User>,
+) -> Vec<ThirdPartyPackage> {
+    let action = match user_id {
+        Some(user_id) => format!("pm list packages -3 -u -i --user {}", user_id.id),
+        None => "pm list packages -3 -u -i".to_string(),
+    };
+
+    // e.g. `package:com.foo  installer=com.android.vending`
+    adb_shell_command(device_id, &action)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| {
+            let mut parts = l.trim().strip_prefix("package:")?.split_whitespace();
+            let name = parts.next()?.to_string();
+            let installer = parts
+                .find_map(|p| p.strip_prefix("installer="))
+                .filter(|i| *i != "null")
+                .map(String::from);
+            Some(ThirdPartyPackage { name, installer })
+        })
+        .collect()
+}
+
+/// First install date of every package (`pm` can't list it)
+pub fn get_install_dates(device_id: &str) -> HashMap<String, String> {
+    match adb_shell_command(
+        device_id,
+        "dumpsys package packages | grep -E 'Package \\[|firstInstallTime='",
+    ) {
+        Ok(output) => parse_install_dates(&output),
+        Err(_) => HashMap::new(),
+    }
+}
+
+pub fn parse_install_dates(output: &str) -> HashMap<String, String> {
+    #[dynamic]
+    static RE: Regex = Regex::new(r"Package \[(.+)\]|firstInstallTime=(.+)").unwrap();
+
+    let mut dates = HashMap::new();
+    let mut package = None;
+    for c in RE.captures_iter(output) {
+        match (c.get(1), c.get(2)) {
+            (Some(name), _) => package = Some(name.as_str().to_string()),
+            // The date is repeated for each user on recent Android versions
+            (_, Some(date)) => {
+                if let Some(p) = package.take() {
+                    dates.insert(p, date.as_str().trim().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    dates
+}
+
 // Minimum information for processing adb commands
 pub struct CorePackage {
     pub name: String,
     pub state: PackageState,
+    /// State after its removal, if not the one of the settings (see `PackageRow::target`)
+    pub target: Option<PackageState>,
 }
 
 impl From<&mut PackageRow> for CorePackage {
@@ -131,6 +509,7 @@ impl From<&mut PackageRow> for CorePackage {
         CorePackage {
             name: pr.name.clone(),
             state: pr.state,
+            target: pr.target,
         }
     }
 }
@@ -139,6 +518,7 @@ impl From<&PackageRow> for CorePackage {
         CorePackage {
             name: pr.name.clone(),
             state: pr.state,
+            target: pr.target,
         }
     }
 }
@@ -149,46 +529,74 @@ pub fn action_handler(
     phone: &Phone,
     settings: &DeviceSettings,
 ) -> Vec<String> {
+    package_action(user, package, phone, settings).0
+}
+
+/// Commands changing the state of the package and the state it will be in once they
+/// have succeeded
+pub fn package_action(
+    user: &User,
+    package: &CorePackage,
+    phone: &Phone,
+    settings: &DeviceSettings,
+) -> (Vec<String>, PackageState) {
     // https://github.com/0x192/universal-android-debloater/wiki/ADB-reference
     // ALWAYS PUT THE COMMAND THAT CHANGES THE PACKAGE STATE FIRST!
-    let commands = match package.state {
+    let settings = &settings.with_target(package.target);
+    let (commands, state) = match package.state {
         PackageState::Enabled => {
-            let commands = match settings.disable_mode {
-                true => vec!["pm disable-user", "am force-stop", "pm clear"],
-                false => vec!["pm uninstall"],
+            // Packages can't be suspended before Android 9: they are disabled instead
+            let commands = match settings.disable_mode || settings.suspend_mode {
+                true => (
+                    vec!["pm disable-user", "am force-stop", "pm clear"],
+                    PackageState::Disabled,
+                ),
+                false => (vec!["pm uninstall"], PackageState::Uninstalled),
             };
 
             match phone.android_sdk {
-                sdk if sdk >= 23 => commands,            // > Android Marshmallow (6.0)
-                21 | 22 => vec!["pm hide", "pm clear"],  // Android Lollipop (5.x)
-                19 | 20 => vec!["pm block", "pm clear"], // Android KitKat (4.4/4.4W)
-                _ => vec!["pm uninstall"], // Disable mode is unavailable on older devices because the specific ADB commands need root
+                sdk if sdk >= 28 && settings.suspend_mode => {
+                    (vec!["pm suspend"], PackageState::Suspended) // > Android Pie (9.0)
+                }
+                sdk if sdk >= 23 => commands, // > Android Marshmallow (6.0)
+                // Hidden and blocked packages are listed as uninstalled
+                21 | 22 => (vec!["pm hide", "pm clear"], PackageState::Uninstalled), // Android Lollipop (5.x)
+                19 | 20 => (vec!["pm block", "pm clear"], PackageState::Uninstalled), // Android KitKat (4.4/4.4W)
+                _ => (vec!["pm uninstall"], PackageState::Uninstalled), // Disable mode is unavailable on older devices because the specific ADB commands need root
             }
         }
         PackageState::Uninstalled => {
             match phone.android_sdk {
-                i if i >= 23 => vec!["cmd package install-existing"],
-                21 | 22 => vec!["pm unhide"],
-                19 | 20 => vec!["pm unblock", "pm clear"],
-                _ => vec![], // Impossible action already prevented by the GUI
+                i if i >= 23 => (vec!["cmd package install-existing"], PackageState::Enabled),
+                21 | 22 => (vec!["pm unhide"], PackageState::Enabled),
+                19 | 20 => (vec!["pm unblock", "pm clear"], PackageState::Enabled),
+                _ => (vec![], PackageState::Uninstalled), // Impossible action already prevented by the GUI
             }
         }
         // `pm enable` doesn't work without root before Android 6.x and this is most likely the same on even older devices too.
         // Should never happen as disable_mode is unavailable on older devices
         PackageState::Disabled => match phone.android_sdk {
-            i if i >= 23 => vec!["pm enable"],
-            _ => vec!["pm enable"],
+            i if i >= 23 => (vec!["pm enable"], PackageState::Enabled),
+            _ => (vec!["pm enable"], PackageState::Enabled),
         },
-        PackageState::All => vec![], // This can't happen (like... never)
+        PackageState::Suspended => (vec!["pm unsuspend"], PackageState::Enabled),
+        PackageState::All => (vec![], PackageState::All), // This can't happen (like... never)
     };
 
-    if settings.multi_user_mode {
+    let commands = if settings.multi_user_mode {
         request_builder(commands, &package.name, &phone.user_list)
     } else if phone.android_sdk < 21 {
         request_builder(commands, &package.name, &[])
     } else {
-        request_builder(commands, &package.name, &[*user])
-    }
+        request_builder(commands, &package.name, std::slice::from_ref(user))
+    };
+    (commands, state)
+}
+
+/// The command changes the state of the package (there is one per user in the commands of
+/// `action_handler`). `am force-stop` and `pm clear` only clean up after it.
+pub fn changes_state(command: &str) -> bool {
+    !command.starts_with("am force-stop") && !command.starts_with("pm clear")
 }
 
 pub fn request_builder(commands: Vec<&str>, package: &str, users: &[User]) -> Vec<String> {
@@ -209,73 +617,84 @@ pub fn request_builder(commands: Vec<&str>, package: &str, users: &[User]) -> Ve
     }
 }
 
-pub fn get_phone_model() -> String {
-    match adb_shell_command(true, "getprop ro.product.model") {
+pub fn get_phone_model(device_id: &str) -> String {
+    match adb_shell_command(device_id, "getprop ro.product.model") {
         Ok(model) => model,
         Err(err) => {
-            println!("ERROR: {}", err);
-            if err.contains("adb: no devices/emulators found") {
-                "no devices/emulators found".to_string()
-            } else {
-                err
+            error!("get_phone_model() -> {}", err);
+            match err {
+                AdbError::NoDevice => "no devices/emulators found".to_string(),
+                err => err.to_string(),
             }
         }
     }
 }
 
-pub fn get_android_sdk() -> u8 {
-    match adb_shell_command(true, "getprop ro.build.version.sdk") {
-        Ok(sdk) => sdk.parse().unwrap(),
+pub fn get_android_sdk(device_id: &str) -> u8 {
+    match adb_shell_command(device_id, "getprop ro.build.version.sdk") {
+        Ok(sdk) => sdk.parse().unwrap_or(0),
         Err(_) => 0,
     }
 }
 
-pub fn get_phone_brand() -> String {
+pub fn get_phone_brand(device_id: &str) -> String {
     format!(
         "{} {}",
-        adb_shell_command(true, "getprop ro.product.brand")
+        adb_shell_command(device_id, "getprop ro.product.brand")
             .unwrap_or_else(|_| "".to_string())
             .trim(),
-        get_phone_model()
+        get_phone_model(device_id)
     )
 }
 
-pub fn get_user_list() -> Vec<User> {
+/// Users and profiles of the device. Their parent comes from `dumpsys user` (which may
+/// not be allowed on some devices).
+pub fn get_user_list(device_id: &str) -> Vec<User> {
     #[dynamic]
-    static RE: Regex = Regex::new(r"\{([0-9]+)").unwrap();
-    match adb_shell_command(true, "pm list users") {
-        Ok(users) => RE
-            .find_iter(&users)
-            .enumerate()
-            .map(|(i, u)| User {
-                id: u.as_str()[1..].parse().unwrap(),
-                index: i,
-            })
-            .collect(),
-        Err(_) => vec![],
-    }
+    static RE: Regex =
+        Regex::new(r"UserInfo\{([0-9]+):([^\n]*):([0-9a-fA-F]+)\}( running)?").unwrap();
+    #[dynamic]
+    static PARENT_RE: Regex = Regex::new(r"UserInfo\{([0-9]+):[^\n]*parentId=([0-9]+)").unwrap();
+
+    let users = match adb_shell_command(device_id, "pm list users") {
+        Ok(users) => users,
+        Err(_) => return vec![],
+    };
+    let parents: Vec<(u16, u16)> = adb_shell_command(device_id, "dumpsys user")
+        .map(|dump| {
+            PARENT_RE
+                .captures_iter(&dump)
+                .filter_map(|c| Some((c[1].parse().ok()?, c[2].parse().ok()?)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    RE.captures_iter(&users)
+        .filter_map(|c| c[1].parse::<u16>().ok().map(|id| (id, c)))
+        .enumerate()
+        .map(|(index, (id, c))| User {
+            id,
+            index,
+            name: c[2].to_string(),
+            flags: u32::from_str_radix(&c[3], 16).unwrap_or(0),
+            parent: parents.iter().find(|(u, _)| *u == id).map(|(_, p)| *p),
+            running: c.get(4).is_some(),
+        })
+        .collect()
 }
 
-// getprop ro.serialno
+/// Every device known by ADB, whatever its state. Only the ready ones are queried for
+/// their properties. Waits (up to 60 seconds) for a device to be plugged in.
 pub async fn get_devices_list() -> Vec<Phone> {
-    match retry(
+    retry(
         Fixed::from_millis(500).take(120),
-        || match adb_shell_command(false, "devices") {
+        || match adb_host_command("devices -l") {
             Ok(devices) => {
-                let mut device_list: Vec<Phone> = vec![];
-                if !RE.is_match(&devices) {
+                let devices = parse_devices(&devices);
+                if devices.is_empty() {
                     return OperationResult::Retry(vec![]);
                 }
-                for device in RE.captures_iter(&devices) {
-                    env::set_var("ANDROID_SERIAL", &device[1]);
-                    device_list.push(Phone {
-                        model: get_phone_brand(),
-                        android_sdk: get_android_sdk(),
-                        user_list: get_user_list(),
-                        adb_id: device[1].to_string(),
-                    });
-                }
-                OperationResult::Ok(device_list)
+                OperationResult::Ok(devices.into_iter().map(probe_device).collect())
             }
             Err(err) => {
                 error!("get_device_list() -> {}", err);
@@ -283,8 +702,582 @@ pub async fn get_devices_list() -> Vec<Phone> {
                 OperationResult::Retry(test)
             }
         },
-    ) {
-        Ok(devices) => devices,
-        Err(_) => vec![],
+    )
+    .unwrap_or_default()
+}
+
+/// Blocks and calls `on_change` with the devices every time one of them is plugged,
+/// unplugged or changes state. If the connection to ADB is lost, it's retried every
+/// 2 seconds. Returns when `on_change` returns `false`.
+pub fn track_devices(mut on_change: impl FnMut(Vec<AdbDevice>) -> bool) {
+    loop {
+        match transport().track_devices() {
+            Ok(mut stream) => loop {
+                match read_frame(&mut stream) {
+                    Ok(devices) => {
+                        if !on_change(parse_devices(&devices)) {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Device tracking interrupted: {}", e);
+                        break;
+                    }
+                }
+            },
+            Err(e) => warn!("Could not track devices: {}", AdbError::from_output(&e)),
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+}
+
+/// Converts the devices reported by `track_devices`. `known` devices that are still
+/// ready are reused, the others are queried.
+pub async fn refresh_devices(known: Vec<Phone>, devices: Vec<AdbDevice>) -> Vec<Phone> {
+    devices
+        .into_iter()
+        .map(|device| {
+            match known
+                .iter()
+                .find(|p| p.adb_id == device.serial && p.is_ready() && p.status == device.status)
+            {
+                Some(phone) => phone.clone(),
+                None => probe_device(device),
+            }
+        })
+        .collect()
+}
+
+/// Where a device can be rebooted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootTarget {
+    System,
+    /// Only the system apps are started: a way out of a bootloop caused by a third-party app
+    /// or by a package removed by mistake (it can be restored from there)
+    SafeMode,
+    Recovery,
+    Bootloader,
+    /// Userspace fastboot (Android 10+ devices with dynamic partitions)
+    Fastboot,
+}
+
+impl RebootTarget {
+    pub const ALL: [RebootTarget; 5] = [
+        RebootTarget::System,
+        RebootTarget::SafeMode,
+        RebootTarget::Recovery,
+        RebootTarget::Bootloader,
+        RebootTarget::Fastboot,
+    ];
+
+    // Shell commands, run in order
+    fn commands(&self) -> Vec<&'static str> {
+        match self {
+            Self::System => vec!["reboot"],
+            // The property is reset by Android once in safe mode
+            Self::SafeMode => vec!["setprop persist.sys.safemode 1", "reboot"],
+            Self::Recovery => vec!["reboot recovery"],
+            Self::Bootloader => vec!["reboot bootloader"],
+            Self::Fastboot => vec!["reboot fastboot"],
+        }
+    }
+
+    /// The device is usable by UAD again after the reboot. The other targets need
+    /// the user to reboot the device to the system by hand.
+    pub fn comes_back(&self) -> bool {
+        matches!(self, Self::System | Self::SafeMode)
+    }
+}
+
+impl std::fmt::Display for RebootTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::System => "System",
+                Self::SafeMode => "Safe mode",
+                Self::Recovery => "Recovery",
+                Self::Bootloader => "Bootloader",
+                Self::Fastboot => "Fastboot (userspace)",
+            }
+        )
+    }
+}
+
+pub async fn reboot(device_id: String, target: RebootTarget) -> Result<(), AdbError> {
+    info!("Rebooting {} into {}", device_id, target);
+    for command in target.commands() {
+        match adb_shell_command(&device_id, command) {
+            // The device can close the connection before `reboot` returns
+            Err(e) if command.starts_with("reboot") => {
+                warn!("{}: {}", command, e);
+            }
+            Err(e) => {
+                error!("{}: {}", command, e);
+                return Err(e);
+            }
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn find_device(serial: &str) -> Option<AdbDevice> {
+    adb_host_command("devices -l").ok().and_then(|devices| {
+        parse_devices(&devices)
+            .into_iter()
+            .find(|d| d.serial == serial)
+    })
+}
+
+/// Waits for a device that has just been rebooted to go away and then, if it `comes_back`,
+/// to be ready again (up to 5 minutes: the first boot after an update can be long).
+pub async fn wait_for_reboot(serial: String, target: RebootTarget) -> Option<Phone> {
+    let _ = retry(Fixed::from_millis(500).take(60), || {
+        match find_device(&serial) {
+            Some(device) if device.status == DeviceStatus::Device => OperationResult::Retry(()),
+            _ => OperationResult::Ok(()),
+        }
+    });
+    if !target.comes_back() {
+        return None;
+    }
+    match retry(Fixed::from_millis(500).take(600), || {
+        match find_device(&serial) {
+            Some(device) if device.status == DeviceStatus::Device => OperationResult::Ok(device),
+            _ => OperationResult::Retry(()),
+        }
+    }) {
+        Ok(device) => Some(probe_device(device)),
+        Err(_) => {
+            error!("{} did not come back after the reboot", serial);
+            None
+        }
+    }
+}
+
+/// Queries the properties of a ready device
+pub fn probe_device(device: AdbDevice) -> Phone {
+    if device.status != DeviceStatus::Device {
+        warn!(
+            "Device {} is {}: {}",
+            device.serial,
+            device.status,
+            device.status.guidance()
+        );
+        return Phone {
+            model: device.model.unwrap_or_else(|| device.serial.clone()),
+            adb_id: device.serial,
+            status: device.status,
+            ..Phone::default()
+        };
+    }
+    Phone {
+        model: get_phone_brand(&device.serial),
+        android_sdk: get_android_sdk(&device.serial),
+        user_list: get_user_list(&device.serial),
+        adb_id: device.serial,
+        status: DeviceStatus::Device,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{install, install_all, phone, settings, MockDevice};
+    use iced::futures::executor::block_on;
+
+    fn package(state: PackageState) -> CorePackage {
+        CorePackage {
+            name: "com.foo".to_string(),
+            state,
+            target: None,
+        }
+    }
+
+    #[test]
+    fn test_action_handler_per_sdk() {
+        let user = User::new(0, 0);
+        let cases = [
+            (
+                30,
+                PackageState::Enabled,
+                vec!["pm uninstall --user 0 com.foo"],
+            ),
+            (
+                30,
+                PackageState::Uninstalled,
+                vec!["cmd package install-existing --user 0 com.foo"],
+            ),
+            (
+                30,
+                PackageState::Disabled,
+                vec!["pm enable --user 0 com.foo"],
+            ),
+            (
+                22,
+                PackageState::Enabled,
+                vec!["pm hide --user 0 com.foo", "pm clear --user 0 com.foo"],
+            ),
+            (
+                21,
+                PackageState::Uninstalled,
+                vec!["pm unhide --user 0 com.foo"],
+            ),
+            (
+                19,
+                PackageState::Enabled,
+                vec!["pm block com.foo", "pm clear com.foo"],
+            ),
+            (
+                19,
+                PackageState::Uninstalled,
+                vec!["pm unblock com.foo", "pm clear com.foo"],
+            ),
+            (17, PackageState::Enabled, vec!["pm uninstall com.foo"]),
+        ];
+        for (sdk, state, expected) in cases {
+            assert_eq!(
+                action_handler(
+                    &user,
+                    &package(state),
+                    &phone(sdk, &[0]),
+                    &settings(false, false)
+                ),
+                expected,
+                "SDK {} / {}",
+                sdk,
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn test_action_handler_disable_mode() {
+        let user = User::new(0, 0);
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Enabled),
+            &phone(30, &[0]),
+            &settings(true, false),
+        );
+        assert_eq!(commands[0], "pm disable-user --user 0 com.foo");
+        assert!(commands.contains(&"am force-stop --user 0 com.foo".to_string()));
+
+        // Disable mode doesn't exist before Android 6.0
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Enabled),
+            &phone(22, &[0]),
+            &settings(true, false),
+        );
+        assert_eq!(commands[0], "pm hide --user 0 com.foo");
+    }
+
+    #[test]
+    fn test_action_handler_suspend_mode() {
+        let user = User::new(0, 0);
+        let suspend = DeviceSettings {
+            suspend_mode: true,
+            ..settings(true, false)
+        };
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Enabled),
+            &phone(28, &[0]),
+            &suspend,
+        );
+        assert_eq!(commands, vec!["pm suspend --user 0 com.foo"]);
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Suspended),
+            &phone(28, &[0]),
+            &suspend,
+        );
+        assert_eq!(commands, vec!["pm unsuspend --user 0 com.foo"]);
+
+        // `pm suspend` doesn't exist before Android 9: the disable mode is used
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Enabled),
+            &phone(27, &[0]),
+            &suspend,
+        );
+        assert_eq!(commands[0], "pm disable-user --user 0 com.foo");
+    }
+
+    #[test]
+    fn test_action_handler_target() {
+        let user = User::new(0, 0);
+        let package = |target| CorePackage {
+            target: Some(target),
+            ..package(PackageState::Enabled)
+        };
+        // The target of the package has priority over the mode of the device
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Disabled),
+            &phone(30, &[0]),
+            &settings(false, false),
+        );
+        assert_eq!(commands[0], "pm disable-user --user 0 com.foo");
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Uninstalled),
+            &phone(30, &[0]),
+            &settings(true, false),
+        );
+        assert_eq!(commands, vec!["pm uninstall --user 0 com.foo"]);
+
+        // A package to suspend is disabled (not uninstalled) before Android 9
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Suspended),
+            &phone(26, &[0]),
+            &settings(false, false),
+        );
+        assert_eq!(commands[0], "pm disable-user --user 0 com.foo");
+    }
+
+    #[test]
+    fn test_hashset_suspended_packages() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_user(10, "Alice")
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.bar", PackageState::Enabled),
+        );
+        device.set_state("com.foo", 10, PackageState::Suspended);
+        let user = User::new(10, 1);
+
+        let suspended =
+            hashset_system_packages("mock-device", PackageState::Suspended, Some(&user));
+        assert_eq!(suspended, HashSet::from(["com.foo".to_string()]));
+        assert!(
+            hashset_system_packages("mock-device", PackageState::Enabled, Some(&user))
+                .contains("com.foo")
+        );
+        assert!(hashset_system_packages("mock-device", PackageState::Suspended, None).is_empty());
+    }
+
+    #[test]
+    fn test_get_package_state() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_user(10, "Alice")
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.foo.bar", PackageState::Disabled),
+        );
+        device.set_state("com.foo", 10, PackageState::Suspended);
+        let state = |package, user: Option<&User>| get_package_state("mock-device", package, user);
+        let alice = User::new(10, 1);
+
+        assert_eq!(state("com.foo", None), Ok(PackageState::Enabled));
+        assert_eq!(state("com.foo", Some(&alice)), Ok(PackageState::Suspended));
+        assert_eq!(state("com.foo.bar", None), Ok(PackageState::Disabled));
+        assert_eq!(state("com.unknown", None), Ok(PackageState::Uninstalled));
+
+        device.set_state("com.foo.bar", 0, PackageState::Uninstalled);
+        assert_eq!(state("com.foo.bar", None), Ok(PackageState::Uninstalled));
+        assert_eq!(
+            state("com.foo", Some(&User::new(11, 2))),
+            Err(AdbError::Unknown("Error: user 11 not found".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_action_handler_multi_user() {
+        let phone = phone(30, &[0, 10]);
+        let user = phone.user_list[1].clone();
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Enabled),
+            &phone,
+            &settings(false, true),
+        );
+        assert_eq!(
+            commands,
+            vec![
+                "pm uninstall --user 0 com.foo",
+                "pm uninstall --user 10 com.foo"
+            ]
+        );
+
+        let commands = action_handler(
+            &user,
+            &package(PackageState::Enabled),
+            &phone,
+            &settings(false, false),
+        );
+        assert_eq!(commands, vec!["pm uninstall --user 10 com.foo"]);
+    }
+
+    #[test]
+    fn test_get_devices_list() {
+        let (_device, _guard) = install(MockDevice::new(30).with_work_profile(10, "Work profile"));
+        let devices = block_on(get_devices_list());
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].adb_id, "mock-device");
+        assert_eq!(devices[0].model, "google Pixel");
+        assert_eq!(devices[0].android_sdk, 30);
+        let ids: Vec<u16> = devices[0].user_list.iter().map(|u| u.id).collect();
+        assert_eq!(ids, vec![0, 10]);
+        assert!(devices[0].is_ready());
+    }
+
+    #[test]
+    fn test_get_user_list() {
+        let (_device, _guard) = install(
+            MockDevice::new(30)
+                .with_work_profile(10, "Work profile")
+                .with_user(11, "Alice: kids"),
+        );
+        let users = get_user_list("mock-device");
+
+        assert_eq!(users.len(), 3);
+        assert_eq!(users[0].name, "Owner");
+        assert!(!users[0].is_profile());
+        assert_eq!(users[0].parent, None);
+        assert!(users[1].is_work_profile());
+        assert_eq!(users[1].parent, Some(0));
+        assert!(users[1].running);
+        assert_eq!(users[2].name, "Alice: kids");
+        assert_eq!(users[2].index, 2);
+        assert_eq!(
+            users[1].to_string(),
+            "user 10: Work profile (work profile, of user 0)"
+        );
+    }
+
+    #[test]
+    fn test_user_target() {
+        let mut phone = phone(30, &[0, 10, 11]);
+        phone.user_list[1].flags = FLAG_MANAGED_PROFILE | FLAG_PROFILE;
+        let user = phone.user_list[0].clone();
+        let ids = |target: UserTarget, multi_user_mode| -> Vec<u16> {
+            target
+                .users(&user, &phone, &settings(false, multi_user_mode))
+                .iter()
+                .map(|u| u.id)
+                .collect()
+        };
+
+        assert_eq!(ids(UserTarget::Selected, false), vec![0]);
+        assert_eq!(ids(UserTarget::Selected, true), vec![0, 10, 11]);
+        assert_eq!(ids(UserTarget::WorkProfiles, false), vec![10]);
+        assert_eq!(ids(UserTarget::Users(vec![11, 12]), false), vec![11]);
+    }
+
+    #[test]
+    fn test_devices_not_ready() {
+        let (_devices, _guard) = install_all(vec![
+            MockDevice::new(30).with_serial("ready"),
+            MockDevice::new(30)
+                .with_serial("locked")
+                .with_status("unauthorized"),
+        ]);
+        let devices = block_on(get_devices_list());
+
+        assert_eq!(devices.len(), 2);
+        assert!(devices[0].is_ready());
+        assert_eq!(devices[1].status, DeviceStatus::Unauthorized);
+        assert_eq!(devices[1].model, "Pixel");
+        assert!(devices[1].user_list.is_empty());
+    }
+
+    #[test]
+    fn test_track_devices() {
+        let (devices, _guard) = install_all(vec![
+            MockDevice::new(30).with_serial("ready"),
+            MockDevice::new(29)
+                .with_serial("locked")
+                .with_status("unauthorized"),
+        ]);
+        let mut updates = vec![];
+        track_devices(|devices| {
+            updates.push(devices);
+            false
+        });
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0][1].serial, "locked");
+        assert_eq!(updates[0][1].status, DeviceStatus::Unauthorized);
+
+        // The phone has been authorized: it's queried, the other one is reused
+        let known = block_on(refresh_devices(vec![], updates[0].clone()));
+        assert_eq!(known[0].android_sdk, 30);
+        assert_eq!(known[1].android_sdk, 0);
+        let before = devices[0].history().len();
+        let mut update = updates[0].clone();
+        update[1].status = DeviceStatus::Device;
+        let phones = block_on(refresh_devices(known, update));
+        assert_eq!(devices[0].history().len(), before);
+        assert_eq!(phones[1].status, DeviceStatus::Device);
+        assert!(phones.iter().all(Phone::is_ready));
+    }
+
+    #[test]
+    fn test_reboot() {
+        let (device, _guard) = install(MockDevice::new(30));
+        block_on(reboot("mock-device".to_string(), RebootTarget::SafeMode)).unwrap();
+        assert_eq!(
+            device.history(),
+            vec!["setprop persist.sys.safemode 1", "reboot"]
+        );
+        let phone = block_on(wait_for_reboot(
+            "mock-device".to_string(),
+            RebootTarget::SafeMode,
+        ))
+        .unwrap();
+        assert!(phone.is_ready());
+        assert_eq!(phone.android_sdk, 30);
+
+        block_on(reboot("mock-device".to_string(), RebootTarget::Recovery)).unwrap();
+        assert!(block_on(wait_for_reboot(
+            "mock-device".to_string(),
+            RebootTarget::Recovery
+        ))
+        .is_none());
+        assert_eq!(
+            find_device("mock-device").unwrap().status,
+            DeviceStatus::Recovery
+        );
+    }
+
+    #[test]
+    fn test_parse_devices() {
+        let output = "\
+* daemon not running; starting now at tcp:5037
+* daemon started successfully
+List of devices attached
+emulator-5554          device product:sdk_gphone64_x86_64 model:sdk_gphone64_x86_64 device:emu64xa transport_id:1
+R58M12ABCDE            unauthorized usb:1-1 transport_id:2
+0123456789ABCDEF       no permissions (user in plugdev group; are your udev rules wrong?); see [http://developer.android.com/tools/device.html] usb:3-4 transport_id:3
+192.168.1.5:5555       offline transport_id:4
+ZY2233                 recovery product:lake model:moto_g7_plus device:lake transport_id:5
+";
+        let devices = parse_devices(output);
+        assert_eq!(devices.len(), 5);
+        assert_eq!(
+            devices[0],
+            AdbDevice {
+                serial: "emulator-5554".to_string(),
+                status: DeviceStatus::Device,
+                product: Some("sdk_gphone64_x86_64".to_string()),
+                model: Some("sdk gphone64 x86 64".to_string()),
+                device: Some("emu64xa".to_string()),
+                transport_id: Some(1),
+            }
+        );
+        assert_eq!(devices[1].status, DeviceStatus::Unauthorized);
+        assert_eq!(devices[1].transport_id, Some(2));
+        assert_eq!(devices[2].status, DeviceStatus::NoPermissions);
+        assert_eq!(devices[2].transport_id, Some(3));
+        assert_eq!(devices[3].serial, "192.168.1.5:5555");
+        assert_eq!(devices[3].status, DeviceStatus::Offline);
+        assert_eq!(devices[4].status, DeviceStatus::Recovery);
+        assert_eq!(devices[4].model, Some("moto g7 plus".to_string()));
+        assert!(parse_devices("List of devices attached\n").is_empty());
     }
 }
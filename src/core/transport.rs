@@ -0,0 +1,485 @@
+use static_init::dynamic;
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, RwLock};
+
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+
+/// How UAD talks to ADB. Every device interaction goes through the current transport.
+pub trait Transport: Send + Sync {
+    /// Equivalent of `adb <command>` (e.g `devices`)
+    fn host(&self, command: &str) -> Result<String, String>;
+
+    /// Equivalent of `adb -s <serial> shell <command>`
+    fn shell(&self, serial: &str, command: &str) -> Result<String, String>;
+
+    /// Equivalent of `adb track-devices`: the list of devices (same format as `adb devices`)
+    /// is sent again every time it changes. Use `read_frame` to read each list.
+    fn track_devices(&self) -> Result<Box<dyn Read + Send>, String>;
+
+    /// Equivalent of `adb -s <serial> pull <remote> <local>`
+    fn pull(&self, serial: &str, remote: &str, local: &Path) -> Result<(), String>;
+}
+
+/// Reads a message prefixed by its length (4 hex digits)
+pub fn read_frame(reader: &mut dyn Read) -> Result<String, String> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len).map_err(|e| e.to_string())?;
+    let len =
+        usize::from_str_radix(&String::from_utf8_lossy(&len), 16).map_err(|e| e.to_string())?;
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&payload).to_string())
+}
+
+#[dynamic]
+static TRANSPORT: RwLock<Arc<dyn Transport>> = RwLock::new(Arc::new(AdbBinary));
+
+pub fn transport() -> Arc<dyn Transport> {
+    TRANSPORT.read().unwrap().clone()
+}
+
+pub fn set_transport(transport: Arc<dyn Transport>) {
+    *TRANSPORT.write().unwrap() = transport;
+}
+
+pub fn use_native_transport(native: bool) {
+    if native {
+        set_transport(Arc::new(AdbServer::default()));
+    } else {
+        set_transport(Arc::new(AdbBinary));
+    }
+}
+
+/// Shells out to the `adb` binary found in the PATH
+pub struct AdbBinary;
+
+impl AdbBinary {
+    fn run(args: &[&str]) -> Result<String, String> {
+        #[cfg(target_os = "windows")]
+        let output = Command::new("adb")
+            .args(args)
+            .creation_flags(0x08000000) // do not open a cmd window
+            .output();
+
+        #[cfg(target_os = "macos")]
+        let output = Command::new("adb").args(args).output();
+
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        let output = Command::new("adb").args(args).output();
+
+        match output {
+            Err(e) => {
+                error!("ADB: {}", e);
+                Err("ADB was not found".to_string())
+            }
+            Ok(o) => {
+                if !o.status.success() {
+                    let stdout = String::from_utf8(o.stdout).unwrap().trim_end().to_string();
+                    let stderr = String::from_utf8(o.stderr).unwrap().trim_end().to_string();
+
+                    // ADB does really weird things. Some errors are not redirected to stderr
+                    let err = if stdout.is_empty() { stderr } else { stdout };
+                    Err(err)
+                } else {
+                    Ok(String::from_utf8(o.stdout).unwrap().trim_end().to_string())
+                }
+            }
+        }
+    }
+}
+
+impl Transport for AdbBinary {
+    fn host(&self, command: &str) -> Result<String, String> {
+        Self::run(&command.split_whitespace().collect::<Vec<&str>>())
+    }
+
+    fn shell(&self, serial: &str, command: &str) -> Result<String, String> {
+        Self::run(&["-s", serial, "shell", command])
+    }
+
+    // `adb track-devices` never exits: its output is read while it runs
+    fn track_devices(&self) -> Result<Box<dyn Read + Send>, String> {
+        let mut command = Command::new("adb");
+        command.arg("track-devices").stdout(Stdio::piped());
+
+        #[cfg(target_os = "windows")]
+        command.creation_flags(0x08000000); // do not open a cmd window
+
+        match command.spawn() {
+            Ok(mut child) => match child.stdout.take() {
+                Some(stdout) => Ok(Box::new(TrackDevices { child, stdout })),
+                None => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    Err("Could not read the output of `adb track-devices`".to_string())
+                }
+            },
+            Err(e) => {
+                error!("ADB: {}", e);
+                Err("ADB was not found".to_string())
+            }
+        }
+    }
+
+    fn pull(&self, serial: &str, remote: &str, local: &Path) -> Result<(), String> {
+        Self::run(&["-s", serial, "pull", remote, &local.to_string_lossy()]).map(|_| ())
+    }
+}
+
+/// Output of a running `adb track-devices`. The process is stopped when it's dropped.
+struct TrackDevices {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for TrackDevices {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for TrackDevices {
+    fn drop(&mut self) {
+        // `wait` reaps the process (no zombie left behind)
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Speaks the ADB server protocol directly (no `adb` binary needed).
+/// See https://android.googlesource.com/platform/packages/modules/adb/+/refs/heads/master/OVERVIEW.TXT
+pub struct AdbServer {
+    pub address: String,
+}
+
+impl Default for AdbServer {
+    fn default() -> Self {
+        let port = env::var("ANDROID_ADB_SERVER_PORT").unwrap_or_else(|_| "5037".to_string());
+        Self::new(format!("127.0.0.1:{}", port))
+    }
+}
+
+impl AdbServer {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, String> {
+        TcpStream::connect(&self.address).map_err(|e| {
+            error!("ADB server ({}): {}", self.address, e);
+            format!(
+                "Could not connect to the ADB server on {} (try `adb start-server`)",
+                self.address
+            )
+        })
+    }
+
+    fn send(stream: &mut TcpStream, request: &str) -> Result<(), String> {
+        stream
+            .write_all(format!("{:04x}{}", request.len(), request).as_bytes())
+            .map_err(|e| e.to_string())
+    }
+
+    // Every request is answered by `OKAY` or by `FAIL` followed by an error message
+    fn read_status(stream: &mut TcpStream) -> Result<(), String> {
+        let mut status = [0; 4];
+        stream.read_exact(&mut status).map_err(|e| e.to_string())?;
+        match &status {
+            b"OKAY" => Ok(()),
+            b"FAIL" => Err(format!("adb: {}", read_frame(stream)?)),
+            _ => Err(format!(
+                "Unexpected ADB server response: {}",
+                String::from_utf8_lossy(&status)
+            )),
+        }
+    }
+
+    fn open_device(&self, serial: &str) -> Result<TcpStream, String> {
+        let mut stream = self.connect()?;
+        Self::send(&mut stream, &format!("host:transport:{}", serial))?;
+        Self::read_status(&mut stream)?;
+        Ok(stream)
+    }
+
+    // Devices older than Android 7.0 don't support the shell v2 protocol.
+    // The exit code is not available with this one.
+    fn legacy_shell(&self, serial: &str, command: &str) -> Result<String, String> {
+        let mut stream = self.open_device(serial)?;
+        Self::send(&mut stream, &format!("shell:{}", command))?;
+        Self::read_status(&mut stream)?;
+        let mut output = vec![];
+        stream.read_to_end(&mut output).map_err(|e| e.to_string())?;
+        Ok(String::from_utf8_lossy(&output).trim_end().to_string())
+    }
+}
+
+impl Transport for AdbServer {
+    fn host(&self, command: &str) -> Result<String, String> {
+        let mut stream = self.connect()?;
+        let service = match command {
+            "devices" => "host:devices".to_string(),
+            "devices -l" => "host:devices-l".to_string(),
+            c if c.starts_with("pair ") => match c.split_whitespace().collect::<Vec<&str>>()[..] {
+                [_, address, code] => format!("host:pair:{}:{}", code, address),
+                _ => return Err(format!("Invalid command: {}", c)),
+            },
+            c => format!(
+                "host:{}",
+                c.split_whitespace().collect::<Vec<&str>>().join(":")
+            ),
+        };
+        Self::send(&mut stream, &service)?;
+        Self::read_status(&mut stream)?;
+        let payload = read_frame(&mut stream)?;
+
+        // Mimic the output of the `adb` binary
+        match command {
+            "devices" | "devices -l" => Ok(format!("List of devices attached\n{}", payload)
+                .trim_end()
+                .to_string()),
+            _ => Ok(payload.trim_end().to_string()),
+        }
+    }
+
+    fn shell(&self, serial: &str, command: &str) -> Result<String, String> {
+        let mut stream = self.open_device(serial)?;
+        Self::send(&mut stream, &format!("shell,v2,raw:{}", command))?;
+        if Self::read_status(&mut stream).is_err() {
+            return self.legacy_shell(serial, command);
+        }
+
+        // Shell v2 packets: [id: u8][length: u32 LE][payload]
+        let (mut stdout, mut stderr, mut exit_code) = (vec![], vec![], None);
+        let mut header = [0; 5];
+        while stream.read_exact(&mut header).is_ok() {
+            let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            let mut payload = vec![0; len];
+            stream.read_exact(&mut payload).map_err(|e| e.to_string())?;
+            match header[0] {
+                1 => stdout.extend(payload),
+                2 => stderr.extend(payload),
+                3 => {
+                    exit_code = payload.first().copied();
+                    break;
+                }
+                _ => {}
+            }
+        }
+        // The output may be incomplete: the command can't be considered successful
+        let exit_code = exit_code.ok_or_else(|| {
+            error!(
+                "ADB server ({}): `{}` was interrupted",
+                self.address, command
+            );
+            "Failed: the connection was closed before the end of the command".to_string()
+        })?;
+
+        let stdout = String::from_utf8_lossy(&stdout).trim_end().to_string();
+        if exit_code != 0 {
+            let stderr = String::from_utf8_lossy(&stderr).trim_end().to_string();
+            // Same as the `adb` binary: some errors are not redirected to stderr
+            Err(if stdout.is_empty() { stderr } else { stdout })
+        } else {
+            Ok(stdout)
+        }
+    }
+
+    fn track_devices(&self) -> Result<Box<dyn Read + Send>, String> {
+        let mut stream = self.connect()?;
+        Self::send(&mut stream, "host:track-devices")?;
+        Self::read_status(&mut stream)?;
+        Ok(Box::new(stream))
+    }
+
+    // Sync protocol: `RECV` is answered by `DATA` chunks then `DONE`, or by `FAIL`.
+    // Each message is [id: 4 bytes][length: u32 LE][payload].
+    fn pull(&self, serial: &str, remote: &str, local: &Path) -> Result<(), String> {
+        let mut stream = self.open_device(serial)?;
+        Self::send(&mut stream, "sync:")?;
+        Self::read_status(&mut stream)?;
+
+        let mut request = b"RECV".to_vec();
+        request.extend((remote.len() as u32).to_le_bytes());
+        request.extend(remote.as_bytes());
+        stream.write_all(&request).map_err(|e| e.to_string())?;
+
+        let mut file = fs::File::create(local).map_err(|e| e.to_string())?;
+        let mut header = [0; 8];
+        loop {
+            stream.read_exact(&mut header).map_err(|e| e.to_string())?;
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            match &header[..4] {
+                b"DATA" => {
+                    let mut chunk = vec![0; len];
+                    stream.read_exact(&mut chunk).map_err(|e| e.to_string())?;
+                    file.write_all(&chunk).map_err(|e| e.to_string())?;
+                }
+                b"DONE" => break,
+                b"FAIL" => {
+                    let mut message = vec![0; len];
+                    stream.read_exact(&mut message).map_err(|e| e.to_string())?;
+                    drop(file);
+                    let _ = fs::remove_file(local);
+                    return Err(format!("adb: {}", String::from_utf8_lossy(&message)));
+                }
+                id => {
+                    return Err(format!(
+                        "Unexpected ADB sync response: {}",
+                        String::from_utf8_lossy(id)
+                    ))
+                }
+            }
+        }
+        let _ = stream.write_all(b"QUIT\0\0\0\0");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::AdbError;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut request =
+            vec![0; usize::from_str_radix(std::str::from_utf8(&len).unwrap(), 16).unwrap()];
+        stream.read_exact(&mut request).unwrap();
+        String::from_utf8(request).unwrap()
+    }
+
+    fn shell_packet(id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![id];
+        packet.extend((payload.len() as u32).to_le_bytes());
+        packet.extend(payload);
+        packet
+    }
+
+    // Stand-in ADB server answering a single connection
+    fn fake_server(handler: fn(TcpStream)) -> AdbServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || handler(listener.accept().unwrap().0));
+        AdbServer::new(address)
+    }
+
+    #[test]
+    fn test_host_devices() {
+        let server = fake_server(|mut stream| {
+            assert_eq!(read_request(&mut stream), "host:devices");
+            stream
+                .write_all(b"OKAY0015emulator-5554\tdevice\n")
+                .unwrap();
+        });
+        assert_eq!(
+            server.host("devices").unwrap(),
+            "List of devices attached\nemulator-5554\tdevice"
+        );
+    }
+
+    #[test]
+    fn test_shell_v2() {
+        let server = fake_server(|mut stream| {
+            assert_eq!(read_request(&mut stream), "host:transport:emulator-5554");
+            stream.write_all(b"OKAY").unwrap();
+            assert_eq!(read_request(&mut stream), "shell,v2,raw:pm path foo");
+            stream.write_all(b"OKAY").unwrap();
+            stream.write_all(&shell_packet(2, b"not found\n")).unwrap();
+            stream.write_all(&shell_packet(3, &[1])).unwrap();
+        });
+        assert_eq!(
+            server.shell("emulator-5554", "pm path foo"),
+            Err("not found".to_string())
+        );
+    }
+
+    #[test]
+    fn test_shell_v2_truncated() {
+        let server = fake_server(|mut stream| {
+            read_request(&mut stream);
+            stream.write_all(b"OKAY").unwrap();
+            read_request(&mut stream);
+            stream.write_all(b"OKAY").unwrap();
+            // Closed before the exit code
+            stream
+                .write_all(&shell_packet(1, b"package:com.foo\n"))
+                .unwrap();
+        });
+        let err = server
+            .shell("emulator-5554", "pm list packages")
+            .unwrap_err();
+        assert!(matches!(
+            AdbError::from_output(&err),
+            AdbError::ConnectionFailed(_)
+        ));
+    }
+
+    #[test]
+    fn test_track_devices() {
+        let server = fake_server(|mut stream| {
+            assert_eq!(read_request(&mut stream), "host:track-devices");
+            stream.write_all(b"OKAY0000").unwrap();
+            stream
+                .write_all(b"001bemulator-5554\tunauthorized\n")
+                .unwrap();
+        });
+        let mut stream = server.track_devices().unwrap();
+        assert_eq!(read_frame(&mut stream).unwrap(), "");
+        assert_eq!(
+            read_frame(&mut stream).unwrap(),
+            "emulator-5554\tunauthorized\n"
+        );
+        assert!(read_frame(&mut stream).is_err());
+    }
+
+    #[test]
+    fn test_server_failure() {
+        let server = fake_server(|mut stream| {
+            read_request(&mut stream);
+            stream
+                .write_all(b"FAIL001ano devices/emulators found")
+                .unwrap();
+        });
+        assert_eq!(
+            server.shell("emulator-5554", "getprop"),
+            Err("adb: no devices/emulators found".to_string())
+        );
+    }
+
+    #[test]
+    fn test_pull() {
+        let server = fake_server(|mut stream| {
+            assert_eq!(read_request(&mut stream), "host:transport:emulator-5554");
+            stream.write_all(b"OKAY").unwrap();
+            assert_eq!(read_request(&mut stream), "sync:");
+            stream.write_all(b"OKAY").unwrap();
+            let mut request = [0; 21];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"RECV\x0d\0\0\0/system/a.apk");
+            for chunk in [
+                &b"DATA\x03\0\0\0abc"[..],
+                b"DATA\x02\0\0\0de",
+                b"DONE\0\0\0\0",
+            ] {
+                stream.write_all(chunk).unwrap();
+            }
+        });
+        let local = env::temp_dir().join("uad_test_pull.apk");
+        server
+            .pull("emulator-5554", "/system/a.apk", &local)
+            .unwrap();
+        assert_eq!(fs::read(&local).unwrap(), b"abcde");
+        let _ = fs::remove_file(local);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::core::utils::{format_diff_time_from_now, last_modified_date};
 use crate::CACHE_DIR;
 use retry::{delay::Fixed, retry, OperationResult};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json;
 use std::collections::HashMap;
 use std::fs;
@@ -10,17 +10,18 @@ use std::path::{Path, PathBuf};
 #[derive(Deserialize, Debug, Clone, PartialEq, Hash, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Package {
-    id: String,
+    pub id: String,
     pub list: UadList,
     pub description: String,
-    dependencies: Vec<String>,
-    needed_by: Vec<String>,
+    pub dependencies: Vec<String>,
+    pub needed_by: Vec<String>,
     labels: Vec<String>,
     pub removal: Removal,
 }
 
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum UadList {
+    #[default]
     All,
     Aosp,
     Carrier,
@@ -29,21 +30,18 @@ pub enum UadList {
     Oem,
     Pending,
     Unlisted,
+    /// User-installed apps (`pm list packages -3`), whether they are in a list or not
+    ThirdParty,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum UadListState {
+    #[default]
     Downloading,
     Done,
     Failed,
 }
 
-impl Default for UadListState {
-    fn default() -> Self {
-        UadListState::Downloading
-    }
-}
-
 impl std::fmt::Display for UadListState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let date = last_modified_date(CACHE_DIR.join("uad_lists.json"));
@@ -56,14 +54,8 @@ impl std::fmt::Display for UadListState {
     }
 }
 
-impl Default for UadList {
-    fn default() -> UadList {
-        UadList::All
-    }
-}
-
 impl UadList {
-    pub const ALL: [UadList; 8] = [
+    pub const ALL: [UadList; 9] = [
         UadList::All,
         UadList::Aosp,
         UadList::Carrier,
@@ -72,6 +64,7 @@ impl UadList {
         UadList::Oem,
         UadList::Pending,
         UadList::Unlisted,
+        UadList::ThirdParty,
     ];
 }
 
@@ -89,31 +82,30 @@ impl std::fmt::Display for UadList {
                 UadList::Oem => "oem",
                 UadList::Pending => "pending",
                 UadList::Unlisted => "unlisted",
+                UadList::ThirdParty => "third-party",
             }
         )
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum PackageState {
     All,
+    #[default]
     Enabled,
     Uninstalled,
     Disabled,
-}
-
-impl Default for PackageState {
-    fn default() -> PackageState {
-        PackageState::Enabled
-    }
+    /// Still installed but can't run (`pm suspend`, Android 9+). Its data is kept.
+    Suspended,
 }
 
 impl PackageState {
-    pub const ALL: [PackageState; 4] = [
+    pub const ALL: [PackageState; 5] = [
         PackageState::All,
         PackageState::Enabled,
         PackageState::Uninstalled,
         PackageState::Disabled,
+        PackageState::Suspended,
     ];
 }
 
@@ -127,35 +119,17 @@ impl std::fmt::Display for PackageState {
                 PackageState::Enabled => "Enabled",
                 PackageState::Uninstalled => "Uninstalled",
                 PackageState::Disabled => "Disabled",
+                PackageState::Suspended => "Suspended",
             }
         )
     }
 }
 
-pub trait Opposite {
-    fn opposite(&self, disable: bool) -> PackageState;
-}
-
-impl Opposite for PackageState {
-    fn opposite(&self, disable: bool) -> PackageState {
-        match self {
-            PackageState::Enabled => {
-                if disable {
-                    PackageState::Disabled
-                } else {
-                    PackageState::Uninstalled
-                }
-            }
-            PackageState::Uninstalled | PackageState::Disabled => PackageState::Enabled,
-            PackageState::All => PackageState::All,
-        }
-    }
-}
-
 // Bad names. To be changed!
-#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum Removal {
     All,
+    #[default]
     Recommended,
     Advanced,
     Expert,
@@ -163,12 +137,6 @@ pub enum Removal {
     Unlisted,
 }
 
-impl Default for Removal {
-    fn default() -> Removal {
-        Removal::Recommended
-    }
-}
-
 impl Removal {
     pub const ALL: [Removal; 6] = [
         Removal::All,
@@ -29,20 +29,15 @@ pub struct SelfUpdateState {
     pub status: SelfUpdateStatus,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub enum SelfUpdateStatus {
     Updating,
+    #[default]
     Checking,
     Done,
     Failed,
 }
 
-impl Default for SelfUpdateStatus {
-    fn default() -> Self {
-        SelfUpdateStatus::Checking
-    }
-}
-
 impl std::fmt::Display for SelfUpdateStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = match self {
@@ -1,58 +1,125 @@
-use crate::core::sync::{
-    adb_shell_command, hashset_system_packages, list_all_system_packages, User,
+use crate::core::error::AdbError;
+use crate::core::journal::{record_entry, JournalEntry};
+use crate::core::profile::Profile;
+use crate::core::snapshot::{
+    list_packages, load_snapshot, save_snapshot, take_snapshot, DevicePackage,
 };
+use crate::core::sync::{adb_shell_command, Phone, User};
 use crate::core::theme::Theme;
 use crate::core::uad_lists::{Package, PackageState, Removal, UadList};
 use crate::gui::views::list::Selection;
-use crate::gui::widgets::package_row::PackageRow;
+use crate::gui::widgets::package_row::{PackageRow, RemovalTarget};
 use chrono::offset::Utc;
 use chrono::DateTime;
 use std::collections::HashMap;
 use std::fs;
-use std::io::{self, prelude::*, BufReader};
 use std::path::PathBuf;
 use std::process::Command;
 
-pub fn fetch_packages(
+/// Rows of the packages of a user, sorted by name
+pub fn package_rows(
     uad_lists: &HashMap<String, Package>,
-    user_id: Option<&User>,
+    packages: &[DevicePackage],
 ) -> Vec<PackageRow> {
-    let all_system_packages = list_all_system_packages(user_id); // installed and uninstalled packages
-    let enabled_system_packages = hashset_system_packages(PackageState::Enabled, user_id);
-    let disabled_system_packages = hashset_system_packages(PackageState::Disabled, user_id);
-    let mut description;
-    let mut uad_list;
-    let mut state;
-    let mut removal;
-    let mut user_package: Vec<PackageRow> = Vec::new();
-
-    for p_name in all_system_packages.lines() {
-        state = PackageState::Uninstalled;
-        description = "[No description] : CONTRIBUTION WELCOMED";
-        uad_list = UadList::Unlisted;
-        removal = Removal::Unlisted;
-
-        if uad_lists.contains_key(p_name) {
-            description = &uad_lists.get(p_name).unwrap().description;
-            if description.is_empty() {
-                description = "[No description] : CONTRIBUTION WELCOMED"
-            };
-            uad_list = uad_lists.get(p_name).unwrap().list;
-            removal = uad_lists.get(p_name).unwrap().removal;
-        }
+    let mut rows: Vec<PackageRow> = packages
+        .iter()
+        .map(|p| match p.third_party {
+            true => third_party_row(uad_lists, p),
+            false => system_row(uad_lists, p),
+        })
+        .collect();
+    rows.sort_by_key(|p| p.name.to_lowercase());
+    rows
+}
 
-        if enabled_system_packages.contains(p_name) {
-            state = PackageState::Enabled;
-        } else if disabled_system_packages.contains(p_name) {
-            state = PackageState::Disabled;
+fn system_row(uad_lists: &HashMap<String, Package>, p: &DevicePackage) -> PackageRow {
+    let (description, uad_list, removal) = match uad_lists.get(&p.name) {
+        Some(package) if !package.description.is_empty() => {
+            (package.description.as_str(), package.list, package.removal)
         }
+        Some(package) => (NO_DESCRIPTION, package.list, package.removal),
+        None => (NO_DESCRIPTION, UadList::Unlisted, Removal::Unlisted),
+    };
+    PackageRow::new(
+        &p.name,
+        p.state,
+        description,
+        uad_list,
+        removal,
+        false,
+        false,
+    )
+}
 
-        let package_row =
-            PackageRow::new(p_name, state, description, uad_list, removal, false, false);
-        user_package.push(package_row);
+fn third_party_row(uad_lists: &HashMap<String, Package>, p: &DevicePackage) -> PackageRow {
+    // Carrier and OEM apps pushed after the first boot may be in the lists
+    let (description, removal) = match uad_lists.get(&p.name) {
+        Some(package) if !package.description.is_empty() => {
+            (package.description.as_str(), package.removal)
+        }
+        Some(package) => (THIRD_PARTY_DESCRIPTION, package.removal),
+        None => (THIRD_PARTY_DESCRIPTION, Removal::Unlisted),
+    };
+    PackageRow {
+        installer: p.installer.clone(),
+        install_date: p.install_date.clone(),
+        ..PackageRow::new(
+            &p.name,
+            p.state,
+            description,
+            UadList::ThirdParty,
+            removal,
+            false,
+            false,
+        )
     }
-    user_package.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-    user_package
+}
+
+const NO_DESCRIPTION: &str = "[No description] : CONTRIBUTION WELCOMED";
+
+const THIRD_PARTY_DESCRIPTION: &str = "[User-installed app] If no other user has it, \
+    uninstalling it deletes the app and its data: it can't be restored afterwards.";
+
+/// `third_party` adds the user-installed apps to the system packages
+pub fn fetch_packages(
+    uad_lists: &HashMap<String, Package>,
+    device_id: &str,
+    user_id: Option<&User>,
+    third_party: bool,
+) -> Vec<PackageRow> {
+    package_rows(uad_lists, &list_packages(device_id, user_id, third_party))
+}
+
+/// Packages of every user of the device (indexed like the user list). They are cached
+/// for the next time the device is loaded (see `cached_packages`).
+pub fn fetch_all_packages(
+    uad_lists: &HashMap<String, Package>,
+    phone: &Phone,
+    third_party: bool,
+) -> Vec<Vec<PackageRow>> {
+    let snapshot = take_snapshot(phone, third_party);
+    save_snapshot(&phone.adb_id, &snapshot);
+    snapshot
+        .packages
+        .iter()
+        .map(|packages| package_rows(uad_lists, packages))
+        .collect()
+}
+
+/// Packages of the last `fetch_all_packages` of the device, without any adb call
+pub fn cached_packages(
+    uad_lists: &HashMap<String, Package>,
+    phone: &Phone,
+    third_party: bool,
+) -> Option<Vec<Vec<PackageRow>>> {
+    let snapshot = load_snapshot(phone, third_party)?;
+    Some(
+        snapshot
+            .packages
+            .iter()
+            .map(|packages| package_rows(uad_lists, packages))
+            .collect(),
+    )
 }
 
 pub fn update_selection_count(selection: &mut Selection, p_state: PackageState, add: bool) {
@@ -78,46 +145,71 @@ pub fn update_selection_count(selection: &mut Selection, p_state: PackageState,
                 selection.uninstalled -= 1
             };
         }
+        PackageState::Suspended => {
+            if add {
+                selection.suspended += 1
+            } else if selection.suspended > 0 {
+                selection.suspended -= 1
+            };
+        }
         PackageState::All => {}
     };
 }
 
-pub async fn export_selection(packages: Vec<PackageRow>) -> Result<bool, String> {
-    let selected = packages
-        .iter()
-        .filter(|p| p.selected)
-        .map(|p| p.name.clone())
-        .collect::<Vec<String>>()
-        .join("\n");
+/// Selects the packages that are not in the state targeted by the profile. The packages
+/// to remove get the removal action of the profile if the device supports it (the
+/// settings of the device are used otherwise).
+pub fn select_profile_packages(
+    packages: &mut [PackageRow],
+    selection: &mut Selection,
+    profile: &Profile,
+    android_sdk: u8,
+) {
+    let targets = RemovalTarget::available(android_sdk);
 
-    match fs::write("uad_exported_selection.txt", selected) {
-        Ok(_) => Ok(true),
-        Err(err) => Err(err.to_string()),
-    }
-}
-
-#[allow(clippy::needless_collect)] // false positive: https://github.com/rust-lang/rust-clippy/issues/6164
-pub fn import_selection(packages: &mut [PackageRow], selection: &mut Selection) -> io::Result<()> {
-    let file = fs::File::open("uad_exported_selection.txt")?;
-    let reader = BufReader::new(file);
-    let imported_selection: Vec<String> = reader
-        .lines()
-        .map(|l| l.expect("Could not parse line"))
-        .collect();
-
-    *selection = Selection::default(); // should already be empty normally
+    *selection = Selection::default();
 
     for (i, p) in packages.iter_mut().enumerate() {
-        if imported_selection.contains(&p.name) {
-            p.selected = true;
+        let target = profile.target(&p.name);
+        p.selected = target.is_some_and(|t| t != p.state);
+        if p.selected
+            && p.state == PackageState::Enabled
+            && targets.contains(&RemovalTarget(target))
+        {
+            p.target = target;
+        }
+        if p.selected {
             selection.selected_packages.push(i);
             update_selection_count(selection, p.state, true);
-        } else {
-            p.selected = false;
         }
     }
+}
+
+/// Carries what has been done in the list (selection, highlighted package, removal target,
+/// label and icon) from the rows of a user to the ones just fetched. Returns the new index
+/// of each row (`None` if the package is gone).
+pub fn merge_package_rows(rows: &[PackageRow], fresh: &mut [PackageRow]) -> Vec<Option<usize>> {
+    let positions: HashMap<&str, usize> = fresh
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p.name.as_str(), i))
+        .collect();
+    let moves: Vec<Option<usize>> = rows
+        .iter()
+        .map(|p| positions.get(p.name.as_str()).copied())
+        .collect();
 
-    Ok(())
+    for (row, new) in rows.iter().zip(&moves) {
+        if let Some(new) = new {
+            let package = &mut fresh[*new];
+            package.selected = row.selected;
+            package.current = row.current;
+            package.target = row.target;
+            package.label = row.label.clone();
+            package.icon = row.icon.clone();
+        }
+    }
+    moves
 }
 
 pub fn string_to_theme(theme: String) -> Theme {
@@ -180,27 +272,478 @@ pub fn format_diff_time_from_now(date: DateTime<Utc>) -> String {
     }
 }
 
-pub async fn perform_adb_commands(action: String, i: usize, label: String) -> Result<usize, ()> {
-    match adb_shell_command(true, &action) {
+/// Commands that will be run on a package (as returned by `action_handler`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedAction {
+    pub index: usize, // phone_packages index
+    pub package: String,
+    pub removal: Removal,
+    pub commands: Vec<String>,
+}
+
+/// Groups the commands by targeted user (`None` for devices without multi-user support)
+pub fn group_by_user(commands: &[String]) -> Vec<(Option<u16>, Vec<&str>)> {
+    let mut groups: Vec<(Option<u16>, Vec<&str>)> = vec![];
+    for command in commands {
+        let mut args = command.split_whitespace();
+        let user = args
+            .position(|a| a == "--user")
+            .and_then(|_| args.next())
+            .and_then(|u| u.parse().ok());
+        match groups.iter_mut().find(|(u, _)| *u == user) {
+            Some((_, group)) => group.push(command),
+            None => groups.push((user, vec![command])),
+        }
+    }
+    groups
+}
+
+/// Standalone script running the same commands as UAD. Only `adb` is needed to run it.
+pub fn shell_script(phone: &Phone, title: &str, actions: &[PlannedAction]) -> String {
+    let mut script = format!(
+        "#!/bin/sh\n\
+        # {}\n\
+        # Device: {} (Android SDK {})\n\
+        # Generated by Universal Android Debloater {} on {}\n\
+        \n\
+        ADB=\"adb -s {}\"\n",
+        title,
+        phone.model,
+        phone.android_sdk,
+        env!("CARGO_PKG_VERSION"),
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        phone.adb_id
+    );
+    for action in actions {
+        script += &format!("\n# {} [{}]\n", action.package, action.removal);
+        for command in &action.commands {
+            script += &format!("$ADB shell {}\n", command);
+        }
+    }
+    script
+}
+
+/// `journal_entry` is recorded in the device journal if the command succeeds
+pub async fn perform_adb_commands(
+    device_id: String,
+    action: String,
+    i: usize,
+    label: String,
+    journal_entry: Option<JournalEntry>,
+) -> Result<usize, AdbError> {
+    // On Android 4.4, ADB doesn't check if the package exists. It does not return any
+    // error if you try to `pm block` a non-existent package. Some commands are even killed
+    // by ADB before finishing and UAD can't catch the output.
+    match adb_shell_command(&device_id, &action) {
         Ok(o) => {
-            // On old devices, adb commands can return the '0' exit code even if there
-            // is an error. On Android 4.4, ADB doesn't check if the package exists.
-            // It does not return any error if you try to `pm block` a non-existent package.
-            // Some commands are even killed by ADB before finishing and UAD can't catch
-            // the output.
-            if ["Error", "Failure"].iter().any(|&e| o.contains(e)) {
-                error!("[{}] {} -> {}", label, action, o);
-                Err(())
-            } else {
-                info!("[{}] {} -> {}", label, action, o);
-                Ok(i)
+            info!("[{}] {} -> {}", label, action, o);
+            if let Some(entry) = journal_entry {
+                record_entry(&entry);
             }
+            Ok(i)
         }
         Err(err) => {
-            if !err.contains("[not installed for") {
+            if !matches!(err, AdbError::NotInstalled { .. }) {
                 error!("[{}] {} -> {}", label, action, err);
             }
-            Err(())
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::DeviceSettings;
+    use crate::core::mock::{install, phone, row, settings, MockDevice};
+    use crate::core::sync::{action_handler, Phone};
+    use iced::futures::executor::block_on;
+
+    const UAD_LISTS: &str = r#"[{
+        "id": "com.foo",
+        "list": "Oem",
+        "description": "Foo",
+        "dependencies": [],
+        "neededBy": [],
+        "labels": [],
+        "removal": "Recommended"
+    }]"#;
+
+    fn uad_lists() -> HashMap<String, Package> {
+        let list: Vec<Package> = serde_json::from_str(UAD_LISTS).unwrap();
+        list.into_iter()
+            .map(|p| ("com.foo".to_string(), p))
+            .collect()
+    }
+
+    // Same as what the GUI does: only the first command can change the package state
+    fn apply(phone: &Phone, user: &User, settings: &DeviceSettings, package: &PackageRow) -> bool {
+        action_handler(user, &package.into(), phone, settings)
+            .into_iter()
+            .map(|action| {
+                block_on(perform_adb_commands(
+                    phone.adb_id.clone(),
+                    action,
+                    0,
+                    "test".to_string(),
+                    None,
+                ))
+            })
+            .collect::<Vec<_>>()[0]
+            .is_ok()
+    }
+
+    #[test]
+    fn test_fetch_packages() {
+        let (_device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.bar", PackageState::Disabled)
+                .with_package("com.baz", PackageState::Uninstalled),
+        );
+        let packages = fetch_packages(&uad_lists(), "mock-device", None, false);
+
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["com.bar", "com.baz", "com.foo"]);
+        assert_eq!(packages[0].state, PackageState::Disabled);
+        assert_eq!(packages[1].state, PackageState::Uninstalled);
+        assert_eq!(packages[2].state, PackageState::Enabled);
+        assert_eq!(packages[2].description, "Foo");
+        assert_eq!(packages[2].uad_list, UadList::Oem);
+        assert_eq!(packages[2].removal, Removal::Recommended);
+        assert_eq!(packages[1].uad_list, UadList::Unlisted);
+    }
+
+    #[test]
+    fn test_fetch_third_party_packages() {
+        let (_device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Enabled)
+                .with_third_party_package(
+                    "com.carrier.app",
+                    "com.carrier.installer",
+                    "2023-01-02 10:00:00",
+                )
+                .with_third_party_package("org.sideloaded", "null", "2023-03-04 12:00:00"),
+        );
+        assert_eq!(
+            fetch_packages(&uad_lists(), "mock-device", None, false).len(),
+            1
+        );
+
+        let packages = fetch_packages(&uad_lists(), "mock-device", None, true);
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["com.carrier.app", "com.foo", "org.sideloaded"]);
+        assert_eq!(packages[0].uad_list, UadList::ThirdParty);
+        assert_eq!(packages[0].state, PackageState::Enabled);
+        assert_eq!(
+            packages[0].installer.as_deref(),
+            Some("com.carrier.installer")
+        );
+        assert_eq!(
+            packages[0].install_date.as_deref(),
+            Some("2023-01-02 10:00:00")
+        );
+        assert_eq!(packages[1].installer, None);
+        assert_eq!(packages[2].installer, None);
+        assert_eq!(
+            packages[2].install_date.as_deref(),
+            Some("2023-03-04 12:00:00")
+        );
+    }
+
+    #[test]
+    fn test_fetch_packages_per_user() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_work_profile(10, "Work profile")
+                .with_package("com.foo", PackageState::Enabled),
+        );
+        device.set_state("com.foo", 10, PackageState::Uninstalled);
+
+        let user = User::new(10, 1);
+        assert_eq!(
+            fetch_packages(&uad_lists(), "mock-device", None, false)[0].state,
+            PackageState::Enabled
+        );
+        assert_eq!(
+            fetch_packages(&uad_lists(), "mock-device", Some(&user), false)[0].state,
+            PackageState::Uninstalled
+        );
+    }
+
+    #[test]
+    fn test_remove_and_restore_per_sdk() {
+        for sdk in [19, 21, 23, 30] {
+            let (device, _guard) =
+                install(MockDevice::new(sdk).with_package("com.foo", PackageState::Enabled));
+            let phone = phone(sdk, &[0]);
+            let user = phone.user_list[0].clone();
+            let settings = settings(false, false);
+
+            assert!(apply(
+                &phone,
+                &user,
+                &settings,
+                &row("com.foo", PackageState::Enabled)
+            ));
+            assert_eq!(
+                device.state("com.foo", 0),
+                Some(PackageState::Uninstalled),
+                "SDK {}",
+                sdk
+            );
+
+            assert!(apply(
+                &phone,
+                &user,
+                &settings,
+                &row("com.foo", PackageState::Uninstalled)
+            ));
+            assert_eq!(
+                device.state("com.foo", 0),
+                Some(PackageState::Enabled),
+                "SDK {}",
+                sdk
+            );
         }
     }
+
+    #[test]
+    fn test_disable_mode() {
+        let (device, _guard) =
+            install(MockDevice::new(30).with_package("com.foo", PackageState::Enabled));
+        let phone = phone(30, &[0]);
+        let settings = settings(true, false);
+
+        assert!(apply(
+            &phone,
+            &phone.user_list[0],
+            &settings,
+            &row("com.foo", PackageState::Enabled)
+        ));
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Disabled));
+        assert!(device
+            .history()
+            .contains(&"am force-stop --user 0 com.foo".to_string()));
+
+        assert!(apply(
+            &phone,
+            &phone.user_list[0],
+            &settings,
+            &row("com.foo", PackageState::Disabled)
+        ));
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Enabled));
+    }
+
+    #[test]
+    fn test_multi_user_mode() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_work_profile(10, "Work profile")
+                .with_package("com.foo", PackageState::Enabled)
+                .with_package("com.bar", PackageState::Enabled),
+        );
+        let phone = phone(30, &[0, 10]);
+        let mut settings = settings(false, true);
+
+        assert!(apply(
+            &phone,
+            &phone.user_list[0],
+            &settings,
+            &row("com.foo", PackageState::Enabled)
+        ));
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Uninstalled));
+        assert_eq!(device.state("com.foo", 10), Some(PackageState::Uninstalled));
+
+        settings.multi_user_mode = false;
+        assert!(apply(
+            &phone,
+            &phone.user_list[1],
+            &settings,
+            &row("com.bar", PackageState::Enabled)
+        ));
+        assert_eq!(device.state("com.bar", 0), Some(PackageState::Enabled));
+        assert_eq!(device.state("com.bar", 10), Some(PackageState::Uninstalled));
+    }
+
+    #[test]
+    fn test_failures() {
+        let (device, _guard) = install(
+            MockDevice::new(30)
+                .with_package("com.foo", PackageState::Uninstalled)
+                .with_response(
+                    "pm uninstall --user 0 com.bar",
+                    Ok("Failure [DELETE_FAILED_INTERNAL_ERROR]"),
+                ),
+        );
+
+        let result = block_on(perform_adb_commands(
+            "mock-device".to_string(),
+            "pm uninstall --user 0 com.foo".to_string(),
+            3,
+            "test".to_string(),
+            None,
+        ));
+        assert_eq!(result, Err(AdbError::NotInstalled { user: Some(0) }));
+
+        // Some errors are returned with the `0` exit code
+        let result = block_on(perform_adb_commands(
+            "mock-device".to_string(),
+            "pm uninstall --user 0 com.bar".to_string(),
+            3,
+            "test".to_string(),
+            None,
+        ));
+        assert_eq!(
+            result,
+            Err(AdbError::Unknown(
+                "Failure [DELETE_FAILED_INTERNAL_ERROR]".to_string()
+            ))
+        );
+        assert_eq!(device.state("com.foo", 0), Some(PackageState::Uninstalled));
+
+        let result = block_on(perform_adb_commands(
+            "mock-device".to_string(),
+            "cmd package install-existing --user 0 com.foo".to_string(),
+            3,
+            "test".to_string(),
+            None,
+        ));
+        assert_eq!(result, Ok(3));
+    }
+
+    #[test]
+    fn test_select_profile_packages() {
+        let phone = phone(30, &[0]);
+        let settings = settings(false, false);
+        let uninstalled = row("com.bar", PackageState::Enabled);
+        let suspended = PackageRow {
+            target: Some(PackageState::Suspended),
+            ..row("com.baz", PackageState::Enabled)
+        };
+        let profile = Profile::from_selection(
+            "test",
+            "",
+            &phone,
+            &phone.user_list[0],
+            &settings,
+            &[&uninstalled, &suspended],
+        );
+        assert_eq!(profile.target("com.bar"), Some(PackageState::Uninstalled));
+        assert_eq!(profile.target("com.baz"), Some(PackageState::Suspended));
+
+        // Packages are disabled instead of suspended before Android 9
+        let old_phone = Phone {
+            android_sdk: 26,
+            ..phone.clone()
+        };
+        let old_profile = Profile::from_selection(
+            "test",
+            "",
+            &old_phone,
+            &old_phone.user_list[0],
+            &settings,
+            &[&suspended],
+        );
+        assert_eq!(old_profile.target("com.baz"), Some(PackageState::Disabled));
+
+        let mut packages = vec![
+            row("com.bar", PackageState::Enabled),
+            row("com.baz", PackageState::Enabled),
+            row("com.qux", PackageState::Enabled),
+        ];
+        let mut selection = Selection::default();
+        select_profile_packages(&mut packages, &mut selection, &profile, 30);
+        assert_eq!(selection.selected_packages, vec![0, 1]);
+        assert_eq!(selection.enabled, 2);
+        assert_eq!(packages[1].target, Some(PackageState::Suspended));
+        assert!(!packages[2].selected);
+
+        // Packages can't be suspended on Android 8: the settings of the device are used
+        packages[1].target = None;
+        select_profile_packages(&mut packages, &mut selection, &profile, 26);
+        assert_eq!(selection.selected_packages, vec![0, 1]);
+        assert_eq!(packages[1].target, None);
+
+        // Already in the targeted state
+        packages[0].state = PackageState::Uninstalled;
+        packages[1].state = PackageState::Suspended;
+        select_profile_packages(&mut packages, &mut selection, &profile, 30);
+        assert!(selection.selected_packages.is_empty());
+    }
+
+    #[test]
+    fn test_merge_package_rows() {
+        let row = |name: &str, state| {
+            PackageRow::new(
+                name,
+                state,
+                "",
+                UadList::Oem,
+                Removal::Recommended,
+                false,
+                false,
+            )
+        };
+        let mut rows = vec![
+            row("com.bar", PackageState::Enabled),
+            row("com.foo", PackageState::Enabled),
+        ];
+        rows[0].current = true;
+        rows[1].selected = true;
+        rows[1].target = Some(PackageState::Disabled);
+        // `com.bar` has been uninstalled for every user, `com.baz` installed
+        let mut fresh = vec![
+            row("com.baz", PackageState::Enabled),
+            row("com.foo", PackageState::Disabled),
+        ];
+
+        assert_eq!(merge_package_rows(&rows, &mut fresh), vec![None, Some(1)]);
+        assert!(fresh[1].selected);
+        assert_eq!(fresh[1].state, PackageState::Disabled);
+        assert_eq!(fresh[1].target, Some(PackageState::Disabled));
+        assert!(!fresh[0].selected && !fresh[0].current);
+    }
+
+    #[test]
+    fn test_group_by_user() {
+        let commands: Vec<String> = [
+            "pm disable-user --user 0 com.foo",
+            "am force-stop --user 0 com.foo",
+            "pm disable-user --user 10 com.foo",
+        ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+        let groups = group_by_user(&commands);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            groups[0],
+            (Some(0), vec![commands[0].as_str(), &commands[1]])
+        );
+        assert_eq!(groups[1], (Some(10), vec![commands[2].as_str()]));
+
+        let commands = vec!["pm block com.foo".to_string()];
+        assert_eq!(
+            group_by_user(&commands),
+            vec![(None, vec!["pm block com.foo"])]
+        );
+    }
+
+    #[test]
+    fn test_shell_script() {
+        let actions = [PlannedAction {
+            index: 0,
+            package: "com.foo".to_string(),
+            removal: Removal::Recommended,
+            commands: vec!["pm uninstall --user 0 com.foo".to_string()],
+        }];
+        let script = shell_script(&phone(30, &[0]), "Uninstall 1 package", &actions);
+        assert!(script.starts_with("#!/bin/sh\n# Uninstall 1 package\n"));
+        assert!(script.contains("ADB=\"adb -s mock-device\"\n"));
+        assert!(
+            script.ends_with("# com.foo [Recommended]\n$ADB shell pm uninstall --user 0 com.foo\n")
+        );
+    }
 }
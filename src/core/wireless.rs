@@ -0,0 +1,131 @@
+//! Wireless debugging. Android 11+ devices are paired once with the code shown in
+//! `Developer options > Wireless debugging > Pair device with pairing code`, then
+//! connected to by IP address and port (older devices only need `adb tcpip 5555`).
+use crate::core::error::AdbError;
+use crate::core::sync::{adb_host_command, parse_devices, probe_device, DeviceStatus, Phone};
+use retry::{delay::Fixed, retry, Error, OperationResult};
+
+/// Port used by `adb tcpip` (when the address has no port)
+pub const DEFAULT_PORT: u16 = 5555;
+
+/// `host:port` from what the user typed. The default port is added if missing.
+pub fn parse_address(address: &str) -> Option<String> {
+    let address = address.trim();
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().ok()?),
+        None => (address, DEFAULT_PORT),
+    };
+    if host.is_empty() || host.contains(char::is_whitespace) || port == 0 {
+        return None;
+    }
+    Some(format!("{}:{}", host, port))
+}
+
+/// Pairing codes are 6 digits
+pub fn is_pairing_code(code: &str) -> bool {
+    code.len() == 6 && code.chars().all(|c| c.is_ascii_digit())
+}
+
+/// `address` is the pairing address, not the one used to connect (the port differs)
+pub async fn pair(address: String, code: String) -> Result<(), AdbError> {
+    let output = adb_host_command(&format!("pair {} {}", address, code))?;
+    if output.contains("Successfully paired") {
+        info!("Paired with {}", address);
+        Ok(())
+    } else {
+        Err(AdbError::from_output(&output))
+    }
+}
+
+/// Connects to the device and waits (up to 10 seconds) for it to be usable.
+/// The serial of a wireless device is its address.
+pub async fn connect(address: String) -> Result<Phone, AdbError> {
+    // `adb connect` doesn't always fail when it can't connect
+    let output = adb_host_command(&format!("connect {}", address))?;
+    if !output.contains("connected to") {
+        return Err(AdbError::from_output(&output));
+    }
+    info!("Connected to {}", address);
+
+    let status = retry(
+        Fixed::from_millis(500).take(20),
+        || match adb_host_command("devices -l") {
+            Ok(devices) => match parse_devices(&devices)
+                .into_iter()
+                .find(|d| d.serial == address)
+            {
+                Some(device) if device.status == DeviceStatus::Device => {
+                    OperationResult::Ok(device)
+                }
+                Some(device) => OperationResult::Retry(Some(device.status)),
+                None => OperationResult::Retry(None),
+            },
+            Err(_) => OperationResult::Retry(None),
+        },
+    );
+    match status {
+        Ok(device) => Ok(probe_device(device)),
+        Err(Error::Operation {
+            error: Some(DeviceStatus::Unauthorized | DeviceStatus::Authorizing),
+            ..
+        }) => Err(AdbError::Unauthorized),
+        Err(Error::Operation { error: Some(_), .. }) => Err(AdbError::DeviceOffline),
+        Err(_) => Err(AdbError::NoDevice),
+    }
+}
+
+pub async fn disconnect(address: String) -> Result<(), AdbError> {
+    adb_host_command(&format!("disconnect {}", address)).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mock::{install, MockDevice};
+    use iced::futures::executor::block_on;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(
+            parse_address(" 192.168.1.20 "),
+            Some("192.168.1.20:5555".to_string())
+        );
+        assert_eq!(
+            parse_address("192.168.1.20:37123"),
+            Some("192.168.1.20:37123".to_string())
+        );
+        assert_eq!(parse_address("192.168.1.20:port"), None);
+        assert_eq!(parse_address(":5555"), None);
+        assert!(is_pairing_code("012345"));
+        assert!(!is_pairing_code("12345a"));
+    }
+
+    #[test]
+    fn test_pair_and_connect() {
+        let (_, _guard) = install(
+            MockDevice::new(31)
+                .with_serial("192.168.1.20:5555")
+                .with_status("disconnected"),
+        );
+
+        assert!(matches!(
+            block_on(pair("192.168.1.20:37123".to_string(), "000000".to_string())),
+            Err(AdbError::ConnectionFailed(_))
+        ));
+        assert!(block_on(pair("192.168.1.20:37123".to_string(), "123456".to_string())).is_ok());
+
+        assert!(matches!(
+            block_on(connect("192.168.1.21:5555".to_string())),
+            Err(AdbError::ConnectionFailed(_))
+        ));
+        let phone = block_on(connect("192.168.1.20:5555".to_string())).unwrap();
+        assert_eq!(phone.adb_id, "192.168.1.20:5555");
+        assert_eq!(phone.android_sdk, 31);
+        assert!(phone.is_ready());
+
+        block_on(disconnect("192.168.1.20:5555".to_string())).unwrap();
+        assert!(!adb_host_command("devices")
+            .unwrap()
+            .contains("192.168.1.20:5555"));
+    }
+}
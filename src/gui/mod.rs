@@ -2,20 +2,35 @@ pub mod style;
 pub mod views;
 pub mod widgets;
 
-use crate::core::sync::{get_devices_list, Phone};
+use crate::core::error::AdbError;
+use crate::core::profile::Profile;
+use crate::core::sync::{
+    get_devices_list, reboot, refresh_devices, track_devices, wait_for_reboot, AdbDevice, Phone,
+    RebootTarget,
+};
 use crate::core::theme::Theme;
 use crate::core::uad_lists::UadListState;
 use crate::core::update::{get_latest_release, Release, SelfUpdateState, SelfUpdateStatus};
-use crate::core::utils::{perform_adb_commands, string_to_theme};
+use crate::core::utils::string_to_theme;
 
 use views::about::{About as AboutView, Message as AboutMessage};
+use views::history::{History as HistoryView, Message as HistoryMessage};
 use views::list::{List as AppsView, LoadingState as ListLoadingState, Message as AppsMessage};
+use views::profiles::{Message as ProfilesMessage, Profiles as ProfilesView};
+use views::recovery::{Message as RecoveryMessage, Recovery as RecoveryView};
 use views::settings::{Message as SettingsMessage, Settings as SettingsView};
+use views::wireless::{Message as WirelessMessage, Wireless as WirelessView};
 use widgets::navigation_menu::nav_menu;
 
+use iced::futures::channel::mpsc;
+use iced::futures::StreamExt;
 use iced::widget::column;
-use iced::{window::Settings as Window, Application, Command, Element, Length, Renderer, Settings};
-use std::{env, path::PathBuf};
+use iced::{
+    subscription, window::Settings as Window, Application, Command, Element, Length, Renderer,
+    Settings, Subscription,
+};
+use std::path::PathBuf;
+use std::thread;
 
 #[cfg(feature = "self-update")]
 use crate::core::update::{bin_name, download_update_to_temp_file, remove_file};
@@ -26,6 +41,10 @@ enum View {
     List,
     About,
     Settings,
+    History,
+    Profiles,
+    Wireless,
+    Recovery,
 }
 
 #[derive(Default, Clone)]
@@ -40,8 +59,14 @@ pub struct UadGui {
     apps_view: AppsView,
     about_view: AboutView,
     settings_view: SettingsView,
+    history_view: HistoryView,
+    profiles_view: ProfilesView,
+    wireless_view: WirelessView,
+    recovery_view: RecoveryView,
     devices_list: Vec<Phone>,
     selected_device: Option<Phone>, // index of devices_list
+    last_device_id: Option<String>, // Device to reconnect to when it comes back
+    discovering: bool,              // A full device discovery is running
     update_state: UpdateState,
 }
 
@@ -50,14 +75,26 @@ pub enum Message {
     // Navigation Panel
     AboutPressed,
     SettingsPressed,
+    HistoryPressed,
+    ProfilesPressed,
+    WirelessPressed,
+    RecoveryPressed,
     AppsPress,
     DeviceSelected(Phone),
     AboutAction(AboutMessage),
     AppsAction(AppsMessage),
     SettingsAction(SettingsMessage),
+    HistoryAction(HistoryMessage),
+    ProfilesAction(ProfilesMessage),
+    WirelessAction(WirelessMessage),
+    RecoveryAction(RecoveryMessage),
     RefreshButtonPressed,
-    RebootButtonPressed,
+    RebootSelected(RebootTarget),
+    Rebooted(String, RebootTarget, Result<(), AdbError>),
+    DeviceRebooted(RebootTarget, Option<Phone>),
     LoadDevices(Vec<Phone>),
+    DevicesChanged(Vec<AdbDevice>),
+    DevicesUpdated(Vec<Phone>),
     _NewReleaseDownloaded(Result<(PathBuf, PathBuf), ()>),
     GetLatestRelease(Result<Option<Release>, ()>),
     Nothing,
@@ -71,7 +108,10 @@ impl Application for UadGui {
 
     fn new(_flags: ()) -> (Self, Command<Message>) {
         (
-            Self::default(),
+            Self {
+                discovering: true,
+                ..Self::default()
+            },
             Command::batch([
                 Command::perform(get_devices_list(), Message::LoadDevices),
                 Command::perform(
@@ -92,20 +132,75 @@ impl Application for UadGui {
     fn update(&mut self, msg: Message) -> Command<Message> {
         match msg {
             Message::LoadDevices(devices_list) => {
+                let previous_device = self.selected_device.as_ref().map(|p| p.adb_id.clone());
                 self.selected_device = match &self.selected_device {
                     Some(s_device) => {
                         // Try to reload last selected phone
                         devices_list
                             .iter()
-                            .find(|phone| phone.adb_id == s_device.adb_id)
+                            .find(|phone| phone.adb_id == s_device.adb_id && phone.is_ready())
                             .map(|x| x.to_owned())
                     }
-                    None => devices_list.first().map(|x| x.to_owned()),
+                    None => devices_list
+                        .iter()
+                        .find(|phone| phone.is_ready())
+                        .map(|x| x.to_owned()),
                 };
                 self.devices_list = devices_list;
+                self.discovering = false;
+
+                // Same device: the filters and the selection are kept
+                if matches!(self.apps_view.loading_state, ListLoadingState::Ready)
+                    && previous_device.is_some()
+                    && self.selected_device.as_ref().map(|p| &p.adb_id) == previous_device.as_ref()
+                {
+                    return self.update(Message::AppsAction(AppsMessage::RefreshPackages));
+                }
+                self.apps_view.loading_state = ListLoadingState::FindingPhones;
                 self.update(Message::SettingsAction(SettingsMessage::LoadDeviceSettings));
                 self.update(Message::AppsAction(AppsMessage::LoadUadList(true)))
             }
+            Message::DevicesChanged(devices) => Command::perform(
+                refresh_devices(self.devices_list.clone(), devices),
+                Message::DevicesUpdated,
+            ),
+            Message::DevicesUpdated(devices_list) => {
+                self.devices_list = devices_list;
+                // `LoadDevices` will select the device
+                if self.discovering {
+                    return Command::none();
+                }
+                if let Some(s_device) = &self.selected_device {
+                    if self
+                        .devices_list
+                        .iter()
+                        .any(|phone| phone.adb_id == s_device.adb_id && phone.is_ready())
+                    {
+                        return Command::none();
+                    }
+                    info!("{} has been disconnected", s_device.adb_id);
+                    self.last_device_id = Some(s_device.adb_id.clone());
+                    self.selected_device = None;
+                    self.apps_view.loading_state = ListLoadingState::FindingPhones;
+                }
+
+                // Reconnect to the last device, or to the first one available
+                let ready = self.devices_list.iter().filter(|phone| phone.is_ready());
+                let device = ready
+                    .clone()
+                    .find(|phone| Some(&phone.adb_id) == self.last_device_id.as_ref())
+                    .or_else(|| ready.clone().next())
+                    .cloned();
+                match device {
+                    Some(phone) => {
+                        info!("Connected to {} ({})", phone.model, phone.adb_id);
+                        self.selected_device = Some(phone);
+                        self.update(Message::SettingsAction(SettingsMessage::LoadDeviceSettings));
+                        self.update(Message::AppsAction(AppsMessage::LoadUadList(true)))
+                    }
+                    None => Command::none(),
+                }
+            }
             Message::AppsPress => {
                 self.view = View::List;
                 Command::none()
@@ -122,18 +217,78 @@ impl Application for UadGui {
                 self.view = View::Settings;
                 Command::none()
             }
+            Message::HistoryPressed => {
+                self.view = View::History;
+                self.update(Message::HistoryAction(HistoryMessage::LoadJournal))
+            }
+            Message::ProfilesPressed => {
+                self.view = View::Profiles;
+                self.update(Message::ProfilesAction(ProfilesMessage::LoadProfiles))
+            }
+            Message::WirelessPressed => {
+                self.view = View::Wireless;
+                Command::none()
+            }
+            Message::RecoveryPressed => {
+                self.view = View::Recovery;
+                self.update(Message::RecoveryAction(RecoveryMessage::LoadRestorePoints))
+            }
             Message::RefreshButtonPressed => {
-                self.apps_view.loading_state = ListLoadingState::FindingPhones;
+                // A loaded list is refreshed in place if the device is still there (see `LoadDevices`)
+                if !matches!(self.apps_view.loading_state, ListLoadingState::Ready)
+                    || self.selected_device.is_none()
+                {
+                    self.apps_view.loading_state = ListLoadingState::FindingPhones;
+                }
+                self.discovering = true;
                 Command::perform(get_devices_list(), Message::LoadDevices)
             }
-            Message::RebootButtonPressed => {
-                self.apps_view.loading_state = ListLoadingState::FindingPhones;
-                self.selected_device = None;
-                self.devices_list = vec![];
-                Command::perform(
-                    perform_adb_commands("reboot".to_string(), 0, "ADB".to_string()),
-                    |_| Message::Nothing,
-                )
+            Message::RebootSelected(target) => {
+                let device_id = match self.selected_device.take() {
+                    Some(phone) => phone.adb_id,
+                    None => return Command::none(),
+                };
+                self.apps_view.loading_state = ListLoadingState::Rebooting(target);
+                self.last_device_id = Some(device_id.clone());
+                // The device is selected again by `DeviceRebooted`
+                self.discovering = true;
+                Command::perform(reboot(device_id.clone(), target), move |res| {
+                    Message::Rebooted(device_id, target, res)
+                })
+            }
+            Message::Rebooted(device_id, target, res) => match res {
+                Ok(_) => Command::perform(wait_for_reboot(device_id, target), move |phone| {
+                    Message::DeviceRebooted(target, phone)
+                }),
+                Err(e) => {
+                    error!("Could not reboot {} into {}: {}", device_id, target, e);
+                    self.discovering = false;
+                    self.update(Message::DevicesUpdated(self.devices_list.clone()))
+                }
+            },
+            Message::DeviceRebooted(target, phone) => {
+                self.discovering = false;
+                match phone {
+                    Some(phone) => {
+                        match self
+                            .devices_list
+                            .iter_mut()
+                            .find(|p| p.adb_id == phone.adb_id)
+                        {
+                            Some(p) => *p = phone.clone(),
+                            None => self.devices_list.push(phone.clone()),
+                        }
+                        self.update(Message::DeviceSelected(phone))
+                    }
+                    None => {
+                        // Recovery, bootloader...: the device can't be used until it's
+                        // rebooted to the system. The tracking reconnects to it then.
+                        if target.comes_back() {
+                            self.apps_view.loading_state = ListLoadingState::FindingPhones;
+                        }
+                        self.update(Message::DevicesUpdated(self.devices_list.clone()))
+                    }
+                }
             }
             Message::AppsAction(msg) => self
                 .apps_view
@@ -145,9 +300,111 @@ impl Application for UadGui {
                 )
                 .map(Message::AppsAction),
             Message::SettingsAction(msg) => {
+                let reload = matches!(msg, SettingsMessage::ThirdParty(_));
                 self.settings_view
                     .update(&self.selected_device.clone().unwrap_or_default(), msg);
-                Command::none()
+                if reload {
+                    self.update(Message::AppsAction(AppsMessage::ReloadPackages))
+                } else {
+                    Command::none()
+                }
+            }
+            Message::HistoryAction(msg) => {
+                let rollback_done = matches!(msg, HistoryMessage::RolledBack(_));
+                let command = self
+                    .history_view
+                    .update(&self.selected_device.clone().unwrap_or_default(), msg)
+                    .map(Message::HistoryAction);
+
+                // Packages states have changed: the list needs to be refreshed
+                if rollback_done && !self.history_view.is_busy() {
+                    Command::batch([
+                        command,
+                        self.update(Message::AppsAction(AppsMessage::RefreshPackages)),
+                    ])
+                } else {
+                    command
+                }
+            }
+            Message::ProfilesAction(msg) => {
+                let command = self
+                    .profiles_view
+                    .update(
+                        &self.selected_device.clone().unwrap_or_default(),
+                        &self.devices_list,
+                        self.selection_profile(),
+                        msg.clone(),
+                    )
+                    .map(Message::ProfilesAction);
+
+                match msg {
+                    ProfilesMessage::LoadPressed(name) => {
+                        match self.profiles_view.profile(&name).cloned() {
+                            Some(profile) => {
+                                self.view = View::List;
+                                self.update(Message::AppsAction(AppsMessage::ApplyProfile(profile)))
+                            }
+                            None => command,
+                        }
+                    }
+                    // Packages states have changed: the list needs to be refreshed
+                    ProfilesMessage::PlanApplied(_) => Command::batch([
+                        command,
+                        self.update(Message::AppsAction(AppsMessage::RefreshPackages)),
+                    ]),
+                    ProfilesMessage::BatchApplied(serial, _)
+                        if self.selected_device.as_ref().map(|p| &p.adb_id) == Some(&serial) =>
+                    {
+                        Command::batch([
+                            command,
+                            self.update(Message::AppsAction(AppsMessage::RefreshPackages)),
+                        ])
+                    }
+                    _ => command,
+                }
+            }
+            Message::WirelessAction(msg) => {
+                let command = self
+                    .wireless_view
+                    .update(msg.clone())
+                    .map(Message::WirelessAction);
+
+                // Switch to the device that has just been connected
+                match msg {
+                    WirelessMessage::Connected(_, Ok(phone)) => {
+                        match self
+                            .devices_list
+                            .iter_mut()
+                            .find(|p| p.adb_id == phone.adb_id)
+                        {
+                            Some(p) => *p = phone.clone(),
+                            None => self.devices_list.push(phone.clone()),
+                        }
+                        Command::batch([command, self.update(Message::DeviceSelected(phone))])
+                    }
+                    _ => command,
+                }
+            }
+            Message::RecoveryAction(msg) => {
+                let command = self
+                    .recovery_view
+                    .update(msg.clone())
+                    .map(Message::RecoveryAction);
+
+                // Packages states have changed: the list needs to be refreshed
+                match msg {
+                    RecoveryMessage::Attempted(serial, attempt)
+                        if attempt.results.iter().any(|(_, r)| r.is_ok())
+                            && self.selected_device.as_ref().map(|p| &p.adb_id)
+                                == Some(&serial) =>
+                    {
+                        Command::batch([
+                            command,
+                            self.update(Message::AppsAction(AppsMessage::RefreshPackages)),
+                        ])
+                    }
+                    _ => command,
+                }
             }
             Message::AboutAction(msg) => {
                 self.about_view.update(msg.clone());
@@ -184,10 +441,16 @@ impl Application for UadGui {
                     _ => Command::none(),
                 }
             }
+            // Known wireless devices that are not connected are listed in the picker too
+            Message::DeviceSelected(s_device) if !s_device.is_ready() => {
+                self.view = View::Wireless;
+                self.update(Message::WirelessAction(WirelessMessage::ConnectPressed(
+                    Some(s_device.adb_id),
+                )))
+            }
             Message::DeviceSelected(s_device) => {
                 self.selected_device = Some(s_device.clone());
                 self.view = View::List;
-                env::set_var("ANDROID_SERIAL", s_device.adb_id);
                 info!("{:-^65}", "-");
                 info!(
                     "ANDROID_SDK: {} | DEVICE: {}",
@@ -260,9 +523,31 @@ impl Application for UadGui {
         }
     }
 
+    // Devices plugged, unplugged or authorized
+    fn subscription(&self) -> Subscription<Message> {
+        struct TrackDevices;
+
+        subscription::unfold(
+            std::any::TypeId::of::<TrackDevices>(),
+            None,
+            |receiver: Option<mpsc::UnboundedReceiver<Vec<AdbDevice>>>| async move {
+                let mut receiver = receiver.unwrap_or_else(|| {
+                    let (sender, receiver) = mpsc::unbounded();
+                    thread::spawn(move || track_devices(|d| sender.unbounded_send(d).is_ok()));
+                    receiver
+                });
+                match receiver.next().await {
+                    Some(devices) => (Some(Message::DevicesChanged(devices)), Some(receiver)),
+                    None => (None, None),
+                }
+            },
+        )
+    }
+
     fn view(&self) -> Element<Self::Message, Renderer<Self::Theme>> {
         let navigation_container = nav_menu(
             &self.devices_list,
+            &self.wireless_view.known_devices,
             self.selected_device.clone(),
             &self.apps_view,
             &self.update_state.self_update,
@@ -282,6 +567,19 @@ impl Application for UadGui {
                 .settings_view
                 .view(&selected_device)
                 .map(Message::SettingsAction),
+            View::History => self.history_view.view().map(Message::HistoryAction),
+            View::Profiles => self
+                .profiles_view
+                .view(&self.selection_profile(), &self.devices_list)
+                .map(Message::ProfilesAction),
+            View::Recovery => self
+                .recovery_view
+                .view(&self.devices_list)
+                .map(Message::RecoveryAction),
+            View::Wireless => self
+                .wireless_view
+                .view(&self.devices_list)
+                .map(Message::WirelessAction),
         };
 
         column![navigation_container, main_container]
@@ -291,6 +589,18 @@ impl Application for UadGui {
 }
 
 impl UadGui {
+    // Profile made from the packages currently selected in the list
+    fn selection_profile(&self) -> Profile {
+        Profile::from_selection(
+            "",
+            "",
+            &self.selected_device.clone().unwrap_or_default(),
+            &self.apps_view.selected_user(),
+            &self.settings_view.device,
+            &self.apps_view.selected_packages(),
+        )
+    }
+
     pub fn start() {
         let settings: Settings<()> = Settings {
             window: Window {
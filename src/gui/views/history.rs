@@ -0,0 +1,183 @@
+use crate::core::error::AdbError;
+use crate::core::journal::{load_journal, mark_rolled_back, JournalEntry};
+use crate::core::sync::Phone;
+use crate::core::theme::Theme;
+use crate::core::utils::perform_adb_commands;
+use crate::gui::style;
+
+use iced::widget::{button, column, container, row, scrollable, text, Space};
+use iced::{Alignment, Command, Element, Length, Renderer};
+
+#[derive(Default, Debug, Clone)]
+pub struct History {
+    entries: Vec<JournalEntry>,
+    pending_rollbacks: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    LoadJournal,
+    RollbackSession(i64),
+    RollbackEntry(usize),
+    RolledBack(Result<usize, AdbError>),
+    Nothing,
+}
+
+impl History {
+    pub fn update(&mut self, phone: &Phone, msg: Message) -> Command<Message> {
+        match msg {
+            Message::LoadJournal => {
+                self.entries = load_journal(&phone.adb_id);
+                Command::none()
+            }
+            Message::RollbackEntry(i) => self.rollback(phone, &[i]),
+            Message::RollbackSession(session) => {
+                // Most recent actions are rolled back first
+                let indexes: Vec<usize> = (0..self.entries.len())
+                    .rev()
+                    .filter(|i| {
+                        let entry = &self.entries[*i];
+                        entry.session == session && !entry.rolled_back && !entry.is_rollback
+                    })
+                    .collect();
+                self.rollback(phone, &indexes)
+            }
+            Message::RolledBack(res) => {
+                self.pending_rollbacks = self.pending_rollbacks.saturating_sub(1);
+                if let Ok(i) = res {
+                    mark_rolled_back(&phone.adb_id, &[i]);
+                    self.entries = load_journal(&phone.adb_id);
+                }
+                Command::none()
+            }
+            Message::Nothing => Command::none(),
+        }
+    }
+
+    /// True while some rollback commands have not returned yet
+    pub fn is_busy(&self) -> bool {
+        self.pending_rollbacks > 0
+    }
+
+    fn rollback(&mut self, phone: &Phone, indexes: &[usize]) -> Command<Message> {
+        let mut commands = vec![];
+        for i in indexes {
+            let entry = &self.entries[*i];
+            let actions = entry.rollback_commands(phone);
+            let mut journal_entry = Some(entry.rollback(&actions));
+            self.pending_rollbacks += 1;
+
+            for (j, action) in actions.into_iter().enumerate() {
+                // Only the first command can change the package state
+                commands.push(Command::perform(
+                    perform_adb_commands(
+                        phone.adb_id.clone(),
+                        action,
+                        *i,
+                        "Rollback".to_string(),
+                        journal_entry.take(),
+                    ),
+                    if j == 0 {
+                        Message::RolledBack
+                    } else {
+                        |_| Message::Nothing
+                    },
+                ));
+            }
+        }
+        Command::batch(commands)
+    }
+
+    pub fn view(&self) -> Element<Message, Renderer<Theme>> {
+        if self.entries.is_empty() {
+            return container(text("No action has been recorded for this device yet").size(20))
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .center_x()
+                .center_y()
+                .into();
+        }
+
+        // Most recent sessions first
+        let mut sessions: Vec<i64> = vec![];
+        for entry in self.entries.iter().rev() {
+            if !sessions.contains(&entry.session) {
+                sessions.push(entry.session);
+            }
+        }
+
+        let content = sessions.iter().fold(column![].spacing(15), |col, session| {
+            let indexes: Vec<usize> = (0..self.entries.len())
+                .rev()
+                .filter(|i| self.entries[*i].session == *session)
+                .collect();
+
+            let header = row![
+                text(format!(
+                    "Session of {}",
+                    self.entries[*indexes.last().unwrap()].date
+                ))
+                .size(20),
+                Space::new(Length::Fill, Length::Shrink),
+                button("Roll back session")
+                    .on_press(Message::RollbackSession(*session))
+                    .padding(5)
+                    .style(style::Button::Primary),
+            ]
+            .align_items(Alignment::Center);
+
+            let entries = indexes
+                .iter()
+                .fold(column![].spacing(6), |col, i| col.push(self.entry_row(*i)));
+
+            col.push(
+                container(column![header, entries].spacing(10))
+                    .padding(10)
+                    .width(Length::Fill)
+                    .style(style::Container::Frame),
+            )
+        });
+
+        container(
+            scrollable(content)
+                .scrollbar_margin(2)
+                .style(style::Scrollable::Packages),
+        )
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .padding(10)
+        .into()
+    }
+
+    fn entry_row(&self, i: usize) -> Element<Message, Renderer<Theme>> {
+        let entry = &self.entries[i];
+        let user = if entry.multi_user {
+            "all users".to_string()
+        } else {
+            format!("user {}", entry.user)
+        };
+
+        let rollback_btn = if entry.rolled_back {
+            button(text("Rolled back").size(15)).style(style::Button::Unavailable)
+        } else {
+            button(text("Roll back").size(15))
+                .on_press(Message::RollbackEntry(i))
+                .style(style::Button::RestorePackage)
+        };
+
+        row![
+            text(&entry.date)
+                .width(Length::Units(170))
+                .style(style::Text::Commentary),
+            text(&entry.package).width(Length::Fill),
+            text(user).width(Length::Units(90)),
+            text(format!("{} -> {}", entry.previous_state, entry.new_state))
+                .width(Length::Units(200)),
+            rollback_btn.padding(5),
+            Space::with_width(Length::Units(15)),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .into()
+    }
+}
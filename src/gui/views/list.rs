@@ -1,28 +1,41 @@
-use crate::core::sync::{action_handler, Phone, User};
+use crate::core::config::DeviceSettings;
+use crate::core::dependencies::DependencyGraph;
+use crate::core::error::AdbError;
+use crate::core::journal::JournalEntry;
+use crate::core::labels::{fetch_labels, AppLabel, LabelBatch};
+use crate::core::package_info::{get_package_details, PackageDetails};
+use crate::core::profile::Profile;
+use crate::core::queue::{run_job, ActionQueue, ExpectedState, Job, JobResult, JobStatus};
+use crate::core::recovery::{is_risky, save_restore_point, RestorePackage};
+use crate::core::sync::{action_handler, package_action, Phone, RebootTarget, User, UserTarget};
 use crate::core::theme::Theme;
 use crate::core::uad_lists::{
-    load_debloat_lists, Opposite, Package, PackageState, Removal, UadList, UadListState,
+    load_debloat_lists, Package, PackageState, Removal, UadList, UadListState,
 };
 use crate::core::utils::{
-    export_selection, fetch_packages, import_selection, perform_adb_commands,
-    update_selection_count,
+    cached_packages, fetch_all_packages, group_by_user, merge_package_rows,
+    select_profile_packages, shell_script, update_selection_count, PlannedAction,
 };
 use crate::gui::style;
 use std::collections::HashMap;
-use std::env;
+use std::time::Duration;
 
 use crate::gui::views::settings::Settings;
 use crate::gui::widgets::package_row::{Message as RowMessage, PackageRow};
 use iced::widget::{
-    button, column, container, pick_list, row, scrollable, text, text_input, Space,
+    button, checkbox, column, container, pick_list, row, scrollable, text, text_input, Space,
 };
-use iced::{Alignment, Command, Element, Length, Renderer};
+use iced::{clipboard, Alignment, Command, Element, Length, Renderer};
+
+// APKs pulled between two updates of the labels
+const LABEL_BATCH_SIZE: usize = 20;
 
 #[derive(Debug, Default, Clone)]
 pub struct Selection {
     pub uninstalled: u16,
     pub enabled: u16,
     pub disabled: u16,
+    pub suspended: u16,
     pub selected_packages: Vec<usize>, // phone_packages indexes (= what you've selected)
 }
 
@@ -39,13 +52,57 @@ pub enum LoadingState {
     FindingPhones,
     LoadingPackages,
     _UpdatingUad,
+    Rebooting(RebootTarget),
     Ready,
 }
 
+// Bulk action waiting for the user confirmation
+#[derive(Debug, Clone)]
+struct PendingAction {
+    action: Action,
+    actions: Vec<PlannedAction>,
+}
+
+impl PendingAction {
+    fn title(&self, settings: &Settings) -> String {
+        let device = &settings.device;
+        let verb = match &self.action {
+            Action::Remove if device.suspend_mode => "Suspend",
+            Action::Remove if device.disable_mode => "Disable",
+            Action::Remove => "Uninstall",
+            Action::Restore if device.suspend_mode || device.disable_mode => "Enable/Restore",
+            Action::Restore => "Restore",
+        };
+        format!("{} {} packages", verb, self.actions.len())
+    }
+}
+
+// Job of an action on a package for some users
+#[derive(Debug, Clone)]
+struct PlannedJob {
+    user: User,
+    settings: DeviceSettings,
+    users: Vec<usize>, // Users whose package state changes
+    commands: Vec<String>,
+}
+
+// Packages related to the last selected one that should probably be selected too
+#[derive(Debug, Clone)]
+struct DependencyNotice {
+    package: String,
+    user: usize,
+    related: Vec<usize>, // phone_packages indexes
+    restore: bool,
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct List {
     pub loading_state: LoadingState,
     pub uad_lists: HashMap<String, Package>,
+    dependency_graph: DependencyGraph,
+    dependency_notice: Option<DependencyNotice>,
+    pending_action: Option<PendingAction>,
+    queue: ActionQueue,
     phone_packages: Vec<Vec<PackageRow>>, // packages of all users of the phone
     filtered_packages: Vec<usize>, // phone_packages indexes of the selected user (= what you see on screen)
     pub selection: Selection,
@@ -53,27 +110,50 @@ pub struct List {
     selected_removal: Option<Removal>,
     selected_list: Option<UadList>,
     selected_user: Option<User>,
+    user_target: UserTarget,
+    chosen_users: Vec<u16>, // Users targeted by `UserTarget::Users`
     pub input_value: String,
     description: String,
     current_package_index: usize,
+    details: HashMap<String, Result<PackageDetails, AdbError>>, // Loaded when a package is pressed
+    labels: HashMap<String, AppLabel>, // Of `labels_device`, loaded in the background
+    labels_device: String,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     LoadUadList(bool),
     LoadPhonePackages((HashMap<String, Package>, UadListState)),
+    ReloadPackages,
+    /// Reads the packages again without resetting the list (filters, selection...)
+    RefreshPackages,
+    PackagesRefreshed(String, Vec<Vec<PackageRow>>),
     ApplyFilters(Vec<Vec<PackageRow>>),
     SearchInputChanged(String),
     ToggleAllSelected(bool),
     ListSelected(UadList),
     UserSelected(User),
+    UserTargetSelected(UserTarget),
+    ChosenUserToggled(u16, bool),
     PackageStateSelected(PackageState),
     RemovalSelected(Removal),
     ApplyActionOnSelection(Action),
-    ExportSelectionPressed,
+    ConfirmAction,
+    CancelAction,
+    CopyScript,
+    JobDone(JobResult),
+    PauseQueue,
+    ResumeQueue,
+    CancelQueue,
+    DismissQueue,
+    ApplyProfile(Profile),
+    SelectDependencies,
+    DismissDependencyNotice,
     List(usize, RowMessage),
-    ExportedSelection(Result<bool, String>),
-    ChangePackageState(Result<usize, ()>),
+    /// The package (index) has changed: its states read on the device (user index, state)
+    ChangePackageState(usize, Vec<(usize, PackageState)>),
+    DetailsLoaded(String, Result<PackageDetails, AdbError>),
+    LabelsLoaded(LabelBatch),
     Nothing,
 }
 
@@ -85,7 +165,7 @@ impl List {
         list_update_state: &mut UadListState,
         message: Message,
     ) -> Command<Message> {
-        let i_user = self.selected_user.unwrap_or(User { id: 0, index: 0 }).index;
+        let i_user = self.selected_user().index;
         match message {
             Message::LoadUadList(remote) => {
                 info!("{:-^65}", "-");
@@ -109,30 +189,111 @@ impl List {
                 }
                 if *list_update_state != UadListState::Done {
                     self.uad_lists = uad_list.clone();
+                    self.dependency_graph = DependencyGraph::new(&self.uad_lists);
                     *list_update_state = list_state;
+                    // The packages of the last time are shown while they are read again
+                    if let Some(packages) =
+                        cached_packages(&uad_list, selected_device, settings.general.third_party)
+                    {
+                        info!("Packages loaded from the cache, refreshing them");
+                        // The cached packages must be in place before they are refreshed
+                        let labels = self.update(
+                            settings,
+                            selected_device,
+                            list_update_state,
+                            Message::ApplyFilters(packages),
+                        );
+                        let refresh = self.update(
+                            settings,
+                            selected_device,
+                            list_update_state,
+                            Message::RefreshPackages,
+                        );
+                        return Command::batch([labels, refresh]);
+                    }
                     Command::perform(
-                        Self::load_packages(uad_list, selected_device.user_list.clone()),
+                        Self::load_packages(
+                            uad_list,
+                            selected_device.clone(),
+                            settings.general.third_party,
+                        ),
                         Message::ApplyFilters,
                     )
                 } else {
                     Command::none()
                 }
             }
+            Message::ReloadPackages => {
+                self.loading_state = LoadingState::LoadingPackages;
+                Command::perform(
+                    Self::load_packages(
+                        self.uad_lists.clone(),
+                        selected_device.clone(),
+                        settings.general.third_party,
+                    ),
+                    Message::ApplyFilters,
+                )
+            }
+            Message::RefreshPackages => {
+                // The queued jobs refer to the current indexes
+                if !self.queue.is_finished() {
+                    return Command::none();
+                }
+                let device_id = selected_device.adb_id.clone();
+                Command::perform(
+                    Self::load_packages(
+                        self.uad_lists.clone(),
+                        selected_device.clone(),
+                        settings.general.third_party,
+                    ),
+                    move |packages| Message::PackagesRefreshed(device_id, packages),
+                )
+            }
+            Message::PackagesRefreshed(device_id, packages) => {
+                // Another device may have been selected in the meantime
+                if device_id != selected_device.adb_id || !self.queue.is_finished() {
+                    return Command::none();
+                }
+                // The labels of the new packages are missing
+                if self.refresh_packages(packages, selected_device) == 0 {
+                    return Command::none();
+                }
+                Command::perform(
+                    fetch_labels(selected_device.adb_id.clone(), LABEL_BATCH_SIZE),
+                    Message::LabelsLoaded,
+                )
+            }
             Message::ApplyFilters(packages) => {
                 self.phone_packages = packages;
-                self.filtered_packages = (0..self.phone_packages[i_user].len()).collect();
+                // The indexes of the previous packages are meaningless now
+                self.selection = Selection::default();
+                self.pending_action = None;
+                self.details.clear();
+                // The user selected before may not exist on this device
+                self.selected_user = Some(
+                    selected_device
+                        .user_list
+                        .first()
+                        .cloned()
+                        .unwrap_or_default(),
+                );
+                self.filtered_packages =
+                    (0..self.phone_packages[self.selected_user().index].len()).collect();
                 self.selected_package_state = Some(PackageState::Enabled);
                 self.selected_removal = Some(Removal::Recommended);
                 self.selected_list = Some(UadList::All);
-                self.selected_user = Some(User { id: 0, index: 0 });
+                self.dependency_notice = None;
+                if self.labels_device != selected_device.adb_id {
+                    self.labels.clear();
+                    self.labels_device = selected_device.adb_id.clone();
+                }
+                self.apply_labels();
                 Self::filter_package_lists(self);
-
-                match import_selection(&mut self.phone_packages[i_user], &mut self.selection) {
-                    Ok(_) => info!("Custom selection has been successfully imported"),
-                    Err(err) => warn!("No custom selection imported: {}", err),
-                };
                 self.loading_state = LoadingState::Ready;
-                Command::none()
+                Command::perform(
+                    fetch_labels(selected_device.adb_id.clone(), LABEL_BATCH_SIZE),
+                    Message::LabelsLoaded,
+                )
             }
             Message::ToggleAllSelected(selected) => {
                 for i in self.filtered_packages.clone() {
@@ -207,42 +368,44 @@ impl List {
                                 package.selected,
                             );
                         }
+                        if self.phone_packages[i_user][i_package].selected {
+                            self.dependency_notice = self.find_dependency_notice(i_user, i_package);
+                        }
                         Command::none()
                     }
                     RowMessage::ActionPressed => {
-                        let mut commands = vec![];
-                        let actions = action_handler(
-                            &self.selected_user.unwrap(),
-                            &package.into(),
-                            selected_device,
-                            &settings.device,
-                        );
-
-                        for (i, action) in actions.into_iter().enumerate() {
-                            // Only the first command can change the package state
-                            commands.push(Command::perform(
-                                perform_adb_commands(
-                                    action,
-                                    i_package,
-                                    package.removal.to_string(),
-                                ),
-                                if i == 0 {
-                                    Message::ChangePackageState
-                                } else {
-                                    |_| Message::Nothing
-                                },
-                            ));
+                        self.enqueue(i_package, settings, selected_device);
+                        self.dependency_notice = self.find_dependency_notice(i_user, i_package);
+                        self.run_next_job()
+                    }
+                    RowMessage::TargetSelected(target) => {
+                        // Same removal action for every user
+                        let name = package.name.clone();
+                        for package in self
+                            .phone_packages
+                            .iter_mut()
+                            .filter_map(|packages| packages.get_mut(i_package))
+                            .filter(|p| p.name == name)
+                        {
+                            package.target = target.0;
                         }
-                        Command::batch(commands)
+                        Command::none()
                     }
                     RowMessage::PackagePressed => {
-                        self.description = package.clone().description;
                         package.current = true;
+                        let name = package.name.clone();
                         if self.current_package_index != i_package {
                             self.phone_packages[i_user][self.current_package_index].current = false;
                         }
                         self.current_package_index = i_package;
-                        Command::none()
+                        self.update_description(i_user);
+                        if self.details.contains_key(&name) {
+                            return Command::none();
+                        }
+                        Command::perform(
+                            get_package_details(selected_device.adb_id.clone(), name.clone()),
+                            move |res| Message::DetailsLoaded(name, res),
+                        )
                     }
                 }
             }
@@ -261,82 +424,213 @@ impl List {
                         });
                     }
                 }
-                let mut commands = vec![];
-                for i in selected_packages {
-                    let actions = action_handler(
-                        &self.selected_user.unwrap(),
-                        &(&self.phone_packages[i_user][i]).into(),
+                let actions: Vec<PlannedAction> = selected_packages
+                    .into_iter()
+                    .map(|i| {
+                        let package = &self.phone_packages[i_user][i];
+                        PlannedAction {
+                            index: i,
+                            package: package.name.clone(),
+                            removal: package.removal,
+                            commands: self
+                                .plan_jobs(i, selected_device, &settings.device)
+                                .into_iter()
+                                .flat_map(|job| job.commands)
+                                .collect(),
+                        }
+                    })
+                    .collect();
+                if !actions.is_empty() {
+                    self.pending_action = Some(PendingAction { action, actions });
+                }
+                Command::none()
+            }
+            Message::ConfirmAction => {
+                if let Some(pending) = self.pending_action.take() {
+                    for planned in pending.actions {
+                        self.enqueue(planned.index, settings, selected_device);
+                    }
+                }
+                self.run_next_job()
+            }
+            Message::JobDone(result) => {
+                self.queue.finish(&result);
+                // Also when the package is not in the expected state
+                if !result.states.is_empty() {
+                    let _ = self.update(
+                        settings,
                         selected_device,
-                        &settings.device,
+                        list_update_state,
+                        Message::ChangePackageState(result.index, result.states),
                     );
-                    for (j, action) in actions.into_iter().enumerate() {
-                        // Only the first command can change the package state
-                        commands.push(Command::perform(
-                            perform_adb_commands(
-                                action,
-                                i,
-                                self.phone_packages[i_user][i].removal.to_string(),
-                            ),
-                            if j == 0 {
-                                Message::ChangePackageState
-                            } else {
-                                |_| Message::Nothing
-                            },
-                        ));
+                }
+                self.run_next_job()
+            }
+            Message::PauseQueue => {
+                self.queue.pause();
+                Command::none()
+            }
+            Message::ResumeQueue => {
+                self.queue.resume();
+                self.run_next_job()
+            }
+            Message::CancelQueue => {
+                self.queue.cancel();
+                Command::none()
+            }
+            Message::DismissQueue => {
+                if self.queue.is_finished() {
+                    self.queue = ActionQueue::default();
+                }
+                Command::none()
+            }
+            Message::CancelAction => {
+                self.pending_action = None;
+                Command::none()
+            }
+            Message::CopyScript => match &self.pending_action {
+                Some(pending) => clipboard::write(shell_script(
+                    selected_device,
+                    &pending.title(settings),
+                    &pending.actions,
+                )),
+                None => Command::none(),
+            },
+            Message::ApplyProfile(profile) => {
+                select_profile_packages(
+                    &mut self.phone_packages[i_user],
+                    &mut self.selection,
+                    &profile,
+                    selected_device.android_sdk,
+                );
+                info!(
+                    "Profile `{}` loaded: {} packages selected",
+                    profile.name,
+                    self.selection.selected_packages.len()
+                );
+                Self::filter_package_lists(self);
+                Command::none()
+            }
+            Message::SelectDependencies => {
+                if let Some(notice) = self.dependency_notice.take() {
+                    for i in notice.related {
+                        let package = &mut self.phone_packages[notice.user][i];
+                        if package.selected
+                            || (package.removal == Removal::Unsafe && !settings.general.expert_mode)
+                        {
+                            continue;
+                        }
+                        package.selected = true;
+                        self.selection.selected_packages.push(i);
+                        update_selection_count(&mut self.selection, package.state, true);
                     }
                 }
-                Command::batch(commands)
+                Command::none()
             }
-            Message::ExportSelectionPressed => Command::perform(
-                export_selection(self.phone_packages[i_user].clone()),
-                Message::ExportedSelection,
-            ),
-            Message::ExportedSelection(export) => {
-                match export {
-                    Ok(_) => info!("Selection exported"),
-                    Err(err) => error!("Selection export: {}", err),
-                };
+            Message::DismissDependencyNotice => {
+                self.dependency_notice = None;
                 Command::none()
             }
             Message::UserSelected(user) => {
+                self.dependency_notice = None;
                 for p in &mut self.phone_packages[i_user] {
                     p.selected = false;
                 }
-                self.selected_user = Some(user);
                 for i_package in &self.selection.selected_packages {
                     self.phone_packages[user.index][*i_package].selected = true;
                 }
                 self.filtered_packages = (0..self.phone_packages[user.index].len()).collect();
+                self.selected_user = Some(user);
                 Self::filter_package_lists(self);
                 Command::none()
             }
-            Message::ChangePackageState(res) => {
-                if let Ok(i) = res {
-                    let package = &mut self.phone_packages[i_user][i];
-                    update_selection_count(&mut self.selection, package.state, false);
-
-                    if !settings.device.multi_user_mode {
-                        package.state = package.state.opposite(settings.device.disable_mode);
-                        package.selected = false;
-                    } else {
-                        for u in &selected_device.user_list {
-                            self.phone_packages[u.index][i].state = self.phone_packages[u.index][i]
-                                .state
-                                .opposite(settings.device.disable_mode);
-                            self.phone_packages[u.index][i].selected = false;
-                        }
-                    }
-                    self.selection
-                        .selected_packages
-                        .drain_filter(|s_i| *s_i == i);
-                    Self::filter_package_lists(self);
+            Message::UserTargetSelected(target) => {
+                self.user_target = match target {
+                    UserTarget::Users(_) => UserTarget::Users(self.chosen_users.clone()),
+                    target => target,
+                };
+                Command::none()
+            }
+            Message::ChosenUserToggled(id, chosen) => {
+                self.chosen_users.retain(|u| *u != id);
+                if chosen {
+                    self.chosen_users.push(id);
+                }
+                self.user_target = UserTarget::Users(self.chosen_users.clone());
+                Command::none()
+            }
+            Message::ChangePackageState(i, states) => {
+                // An action on several users changes the package state several times
+                if self.selection.selected_packages.contains(&i) {
+                    update_selection_count(
+                        &mut self.selection,
+                        self.phone_packages[i_user][i].state,
+                        false,
+                    );
+                }
+                for (u, state) in states {
+                    let package = &mut self.phone_packages[u][i];
+                    package.state = state;
+                    package.selected = false;
+                }
+                self.phone_packages[i_user][i].selected = false;
+                self.selection
+                    .selected_packages
+                    .drain_filter(|s_i| *s_i == i);
+                Self::filter_package_lists(self);
+                Command::none()
+            }
+            Message::DetailsLoaded(package, res) => {
+                if let Err(e) = &res {
+                    warn!("Could not read the details of {}: {}", package, e);
                 }
+                self.details.insert(package, res);
+                self.update_description(i_user);
                 Command::none()
             }
+            Message::LabelsLoaded(batch) => {
+                // The device may have changed in the meantime
+                if batch.device_id != self.labels_device {
+                    return Command::none();
+                }
+                self.labels.extend(batch.labels);
+                self.apply_labels();
+                Self::filter_package_lists(self);
+                if batch.remaining > 0 {
+                    Command::perform(
+                        fetch_labels(batch.device_id, LABEL_BATCH_SIZE),
+                        Message::LabelsLoaded,
+                    )
+                } else {
+                    Command::none()
+                }
+            }
             Message::Nothing => Command::none(),
         }
     }
 
+    // Description of the current package, followed by its details once they are loaded
+    fn update_description(&mut self, i_user: usize) {
+        let package = match self
+            .phone_packages
+            .get(i_user)
+            .and_then(|p| p.get(self.current_package_index))
+        {
+            Some(package) => package,
+            None => return,
+        };
+        let details = match self.details.get(&package.name) {
+            Some(Ok(details)) => details.to_string(),
+            Some(Err(e)) => format!("Could not read the details: {}", e),
+            None => "Loading details...".to_string(),
+        };
+        self.description = format!(
+            "{}\n\n{}",
+            describe(&self.dependency_graph, package),
+            details
+        );
+    }
+
     pub fn view(
         &self,
         settings: &Settings,
@@ -355,10 +649,28 @@ impl List {
                 let text = "Pulling packages from the device. Please wait...";
                 waiting_view(settings, text, false)
             }
+            LoadingState::Rebooting(target) if target.comes_back() => {
+                let text = format!(
+                    "Rebooting into {}. Waiting for the device to come back...",
+                    target
+                );
+                waiting_view(settings, &text, false)
+            }
+            LoadingState::Rebooting(target) => {
+                let text = format!(
+                    "The device has been rebooted into {}. UAD will reconnect to it once it's \
+                    rebooted to the system.",
+                    target
+                );
+                waiting_view(settings, &text, false)
+            }
             LoadingState::_UpdatingUad => {
                 let text = "Updating UAD. Please wait...";
                 waiting_view(settings, text, false)
             }
+            LoadingState::Ready if self.pending_action.is_some() => {
+                self.confirmation_view(self.pending_action.as_ref().unwrap(), settings)
+            }
             LoadingState::Ready => {
                 let search_packages = text_input(
                     "Search packages...",
@@ -371,10 +683,21 @@ impl List {
 
                 let user_picklist = pick_list(
                     selected_device.user_list.clone(),
-                    self.selected_user,
+                    self.selected_user.clone(),
                     Message::UserSelected,
                 )
-                .width(Length::Units(85));
+                .width(Length::Units(250));
+
+                // The chosen users are only shown when they are the target
+                let shown_target = match &self.user_target {
+                    UserTarget::Users(_) => UserTarget::Users(vec![]),
+                    target => target.clone(),
+                };
+                let target_picklist = pick_list(
+                    UserTarget::ALL.to_vec(),
+                    Some(shown_target),
+                    Message::UserTargetSelected,
+                );
 
                 let divider = Space::new(Length::Fill, Length::Shrink);
 
@@ -395,6 +718,8 @@ impl List {
                 let control_panel = row![
                     search_packages,
                     user_picklist,
+                    text("Actions on").style(style::Text::Commentary),
+                    target_picklist,
                     divider,
                     removal_picklist,
                     package_state_picklist,
@@ -410,7 +735,7 @@ impl List {
                         .iter()
                         .fold(column![].spacing(6), |col, i| {
                             col.push(
-                                self.phone_packages[self.selected_user.unwrap().index][*i]
+                                self.phone_packages[self.selected_user().index][*i]
                                     .view(settings, selected_device)
                                     .map(move |msg| Message::List(*i, msg)),
                             )
@@ -432,19 +757,23 @@ impl List {
                     .width(Length::Fill)
                     .style(style::Container::Frame);
 
-                let restore_action = match settings.device.disable_mode {
-                    true => "Enable/Restore",
-                    false => "Restore",
-                };
-                let remove_action = match settings.device.disable_mode {
-                    true => "Disable",
-                    false => "Uninstall",
+                let restore_action =
+                    match settings.device.disable_mode || settings.device.suspend_mode {
+                        true => "Enable/Restore",
+                        false => "Restore",
+                    };
+                let remove_action = if settings.device.suspend_mode {
+                    "Suspend"
+                } else if settings.device.disable_mode {
+                    "Disable"
+                } else {
+                    "Uninstall"
                 };
 
                 let apply_restore_selection = button(text(format!(
                     "{} selection ({})",
                     restore_action,
-                    self.selection.uninstalled + self.selection.disabled
+                    self.selection.uninstalled + self.selection.disabled + self.selection.suspended
                 )))
                 .on_press(Message::ApplyActionOnSelection(Action::Restore))
                 .padding(5)
@@ -468,19 +797,10 @@ impl List {
                     .on_press(Message::ToggleAllSelected(false))
                     .style(style::Button::Primary);
 
-                let export_selection_btn = button(text(format!(
-                    "Export current selection ({})",
-                    self.selection.selected_packages.len()
-                )))
-                .padding(5)
-                .on_press(Message::ExportSelectionPressed)
-                .style(style::Button::Primary);
-
                 let action_row = row![
                     select_all_btn,
                     unselect_all_btn,
                     Space::new(Length::Fill, Length::Shrink),
-                    export_selection_btn,
                     apply_restore_selection,
                     apply_remove_selection,
                 ]
@@ -488,34 +808,488 @@ impl List {
                 .spacing(10)
                 .align_items(Alignment::Center);
 
-                let content = column![
-                    control_panel,
-                    packages_scrollable,
-                    description_panel,
-                    action_row,
-                ]
-                .width(Length::Fill)
-                .spacing(10)
-                .align_items(Alignment::Center);
+                let mut content = column![control_panel]
+                    .width(Length::Fill)
+                    .spacing(10)
+                    .align_items(Alignment::Center);
+                if let UserTarget::Users(chosen) = &self.user_target {
+                    content = content.push(self.chosen_users_row(chosen, selected_device));
+                }
+                let mut content = content.push(packages_scrollable).push(description_panel);
+
+                if let Some(notice) = &self.dependency_notice {
+                    content = content.push(self.dependency_notice_row(notice));
+                }
+                if self.queue.progress().1 > 0 {
+                    content = content.push(self.queue_panel());
+                }
+                let content = content.push(action_row);
 
                 container(content).height(Length::Fill).padding(10).into()
             }
         }
     }
 
+    /// Jobs of an action on a package: one per targeted user, or a single one for every
+    /// user with the multi-user mode. Users whose package isn't in the same state as for
+    /// the selected user are skipped (the action would do the opposite for them).
+    fn plan_jobs(
+        &self,
+        i_package: usize,
+        phone: &Phone,
+        settings: &DeviceSettings,
+    ) -> Vec<PlannedJob> {
+        let selected_user = self.selected_user();
+        if self.user_target == UserTarget::Selected {
+            let package = &self.phone_packages[selected_user.index][i_package];
+            let users = if settings.multi_user_mode && !phone.user_list.is_empty() {
+                phone.user_list.iter().map(|u| u.index).collect()
+            } else {
+                vec![selected_user.index]
+            };
+            return vec![PlannedJob {
+                commands: action_handler(&selected_user, &package.into(), phone, settings),
+                user: selected_user,
+                settings: settings.clone(),
+                users,
+            }];
+        }
+
+        let state = self.phone_packages[selected_user.index][i_package].state;
+        // Each user has its own job and journal entry
+        let settings = DeviceSettings {
+            multi_user_mode: false,
+            ..settings.clone()
+        };
+        self.user_target
+            .users(&selected_user, phone, &settings)
+            .into_iter()
+            .filter_map(|user| {
+                let package = self.phone_packages.get(user.index)?.get(i_package)?;
+                if package.state != state {
+                    return None;
+                }
+                Some(PlannedJob {
+                    commands: action_handler(&user, &package.into(), phone, &settings),
+                    users: vec![user.index],
+                    user,
+                    settings: settings.clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn enqueue(&mut self, i_package: usize, settings: &Settings, phone: &Phone) {
+        if self.queue.is_queued(i_package) {
+            return;
+        }
+        let jobs = self.plan_jobs(i_package, phone, &settings.device);
+        if jobs.is_empty() {
+            warn!(
+                "No targeted user for {}",
+                self.phone_packages[self.selected_user().index][i_package].name
+            );
+        }
+        for job in jobs {
+            let package = &self.phone_packages[job.user.index][i_package];
+            let journal_entry = JournalEntry::new(
+                phone,
+                &job.user,
+                &package.into(),
+                &job.settings,
+                &job.commands,
+            );
+            // In case the device doesn't boot anymore
+            if package.state == PackageState::Enabled && is_risky(package.removal) {
+                save_restore_point(
+                    phone,
+                    vec![RestorePackage::new(phone, package.removal, &journal_entry)],
+                );
+            }
+            self.queue.push(Job {
+                device_id: phone.adb_id.clone(),
+                index: i_package,
+                users: self.expected_states(i_package, &job, phone),
+                package: package.name.clone(),
+                removal: package.removal,
+                commands: job.commands,
+                journal_entry: Some(journal_entry),
+            });
+        }
+    }
+
+    /// State of the package for the users of the job once it's done
+    fn expected_states(
+        &self,
+        i_package: usize,
+        job: &PlannedJob,
+        phone: &Phone,
+    ) -> Vec<ExpectedState> {
+        // The commands come from the row of the user of the job and are sent for every user
+        let package = &self.phone_packages[job.user.index][i_package];
+        let state = package_action(&job.user, &package.into(), phone, &job.settings).1;
+        ExpectedState::for_users(phone, &job.users, state)
+    }
+
+    fn chosen_users_row(&self, chosen: &[u16], phone: &Phone) -> Element<Message, Renderer<Theme>> {
+        phone
+            .user_list
+            .iter()
+            .fold(
+                row![text("Users:").style(style::Text::Commentary)],
+                |row, user| {
+                    let id = user.id;
+                    row.push(
+                        checkbox(user.to_string(), chosen.contains(&id), move |c| {
+                            Message::ChosenUserToggled(id, c)
+                        })
+                        .style(style::CheckBox::SettingsEnabled),
+                    )
+                },
+            )
+            .spacing(15)
+            .width(Length::Fill)
+            .align_items(Alignment::Center)
+            .into()
+    }
+
+    fn run_next_job(&mut self) -> Command<Message> {
+        match self.queue.next_job() {
+            Some((id, job)) => Command::perform(run_job(id, job), Message::JobDone),
+            None => Command::none(),
+        }
+    }
+
+    fn queue_panel(&self) -> Element<Message, Renderer<Theme>> {
+        let (processed, total) = self.queue.progress();
+        let failed = self.queue.failures();
+
+        let status = if self.queue.is_finished() {
+            format!(
+                "Done: {} succeeded, {} failed, {} cancelled",
+                self.queue.count(&JobStatus::Done),
+                failed.len(),
+                self.queue.count(&JobStatus::Cancelled)
+            )
+        } else {
+            let mut status = if self.queue.is_paused() {
+                format!("Paused ({}/{})", processed, total)
+            } else {
+                format!("Processing {}/{}", processed + 1, total)
+            };
+            if let Some(job) = self.queue.current() {
+                status += &format!(": {}", job.package);
+            }
+            if let Some(eta) = self.queue.eta() {
+                status += &format!(" | ETA {}", format_duration(eta));
+            }
+            if !failed.is_empty() {
+                status += &format!(" | {} failed", failed.len());
+            }
+            status
+        };
+
+        let buttons = if self.queue.is_finished() {
+            row![button("Dismiss")
+                .on_press(Message::DismissQueue)
+                .padding(5)
+                .style(style::Button::Primary)]
+        } else {
+            let pause_btn = if self.queue.is_paused() {
+                button("Resume").on_press(Message::ResumeQueue)
+            } else {
+                button("Pause").on_press(Message::PauseQueue)
+            };
+            row![
+                pause_btn.padding(5).style(style::Button::Primary),
+                button("Cancel")
+                    .on_press(Message::CancelQueue)
+                    .padding(5)
+                    .style(style::Button::UninstallPackage),
+            ]
+        };
+
+        let failures = failed
+            .iter()
+            .fold(column![].spacing(2), |col, (package, e)| {
+                col.push(text(format!("{}: {}", package, e)).style(style::Text::Danger))
+            });
+
+        container(
+            column![
+                row![text(status).width(Length::Fill), buttons.spacing(10)]
+                    .spacing(10)
+                    .align_items(Alignment::Center),
+                failures,
+            ]
+            .spacing(5),
+        )
+        .padding(5)
+        .width(Length::Fill)
+        .style(style::Container::BorderedFrame)
+        .into()
+    }
+
+    fn confirmation_view<'a>(
+        &'a self,
+        pending: &'a PendingAction,
+        settings: &Settings,
+    ) -> Element<'a, Message, Renderer<Theme>> {
+        let header = row![
+            text(format!("{}?", pending.title(settings))).size(20),
+            Space::new(Length::Fill, Length::Shrink),
+            button("Copy as shell script")
+                .on_press(Message::CopyScript)
+                .padding(5)
+                .style(style::Button::Primary),
+            button("Cancel")
+                .on_press(Message::CancelAction)
+                .padding(5)
+                .style(style::Button::Primary),
+            button("Confirm")
+                .on_press(Message::ConfirmAction)
+                .padding(5)
+                .style(style::Button::Primary),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let actions = pending
+            .actions
+            .iter()
+            .fold(column![].spacing(10), |col, a| {
+                let users = group_by_user(&a.commands).into_iter().fold(
+                    column![].spacing(2),
+                    |col, (user, commands)| {
+                        let col = match user {
+                            Some(id) => col.push(text(format!("User {}", id))),
+                            None => col,
+                        };
+                        commands.into_iter().fold(col, |col, c| {
+                            col.push(
+                                text(format!("    adb shell {}", c)).style(style::Text::Commentary),
+                            )
+                        })
+                    },
+                );
+                col.push(column![
+                    row![
+                        text(&a.package).width(Length::Fill),
+                        text(a.removal.to_string()).width(Length::Units(120)),
+                    ]
+                    .padding([0, 16, 0, 0]),
+                    users,
+                ])
+            });
+
+        let actions = container(
+            scrollable(actions)
+                .scrollbar_margin(2)
+                .style(style::Scrollable::Packages),
+        )
+        .padding(10)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(style::Container::Frame);
+
+        container(column![header, actions].spacing(10))
+            .height(Length::Fill)
+            .padding(10)
+            .into()
+    }
+
+    /// Installed packages that may break if the package is removed, or the missing
+    /// packages it needs if it is restored
+    fn find_dependency_notice(&self, i_user: usize, i_package: usize) -> Option<DependencyNotice> {
+        let packages = &self.phone_packages[i_user];
+        let package = &packages[i_package];
+        let restore = package.state != PackageState::Enabled;
+        let names = if restore {
+            self.dependency_graph.dependencies(&package.name)
+        } else {
+            self.dependency_graph.dependents(&package.name)
+        };
+        let related: Vec<usize> = names
+            .iter()
+            .filter_map(|name| packages.iter().position(|p| p.name == *name))
+            .filter(|i| {
+                !packages[*i].selected && (packages[*i].state == PackageState::Enabled) != restore
+            })
+            .collect();
+
+        (!related.is_empty()).then(|| DependencyNotice {
+            package: package.name.clone(),
+            user: i_user,
+            related,
+            restore,
+        })
+    }
+
+    fn dependency_notice_row(
+        &self,
+        notice: &DependencyNotice,
+    ) -> Element<Message, Renderer<Theme>> {
+        let names = notice
+            .related
+            .iter()
+            .map(|i| self.phone_packages[notice.user][*i].name.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        let message = if notice.restore {
+            format!(
+                "{} needs packages that are not installed: {}",
+                notice.package, names
+            )
+        } else {
+            format!(
+                "{} is needed by installed packages that may stop working without it: {}",
+                notice.package, names
+            )
+        };
+
+        row![
+            text(message).width(Length::Fill).style(style::Text::Danger),
+            button("Select them too")
+                .on_press(Message::SelectDependencies)
+                .padding(5)
+                .style(style::Button::Primary),
+            button("Dismiss")
+                .on_press(Message::DismissDependencyNotice)
+                .padding(5)
+                .style(style::Button::Primary),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center)
+        .into()
+    }
+
+    /// Selected packages of the current user
+    pub fn selected_packages(&self) -> Vec<&PackageRow> {
+        let user = self.selected_user();
+        self.selection
+            .selected_packages
+            .iter()
+            .filter_map(|i| self.phone_packages.get(user.index)?.get(*i))
+            .collect()
+    }
+
+    pub fn selected_user(&self) -> User {
+        self.selected_user.clone().unwrap_or_default()
+    }
+
+    /// Replaces the packages by the ones just fetched. The filters, the selection and the
+    /// highlighted package are kept (a package that is gone is dropped from them).
+    /// Returns the number of new packages.
+    fn refresh_packages(&mut self, mut packages: Vec<Vec<PackageRow>>, phone: &Phone) -> usize {
+        let previous_user = self.selected_user();
+        let user = phone
+            .user_list
+            .iter()
+            .find(|u| u.id == previous_user.id)
+            .or_else(|| phone.user_list.first())
+            .cloned()
+            .unwrap_or_default();
+        let i_user = user.index;
+        self.selected_user = Some(user);
+        // e.g. a user has been created: the rows of each user can't be matched anymore and
+        // the selected user (if still there) may have moved
+        let same_users =
+            packages.len() == self.phone_packages.len() && i_user == previous_user.index;
+
+        let mut moves = vec![];
+        for (u, fresh) in packages.iter_mut().enumerate() {
+            let previous = match same_users {
+                true => u,
+                false => previous_user.index,
+            };
+            let rows = self
+                .phone_packages
+                .get(previous)
+                .map_or(&[][..], Vec::as_slice);
+            let user_moves = merge_package_rows(rows, fresh);
+            if u == i_user {
+                moves = user_moves;
+            } else if !same_users {
+                // Only the rows of the selected user are selected
+                for package in fresh.iter_mut() {
+                    package.selected = false;
+                    package.current = false;
+                }
+            }
+        }
+        let new_index = |i: usize| moves.get(i).copied().flatten();
+        let removed = moves.iter().filter(|m| m.is_none()).count();
+        let added = packages[i_user].len() - (moves.len() - removed);
+        info!("Packages refreshed: {} added, {} removed", added, removed);
+
+        let selected: Vec<usize> = self
+            .selection
+            .selected_packages
+            .iter()
+            .filter_map(|i| new_index(*i))
+            .collect();
+        let current = new_index(self.current_package_index);
+        self.phone_packages = packages;
+
+        // The state of a selected package may have changed
+        self.selection = Selection::default();
+        for i in selected {
+            self.selection.selected_packages.push(i);
+            update_selection_count(
+                &mut self.selection,
+                self.phone_packages[i_user][i].state,
+                true,
+            );
+        }
+        // They refer to the previous indexes
+        self.pending_action = None;
+        self.dependency_notice = None;
+
+        self.apply_labels();
+        self.filter_package_lists();
+        match current {
+            Some(i) => {
+                self.current_package_index = i;
+                // Its state may have changed
+                if self.phone_packages[i_user][i].current {
+                    self.update_description(i_user);
+                }
+            }
+            None => {
+                self.current_package_index = 0;
+                self.description.clear();
+            }
+        }
+        added
+    }
+
+    fn apply_labels(&mut self) {
+        for package in self.phone_packages.iter_mut().flatten() {
+            if let Some(label) = self.labels.get(&package.name) {
+                package.label = label.label.clone();
+                package.icon = label.icon.clone();
+            }
+        }
+    }
+
     fn filter_package_lists(&mut self) {
         let list_filter: UadList = self.selected_list.unwrap();
         let package_filter: PackageState = self.selected_package_state.unwrap();
         let removal_filter: Removal = self.selected_removal.unwrap();
+        let search = self.input_value.to_lowercase();
 
-        self.filtered_packages = self.phone_packages[self.selected_user.unwrap().index]
+        self.filtered_packages = self.phone_packages[self.selected_user().index]
             .iter()
             .enumerate()
             .filter(|(_, p)| {
                 (list_filter == UadList::All || p.uad_list == list_filter)
                     && (package_filter == PackageState::All || p.state == package_filter)
                     && (removal_filter == Removal::All || p.removal == removal_filter)
-                    && (self.input_value.is_empty() || p.name.contains(&self.input_value))
+                    && (search.is_empty()
+                        || p.name.contains(&self.input_value)
+                        || p.label
+                            .as_ref()
+                            .is_some_and(|l| l.to_lowercase().contains(&search)))
             })
             .map(|(i, _)| i)
             .collect();
@@ -523,20 +1297,10 @@ impl List {
 
     async fn load_packages(
         uad_list: HashMap<String, Package>,
-        user_list: Vec<User>,
+        phone: Phone,
+        third_party: bool,
     ) -> Vec<Vec<PackageRow>> {
-        let mut phone_packages = vec![];
-
-        if user_list.len() <= 1 {
-            phone_packages.push(fetch_packages(&uad_list, None))
-        } else {
-            phone_packages.extend(
-                user_list
-                    .iter()
-                    .map(|user| fetch_packages(&uad_list, Some(user))),
-            )
-        };
-        phone_packages
+        fetch_all_packages(&uad_list, &phone, third_party)
     }
 
     async fn init_apps_view(
@@ -546,7 +1310,6 @@ impl List {
         let (uad_lists, _) = load_debloat_lists(remote);
         match uad_lists {
             Ok(list) => {
-                env::set_var("ANDROID_SERIAL", phone.adb_id.clone());
                 if phone.adb_id.is_empty() {
                     error!("AppsView ready but no phone found");
                 }
@@ -560,6 +1323,34 @@ impl List {
     }
 }
 
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+// Description of the package along with its relationships
+fn describe(graph: &DependencyGraph, package: &PackageRow) -> String {
+    let mut description = package.description.clone();
+    let dependencies = graph.direct_dependencies(&package.name);
+    if !dependencies.is_empty() {
+        description += &format!("\n\nDepends on: {}", dependencies.join(", "));
+    }
+    let dependents = graph.direct_dependents(&package.name);
+    if !dependents.is_empty() {
+        let separator = if dependencies.is_empty() {
+            "\n\n"
+        } else {
+            "\n"
+        };
+        description += &format!("{}Needed by: {}", separator, dependents.join(", "));
+    }
+    description
+}
+
 fn waiting_view<'a>(
     _settings: &Settings,
     displayed_text: &str,
@@ -591,3 +1382,498 @@ fn waiting_view<'a>(
         .style(style::Container::default())
         .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::GeneralSettings;
+    use crate::core::mock;
+    use crate::gui::widgets::package_row::RemovalTarget;
+
+    fn settings(disable_mode: bool, multi_user_mode: bool) -> Settings {
+        Settings {
+            general: GeneralSettings::default(),
+            device: mock::settings(disable_mode, multi_user_mode),
+        }
+    }
+
+    // Users 0 and 10
+    fn phone(android_sdk: u8) -> Phone {
+        mock::phone(android_sdk, &[0, 10])
+    }
+
+    fn packages(state: PackageState) -> Vec<PackageRow> {
+        ["com.bar", "com.foo"]
+            .iter()
+            .map(|name| {
+                PackageRow::new(
+                    name,
+                    state,
+                    "",
+                    UadList::Oem,
+                    Removal::Recommended,
+                    false,
+                    false,
+                )
+            })
+            .collect()
+    }
+
+    // Loads the packages of the 2 users, selects `com.foo` and simulates a successful action
+    fn change_state(settings: &mut Settings, state: PackageState) -> List {
+        change_state_for(settings, state, UserTarget::Selected)
+    }
+
+    fn verified_states(list: &List, job: &PlannedJob, phone: &Phone) -> Vec<(usize, PackageState)> {
+        list.expected_states(1, job, phone)
+            .into_iter()
+            .map(|e| (e.index, e.state))
+            .collect()
+    }
+
+    fn change_state_for(settings: &mut Settings, state: PackageState, target: UserTarget) -> List {
+        let mut list = List {
+            user_target: target,
+            ..List::default()
+        };
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let _ = list.update(
+            settings,
+            &mut phone,
+            &mut list_state,
+            Message::ApplyFilters(vec![packages(state), packages(state)]),
+        );
+        let _ = list.update(
+            settings,
+            &mut phone,
+            &mut list_state,
+            Message::List(1, RowMessage::ToggleSelection(true)),
+        );
+        assert_eq!(list.selection.selected_packages, vec![1]);
+
+        // The device does what's expected
+        for job in list.plan_jobs(1, &phone, &settings.device) {
+            let states = verified_states(&list, &job, &phone);
+            let _ = list.update(
+                settings,
+                &mut phone,
+                &mut list_state,
+                Message::ChangePackageState(1, states),
+            );
+        }
+        assert!(list.selection.selected_packages.is_empty());
+        list
+    }
+
+    #[test]
+    fn test_change_package_state_single_user() {
+        let list = change_state(&mut settings(false, false), PackageState::Enabled);
+        assert_eq!(list.phone_packages[0][1].state, PackageState::Uninstalled);
+        assert_eq!(list.phone_packages[1][1].state, PackageState::Enabled);
+        assert_eq!(list.phone_packages[0][0].state, PackageState::Enabled);
+        assert!(!list.phone_packages[0][1].selected);
+    }
+
+    #[test]
+    fn test_change_package_state_multi_user() {
+        let list = change_state(&mut settings(true, true), PackageState::Enabled);
+        assert_eq!(list.phone_packages[0][1].state, PackageState::Disabled);
+        assert_eq!(list.phone_packages[1][1].state, PackageState::Disabled);
+    }
+
+    #[test]
+    fn test_change_package_state_targeted_users() {
+        let list = change_state_for(
+            &mut settings(false, true),
+            PackageState::Enabled,
+            UserTarget::Users(vec![10]),
+        );
+        assert_eq!(list.phone_packages[0][1].state, PackageState::Enabled);
+        assert_eq!(list.phone_packages[1][1].state, PackageState::Uninstalled);
+        assert_eq!(list.selection.enabled, 0);
+
+        let mut phone = phone(30);
+        let list = List {
+            user_target: UserTarget::Users(vec![0, 10]),
+            selected_user: Some(phone.user_list[0].clone()),
+            phone_packages: vec![
+                packages(PackageState::Enabled),
+                packages(PackageState::Uninstalled),
+            ],
+            ..List::default()
+        };
+        // The package is already uninstalled for user 10
+        let jobs = list.plan_jobs(1, &phone, &settings(false, false).device);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].commands, vec!["pm uninstall --user 0 com.foo"]);
+        phone.user_list[1].flags = 0x1030;
+        let list = List {
+            user_target: UserTarget::WorkProfiles,
+            ..list
+        };
+        assert!(list
+            .plan_jobs(1, &phone, &settings(false, false).device)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_change_package_state_restore() {
+        let list = change_state(&mut settings(true, false), PackageState::Disabled);
+        assert_eq!(list.phone_packages[0][1].state, PackageState::Enabled);
+
+        let list = change_state(&mut settings(false, true), PackageState::Uninstalled);
+        assert_eq!(list.phone_packages[0][1].state, PackageState::Enabled);
+        assert_eq!(list.phone_packages[1][1].state, PackageState::Enabled);
+    }
+
+    #[test]
+    fn test_change_package_state_users_in_other_states() {
+        let mut settings = settings(false, true);
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let mut list = List::default();
+        // Already uninstalled for user 10
+        let messages = [
+            Message::ApplyFilters(vec![
+                packages(PackageState::Enabled),
+                packages(PackageState::Uninstalled),
+            ]),
+            Message::List(1, RowMessage::ToggleSelection(true)),
+        ];
+        for message in messages {
+            let _ = list.update(&mut settings, &mut phone, &mut list_state, message);
+        }
+        let jobs = list.plan_jobs(1, &phone, &settings.device);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(
+            jobs[0].commands,
+            vec![
+                "pm uninstall --user 0 com.foo",
+                "pm uninstall --user 10 com.foo"
+            ]
+        );
+        assert_eq!(
+            verified_states(&list, &jobs[0], &phone),
+            vec![
+                (0, PackageState::Uninstalled),
+                (1, PackageState::Uninstalled)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_package_state_per_sdk() {
+        let cases = [
+            (30, None, PackageState::Disabled),
+            (30, Some(PackageState::Suspended), PackageState::Suspended),
+            (26, None, PackageState::Disabled),
+            // Packages can't be suspended before Android 9
+            (26, Some(PackageState::Suspended), PackageState::Disabled),
+            // Packages are hidden on Android 5.x (disable mode is unavailable)
+            (22, None, PackageState::Uninstalled),
+            (22, Some(PackageState::Suspended), PackageState::Uninstalled),
+        ];
+        for (sdk, target, expected) in cases {
+            let mut settings = settings(true, false);
+            let mut phone = phone(sdk);
+            let mut list_state = UadListState::Done;
+            let mut list = List::default();
+            let messages = [
+                Message::ApplyFilters(vec![
+                    packages(PackageState::Enabled),
+                    packages(PackageState::Enabled),
+                ]),
+                Message::List(1, RowMessage::ToggleSelection(true)),
+                Message::List(1, RowMessage::TargetSelected(RemovalTarget(target))),
+            ];
+            for message in messages {
+                let _ = list.update(&mut settings, &mut phone, &mut list_state, message);
+            }
+            for job in list.plan_jobs(1, &phone, &settings.device) {
+                let states = verified_states(&list, &job, &phone);
+                let _ = list.update(
+                    &mut settings,
+                    &mut phone,
+                    &mut list_state,
+                    Message::ChangePackageState(1, states),
+                );
+            }
+            assert_eq!(
+                list.phone_packages[0][1].state, expected,
+                "SDK {} / {:?}",
+                sdk, target
+            );
+            assert_eq!(list.phone_packages[1][1].state, PackageState::Enabled);
+        }
+    }
+
+    #[test]
+    fn test_change_package_state_read_on_device() {
+        let mut settings = settings(false, true);
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let mut list = List::default();
+        let messages = [
+            Message::ApplyFilters(vec![
+                packages(PackageState::Enabled),
+                packages(PackageState::Enabled),
+            ]),
+            Message::List(1, RowMessage::ToggleSelection(true)),
+            // The package was not removed for the second user
+            Message::ChangePackageState(
+                1,
+                vec![(0, PackageState::Uninstalled), (1, PackageState::Enabled)],
+            ),
+        ];
+        for message in messages {
+            let _ = list.update(&mut settings, &mut phone, &mut list_state, message);
+        }
+        assert_eq!(list.phone_packages[0][1].state, PackageState::Uninstalled);
+        assert_eq!(list.phone_packages[1][1].state, PackageState::Enabled);
+        assert!(list.selection.selected_packages.is_empty());
+    }
+
+    #[test]
+    fn test_refresh_packages() {
+        let mut settings = settings(false, false);
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let mut list = List::default();
+        // `com.aaa` has been installed and `com.foo` disabled since the packages were loaded
+        let mut fresh = packages(PackageState::Enabled);
+        fresh.insert(
+            0,
+            PackageRow::new(
+                "com.aaa",
+                PackageState::Enabled,
+                "",
+                UadList::Oem,
+                Removal::Recommended,
+                false,
+                false,
+            ),
+        );
+        fresh[2].state = PackageState::Disabled;
+        let messages = [
+            Message::ApplyFilters(vec![
+                packages(PackageState::Enabled),
+                packages(PackageState::Enabled),
+            ]),
+            Message::PackageStateSelected(PackageState::All),
+            Message::List(1, RowMessage::ToggleSelection(true)),
+            Message::PackagesRefreshed("mock-device".to_string(), vec![fresh.clone(), fresh]),
+        ];
+        for message in messages {
+            let _ = list.update(&mut settings, &mut phone, &mut list_state, message);
+        }
+        assert_eq!(list.phone_packages[0].len(), 3);
+        assert_eq!(list.selected_package_state, Some(PackageState::All));
+        assert_eq!(list.filtered_packages, vec![0, 1, 2]);
+        assert_eq!(list.selection.selected_packages, vec![2]);
+        assert_eq!((list.selection.enabled, list.selection.disabled), (0, 1));
+        assert!(list.phone_packages[0][2].selected);
+    }
+
+    #[test]
+    fn test_refresh_packages_new_user() {
+        let mut settings = settings(false, false);
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let mut list = List::default();
+        let mut fresh = packages(PackageState::Enabled);
+        fresh.insert(
+            0,
+            PackageRow::new(
+                "com.aaa",
+                PackageState::Enabled,
+                "",
+                UadList::Oem,
+                Removal::Recommended,
+                false,
+                false,
+            ),
+        );
+        let messages = [
+            Message::ApplyFilters(vec![
+                packages(PackageState::Enabled),
+                packages(PackageState::Enabled),
+            ]),
+            Message::PackageStateSelected(PackageState::All),
+            Message::UserSelected(phone.user_list[1].clone()),
+            Message::List(1, RowMessage::ToggleSelection(true)),
+        ];
+        for message in messages {
+            let _ = list.update(&mut settings, &mut phone, &mut list_state, message);
+        }
+
+        // A user has been created before the selected one
+        phone.user_list = vec![User::new(0, 0), User::new(5, 1), User::new(10, 2)];
+        let _ = list.update(
+            &mut settings,
+            &mut phone,
+            &mut list_state,
+            Message::PackagesRefreshed(
+                "mock-device".to_string(),
+                vec![fresh.clone(), fresh.clone(), fresh],
+            ),
+        );
+        assert_eq!(list.selected_user().id, 10);
+        assert_eq!(list.selected_user().index, 2);
+        assert_eq!(list.selected_package_state, Some(PackageState::All));
+        assert_eq!(list.filtered_packages, vec![0, 1, 2]);
+        // `com.foo` is still selected (at its new index)
+        assert_eq!(list.selection.selected_packages, vec![2]);
+        assert_eq!(list.selection.enabled, 1);
+        assert!(list.phone_packages[2][2].selected);
+        assert!(!list.phone_packages[0][2].selected && !list.phone_packages[1][2].selected);
+    }
+
+    #[test]
+    fn test_apply_filters_other_device() {
+        let mut settings = settings(false, false);
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let mut list = List {
+            selected_user: Some(phone.user_list[1].clone()),
+            ..List::default()
+        };
+        // Single user device: the user index 1 doesn't exist anymore
+        phone.user_list.truncate(1);
+        let _ = list.update(
+            &mut settings,
+            &mut phone,
+            &mut list_state,
+            Message::ApplyFilters(vec![packages(PackageState::Enabled)]),
+        );
+        assert_eq!(list.selected_user().index, 0);
+        assert_eq!(list.filtered_packages, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_apply_filters_resets_selection() {
+        let mut settings = settings(false, false);
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let mut list = List::default();
+        // e.g. reloaded with the user-installed apps: `com.foo` isn't at index 1 anymore
+        let mut reloaded = packages(PackageState::Enabled);
+        reloaded.insert(
+            0,
+            PackageRow::new(
+                "com.aaa",
+                PackageState::Enabled,
+                "",
+                UadList::ThirdParty,
+                Removal::Unlisted,
+                false,
+                false,
+            ),
+        );
+        let messages = [
+            Message::ApplyFilters(vec![
+                packages(PackageState::Enabled),
+                packages(PackageState::Enabled),
+            ]),
+            Message::List(1, RowMessage::ToggleSelection(true)),
+            Message::ApplyActionOnSelection(Action::Remove),
+            Message::ApplyFilters(vec![reloaded.clone(), reloaded]),
+        ];
+        for message in messages {
+            let _ = list.update(&mut settings, &mut phone, &mut list_state, message);
+        }
+        assert!(list.selection.selected_packages.is_empty());
+        assert_eq!(list.selection.enabled, 0);
+        assert!(list.pending_action.is_none());
+        assert!(list.phone_packages[0].iter().all(|p| !p.selected));
+
+        let _ = list.update(
+            &mut settings,
+            &mut phone,
+            &mut list_state,
+            Message::ApplyActionOnSelection(Action::Remove),
+        );
+        assert!(list.pending_action.is_none());
+    }
+
+    #[test]
+    fn test_change_package_state_row_target() {
+        let mut settings = settings(false, false);
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let mut list = List::default();
+        let messages = [
+            Message::ApplyFilters(vec![
+                packages(PackageState::Enabled),
+                packages(PackageState::Enabled),
+            ]),
+            Message::List(
+                1,
+                RowMessage::TargetSelected(RemovalTarget(Some(PackageState::Suspended))),
+            ),
+        ];
+        for message in messages {
+            let _ = list.update(&mut settings, &mut phone, &mut list_state, message);
+        }
+        assert_eq!(
+            list.phone_packages[1][1].target,
+            Some(PackageState::Suspended)
+        );
+
+        let jobs = list.plan_jobs(1, &phone, &settings.device);
+        assert_eq!(jobs[0].commands, vec!["pm suspend --user 0 com.foo"]);
+        let states = verified_states(&list, &jobs[0], &phone);
+        let _ = list.update(
+            &mut settings,
+            &mut phone,
+            &mut list_state,
+            Message::ChangePackageState(1, states),
+        );
+        assert_eq!(list.phone_packages[0][1].state, PackageState::Suspended);
+        assert_eq!(list.phone_packages[0][0].state, PackageState::Enabled);
+    }
+
+    #[test]
+    fn test_dependency_notice() {
+        let uad_lists: Vec<Package> = serde_json::from_str(
+            r#"[{
+                "id": "com.bar",
+                "list": "Oem",
+                "description": "",
+                "dependencies": [],
+                "neededBy": ["com.foo"],
+                "labels": [],
+                "removal": "Recommended"
+            }]"#,
+        )
+        .unwrap();
+        let mut settings = settings(false, false);
+        let mut phone = phone(30);
+        let mut list_state = UadListState::Done;
+        let mut list = List {
+            dependency_graph: DependencyGraph::new(
+                &uad_lists.into_iter().map(|p| (p.id.clone(), p)).collect(),
+            ),
+            ..List::default()
+        };
+        let mut update = |list: &mut List, message| {
+            let _ = list.update(&mut settings, &mut phone, &mut list_state, message);
+        };
+
+        update(
+            &mut list,
+            Message::ApplyFilters(vec![packages(PackageState::Enabled)]),
+        );
+        update(
+            &mut list,
+            Message::List(0, RowMessage::ToggleSelection(true)),
+        );
+        assert_eq!(list.dependency_notice.as_ref().unwrap().related, vec![1]);
+
+        update(&mut list, Message::SelectDependencies);
+        assert!(list.dependency_notice.is_none());
+        assert!(list.phone_packages[0][1].selected);
+        assert_eq!(list.selection.selected_packages, vec![0, 1]);
+        assert_eq!(list.selection.enabled, 2);
+    }
+}
@@ -1,3 +1,7 @@
 pub mod about;
+pub mod history;
 pub mod list;
+pub mod profiles;
+pub mod recovery;
 pub mod settings;
+pub mod wireless;
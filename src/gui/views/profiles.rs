@@ -0,0 +1,796 @@
+use crate::core::profile::{
+    delete_profile, list_profiles, save_profile, Profile, ProfileDiff, ProfilePackage,
+};
+use crate::core::reconcile::{apply, plan_from_device, Change, Plan, Report};
+use crate::core::sync::Phone;
+use crate::core::theme::Theme;
+use crate::gui::style;
+
+use iced::widget::{button, checkbox, column, container, row, scrollable, text, text_input, Space};
+use iced::{Alignment, Command, Element, Length, Renderer};
+
+#[derive(Default, Debug, Clone)]
+pub struct Profiles {
+    profiles: Vec<Profile>,
+    name_input: String,
+    description_input: String,
+    diff: Option<(String, ProfileDiff)>,
+    pending_delete: Option<String>,
+    status: String,
+    planned_profile: Option<Profile>,
+    plan: Option<Plan>,
+    report: Option<Report>,
+    applying: bool,
+    target_devices: Vec<String>, // Serials of the devices checked for a batch
+    batch_profile: Option<Profile>,
+    batch: Vec<BatchTarget>,
+}
+
+/// A device of a batch and where it stands
+#[derive(Debug, Clone)]
+struct BatchTarget {
+    phone: Phone,
+    plan: Option<Result<Plan, String>>,
+    report: Option<Report>,
+    applying: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    LoadProfiles,
+    NameChanged(String),
+    DescriptionChanged(String),
+    SavePressed,
+    LoadPressed(String),
+    DiffPressed(String),
+    DeletePressed(String),
+    PlanPressed(String),
+    PlanReady(Result<Plan, String>),
+    ApplyPlan,
+    PlanApplied(Report),
+    ClosePlan,
+    DeviceToggled(String, bool),
+    /// `None` targets the current selection
+    BatchPressed(Option<String>),
+    BatchPlanReady(String, Result<Plan, String>),
+    ApplyBatch,
+    BatchApplied(String, Report),
+    CloseBatch,
+}
+
+impl Profiles {
+    /// `current` is the profile made from the current selection.
+    /// `devices` are the connected devices (only the ready ones can be part of a batch).
+    pub fn update(
+        &mut self,
+        phone: &Phone,
+        devices: &[Phone],
+        current: Profile,
+        msg: Message,
+    ) -> Command<Message> {
+        match msg {
+            Message::LoadProfiles => {
+                self.profiles = list_profiles();
+                self.diff = None;
+                self.pending_delete = None;
+                self.status.clear();
+                self.target_devices
+                    .retain(|serial| devices.iter().any(|d| d.adb_id == *serial && d.is_ready()));
+            }
+            Message::NameChanged(name) => self.name_input = name,
+            Message::DescriptionChanged(description) => self.description_input = description,
+            Message::SavePressed => {
+                let profile = Profile {
+                    name: self.name_input.trim().to_string(),
+                    description: self.description_input.trim().to_string(),
+                    ..current
+                };
+                match save_profile(&profile) {
+                    Ok(_) => {
+                        info!("Profile `{}` saved", profile.name);
+                        self.status = format!(
+                            "Profile `{}` saved ({} packages)",
+                            profile.name,
+                            profile.packages.len()
+                        );
+                        self.name_input.clear();
+                        self.description_input.clear();
+                        self.profiles = list_profiles();
+                    }
+                    Err(e) => {
+                        error!("Could not save profile: {}", e);
+                        self.status = format!("Could not save profile: {}", e);
+                    }
+                }
+            }
+            // The selection is done by the list view
+            Message::LoadPressed(name) => {
+                if let Some(profile) = self.profile(&name) {
+                    if profile.device != current.device {
+                        warn!(
+                            "Profile `{}` was made on another device ({} - Android SDK {})",
+                            name, profile.device.model, profile.device.android_sdk
+                        );
+                    }
+                    self.status = format!("Profile `{}` loaded", name);
+                }
+            }
+            Message::DiffPressed(name) => {
+                self.diff = self
+                    .profile(&name)
+                    .map(|profile| (name, current.diff(profile)));
+            }
+            Message::DeletePressed(name) => {
+                // A first press asks for confirmation
+                if self.pending_delete.as_ref() != Some(&name) {
+                    self.pending_delete = Some(name);
+                    return Command::none();
+                }
+                self.pending_delete = None;
+                match delete_profile(&name) {
+                    Ok(_) => {
+                        info!("Profile `{}` deleted", name);
+                        self.status = format!("Profile `{}` deleted", name);
+                    }
+                    Err(e) => {
+                        error!("Could not delete profile `{}`: {}", name, e);
+                        self.status = format!("Could not delete profile `{}`: {}", name, e);
+                    }
+                }
+                if self.diff.as_ref().is_some_and(|(n, _)| *n == name) {
+                    self.diff = None;
+                }
+                self.profiles = list_profiles();
+            }
+            Message::PlanPressed(name) => {
+                if let Some(profile) = self.profile(&name).cloned() {
+                    self.planned_profile = Some(profile.clone());
+                    self.plan = None;
+                    self.report = None;
+                    return Command::perform(
+                        plan_from_device(profile, phone.clone()),
+                        Message::PlanReady,
+                    );
+                }
+            }
+            Message::PlanReady(res) => match res {
+                Ok(plan) => self.plan = Some(plan),
+                Err(e) => {
+                    error!("Could not compute the plan: {}", e);
+                    self.status = format!("Could not compute the plan: {}", e);
+                    self.planned_profile = None;
+                }
+            },
+            Message::ApplyPlan => {
+                if let (Some(profile), Some(plan)) = (&self.planned_profile, &self.plan) {
+                    self.applying = true;
+                    return Command::perform(
+                        apply(profile.clone(), phone.clone(), plan.clone()),
+                        Message::PlanApplied,
+                    );
+                }
+            }
+            Message::PlanApplied(report) => {
+                info!(
+                    "Profile applied: {} changes, {} still drifting",
+                    report.results.len(),
+                    report.drift.len()
+                );
+                self.applying = false;
+                self.plan = None;
+                self.report = Some(report);
+            }
+            Message::ClosePlan => {
+                self.planned_profile = None;
+                self.plan = None;
+                self.report = None;
+            }
+            Message::DeviceToggled(serial, checked) => {
+                if checked {
+                    self.target_devices.push(serial);
+                } else {
+                    self.target_devices.retain(|s| *s != serial);
+                }
+            }
+            Message::BatchPressed(name) => {
+                let profile = match name {
+                    Some(name) => self.profile(&name).cloned(),
+                    None => Some(current),
+                };
+                if let Some(profile) = profile {
+                    self.batch = devices
+                        .iter()
+                        .filter(|d| d.is_ready() && self.target_devices.contains(&d.adb_id))
+                        .map(|phone| BatchTarget {
+                            phone: phone.clone(),
+                            plan: None,
+                            report: None,
+                            applying: false,
+                        })
+                        .collect();
+                    // Every device is planned (then applied) at the same time
+                    let commands: Vec<Command<Message>> = self
+                        .batch
+                        .iter()
+                        .map(|target| {
+                            let serial = target.phone.adb_id.clone();
+                            Command::perform(
+                                plan_from_device(profile.clone(), target.phone.clone()),
+                                move |res| Message::BatchPlanReady(serial, res),
+                            )
+                        })
+                        .collect();
+                    self.batch_profile = Some(profile);
+                    return Command::batch(commands);
+                }
+            }
+            Message::BatchPlanReady(serial, res) => {
+                if let Err(e) = &res {
+                    error!("[{}] Could not compute the plan: {}", serial, e);
+                }
+                if let Some(target) = self.batch.iter_mut().find(|t| t.phone.adb_id == serial) {
+                    target.plan = Some(res);
+                }
+            }
+            Message::ApplyBatch => {
+                if let Some(profile) = &self.batch_profile {
+                    let mut commands = vec![];
+                    for target in &mut self.batch {
+                        if let Some(Ok(plan)) = &target.plan {
+                            if plan.changes.is_empty() || target.report.is_some() {
+                                continue;
+                            }
+                            target.applying = true;
+                            let serial = target.phone.adb_id.clone();
+                            commands.push(Command::perform(
+                                apply(profile.clone(), target.phone.clone(), plan.clone()),
+                                move |report| Message::BatchApplied(serial, report),
+                            ));
+                        }
+                    }
+                    return Command::batch(commands);
+                }
+            }
+            Message::BatchApplied(serial, report) => {
+                info!(
+                    "[{}] Profile applied: {} changes, {} still drifting",
+                    serial,
+                    report.results.len(),
+                    report.drift.len()
+                );
+                if let Some(target) = self.batch.iter_mut().find(|t| t.phone.adb_id == serial) {
+                    target.applying = false;
+                    target.report = Some(report);
+                }
+            }
+            Message::CloseBatch => {
+                self.batch_profile = None;
+                self.batch.clear();
+            }
+        }
+        Command::none()
+    }
+
+    fn is_batch_running(&self) -> bool {
+        self.batch.iter().any(|t| t.plan.is_none() || t.applying)
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    pub fn view<'a>(
+        &'a self,
+        current: &Profile,
+        devices: &'a [Phone],
+    ) -> Element<'a, Message, Renderer<Theme>> {
+        if let Some(profile) = &self.planned_profile {
+            return container(self.plan_view(profile))
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .padding(10)
+                .into();
+        }
+        if let Some(profile) = &self.batch_profile {
+            return container(self.batch_view(profile))
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .padding(10)
+                .into();
+        }
+
+        let name_input = text_input("Profile name", &self.name_input, Message::NameChanged)
+            .padding(5)
+            .width(Length::Units(200));
+
+        let description_input = text_input(
+            "Description (optional)",
+            &self.description_input,
+            Message::DescriptionChanged,
+        )
+        .padding(5);
+
+        let save_btn = if self.name_input.trim().is_empty() || current.packages.is_empty() {
+            button(text(format!(
+                "Save current selection ({})",
+                current.packages.len()
+            )))
+            .style(style::Button::Unavailable)
+        } else {
+            button(text(format!(
+                "Save current selection ({})",
+                current.packages.len()
+            )))
+            .on_press(Message::SavePressed)
+            .style(style::Button::Primary)
+        };
+
+        let save_row = row![name_input, description_input, save_btn.padding(5)]
+            .spacing(10)
+            .align_items(Alignment::Center);
+
+        let scope_text = text(format!(
+            "The selection of {} is saved along with the disable mode setting (disabled vs uninstalled)",
+            current.user_scope
+        ))
+        .style(style::Text::Commentary);
+
+        let save_ctn = container(column![save_row, scope_text].spacing(10))
+            .padding(10)
+            .width(Length::Fill)
+            .style(style::Container::Frame);
+
+        let profiles = if self.profiles.is_empty() {
+            column![text("No profile saved yet").size(20)]
+        } else {
+            self.profiles.iter().fold(column![].spacing(10), |col, p| {
+                col.push(self.profile_row(p))
+            })
+        };
+
+        let mut content = column![save_ctn].spacing(15);
+
+        // A batch only makes sense with several devices
+        if devices.iter().filter(|d| d.is_ready()).count() > 1 {
+            content = content.push(self.devices_view(current, devices));
+        }
+
+        if !self.status.is_empty() {
+            content = content.push(text(&self.status));
+        }
+        if let Some((name, diff)) = &self.diff {
+            content = content.push(diff_view(name, diff));
+        }
+
+        content = content.push(
+            scrollable(profiles)
+                .scrollbar_margin(2)
+                .height(Length::Fill)
+                .style(style::Scrollable::Packages),
+        );
+
+        container(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(10)
+            .into()
+    }
+
+    fn profile_row<'a>(&self, profile: &'a Profile) -> Element<'a, Message, Renderer<Theme>> {
+        let delete_label = if self.pending_delete.as_ref() == Some(&profile.name) {
+            "Confirm deletion"
+        } else {
+            "Delete"
+        };
+
+        let batch_label = format!("Apply to {} devices", self.target_devices.len());
+        let batch_btn = if self.target_devices.is_empty() {
+            button(text(batch_label))
+                .padding(5)
+                .style(style::Button::Unavailable)
+        } else {
+            button(text(batch_label))
+                .on_press(Message::BatchPressed(Some(profile.name.clone())))
+                .padding(5)
+                .style(style::Button::Primary)
+        };
+
+        let header = row![
+            text(&profile.name).size(20),
+            text(format!(
+                "{} packages | {} | {} - Android SDK {} | {}",
+                profile.packages.len(),
+                profile.user_scope,
+                profile.device.model,
+                profile.device.android_sdk,
+                profile.created
+            ))
+            .style(style::Text::Commentary),
+            Space::new(Length::Fill, Length::Shrink),
+            button("Load")
+                .on_press(Message::LoadPressed(profile.name.clone()))
+                .padding(5)
+                .style(style::Button::Primary),
+            button("Apply")
+                .on_press(Message::PlanPressed(profile.name.clone()))
+                .padding(5)
+                .style(style::Button::Primary),
+            button("Diff")
+                .on_press(Message::DiffPressed(profile.name.clone()))
+                .padding(5)
+                .style(style::Button::Primary),
+            batch_btn,
+            button(delete_label)
+                .on_press(Message::DeletePressed(profile.name.clone()))
+                .padding(5)
+                .style(style::Button::UninstallPackage),
+            Space::with_width(Length::Units(15)),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let mut col = column![header].spacing(5);
+        if !profile.description.is_empty() {
+            col = col.push(text(&profile.description));
+        }
+
+        container(col)
+            .padding(10)
+            .width(Length::Fill)
+            .style(style::Container::Frame)
+            .into()
+    }
+
+    fn devices_view<'a>(
+        &self,
+        current: &Profile,
+        devices: &'a [Phone],
+    ) -> Element<'a, Message, Renderer<Theme>> {
+        let checkboxes =
+            devices
+                .iter()
+                .filter(|d| d.is_ready())
+                .fold(row![].spacing(15), |row, phone| {
+                    let serial = phone.adb_id.clone();
+                    row.push(
+                        checkbox(
+                            format!("{} ({})", phone.model, phone.adb_id),
+                            self.target_devices.contains(&phone.adb_id),
+                            move |checked| Message::DeviceToggled(serial.clone(), checked),
+                        )
+                        .style(style::CheckBox::SettingsEnabled),
+                    )
+                });
+
+        let apply_label = format!(
+            "Apply current selection to {} devices",
+            self.target_devices.len()
+        );
+        let apply_btn = if self.target_devices.is_empty() || current.packages.is_empty() {
+            button(text(apply_label))
+                .padding(5)
+                .style(style::Button::Unavailable)
+        } else {
+            button(text(apply_label))
+                .on_press(Message::BatchPressed(None))
+                .padding(5)
+                .style(style::Button::Primary)
+        };
+
+        let content = column![
+            row![
+                text("Devices").size(20),
+                Space::new(Length::Fill, Length::Shrink),
+                apply_btn
+            ]
+            .align_items(Alignment::Center),
+            checkboxes,
+            text("A profile (or the current selection) is applied to every checked device at the same time")
+                .style(style::Text::Commentary),
+        ]
+        .spacing(10);
+
+        container(content)
+            .padding(10)
+            .width(Length::Fill)
+            .style(style::Container::Frame)
+            .into()
+    }
+
+    fn batch_view<'a>(&'a self, profile: &'a Profile) -> Element<'a, Message, Renderer<Theme>> {
+        let running = self.is_batch_running();
+        let applied = self.batch.iter().any(|t| t.report.is_some());
+
+        let close_btn = button(if applied { "Close" } else { "Cancel" })
+            .padding(5)
+            .style(style::Button::Primary);
+        let close_btn = if running {
+            close_btn.style(style::Button::Unavailable)
+        } else {
+            close_btn.on_press(Message::CloseBatch)
+        };
+
+        let pending = self.batch.iter().any(|t| {
+            t.report.is_none() && matches!(&t.plan, Some(Ok(plan)) if !plan.changes.is_empty())
+        });
+        let apply_btn = if pending && !running {
+            button("Apply")
+                .on_press(Message::ApplyBatch)
+                .padding(5)
+                .style(style::Button::Primary)
+        } else {
+            button("Apply").padding(5).style(style::Button::Unavailable)
+        };
+
+        let name = if profile.name.is_empty() {
+            "Current selection"
+        } else {
+            &profile.name
+        };
+        let header = row![
+            text(format!("`{}` on {} devices", name, self.batch.len())).size(20),
+            Space::new(Length::Fill, Length::Shrink),
+            close_btn,
+            apply_btn,
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let table_header = row![
+            text("Device").width(Length::Units(300)),
+            text("Plan").width(Length::Units(350)),
+            text("Result"),
+        ]
+        .spacing(10);
+
+        let table = self
+            .batch
+            .iter()
+            .fold(column![table_header].spacing(8), |col, target| {
+                col.push(batch_row(target))
+            });
+
+        column![
+            header,
+            container(
+                scrollable(table)
+                    .scrollbar_margin(2)
+                    .style(style::Scrollable::Packages)
+            )
+            .padding(10)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .style(style::Container::Frame),
+        ]
+        .spacing(10)
+        .into()
+    }
+
+    fn plan_view<'a>(&'a self, profile: &'a Profile) -> Element<'a, Message, Renderer<Theme>> {
+        let close_btn = button(if self.report.is_some() {
+            "Close"
+        } else {
+            "Cancel"
+        })
+        .padding(5)
+        .style(style::Button::Primary);
+        let close_btn = if self.applying {
+            close_btn.style(style::Button::Unavailable)
+        } else {
+            close_btn.on_press(Message::ClosePlan)
+        };
+
+        let (title, content) = match (&self.plan, &self.report) {
+            (_, Some(report)) => (
+                if report.drift.is_empty() {
+                    format!("`{}` applied: the device matches the profile", profile.name)
+                } else {
+                    format!(
+                        "`{}` applied: {} changes could not be made",
+                        profile.name,
+                        report.drift.len()
+                    )
+                },
+                report_view(report),
+            ),
+            (Some(plan), None) => (
+                format!(
+                    "Apply `{}`: {} changes ({} packages already in the targeted state)",
+                    profile.name,
+                    plan.changes.len(),
+                    plan.in_sync
+                ),
+                plan_details(plan),
+            ),
+            (None, None) => (
+                "Reading the state of the packages. Please wait...".to_string(),
+                column![].into(),
+            ),
+        };
+
+        let apply_btn = match &self.plan {
+            Some(plan) if !plan.changes.is_empty() && !self.applying => button("Apply")
+                .on_press(Message::ApplyPlan)
+                .padding(5)
+                .style(style::Button::Primary),
+            Some(_) if self.applying => button("Applying...")
+                .padding(5)
+                .style(style::Button::Unavailable),
+            _ => button("Apply").padding(5).style(style::Button::Unavailable),
+        };
+
+        let header = row![
+            text(title).size(20),
+            Space::new(Length::Fill, Length::Shrink),
+            close_btn,
+            apply_btn,
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        column![
+            header,
+            container(
+                scrollable(content)
+                    .scrollbar_margin(2)
+                    .style(style::Scrollable::Packages)
+            )
+            .padding(10)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .style(style::Container::Frame),
+        ]
+        .spacing(10)
+        .into()
+    }
+}
+
+fn batch_row(target: &BatchTarget) -> Element<Message, Renderer<Theme>> {
+    let device = text(format!(
+        "{} ({}) - Android SDK {}",
+        target.phone.model, target.phone.adb_id, target.phone.android_sdk
+    ))
+    .width(Length::Units(300));
+
+    let plan = match &target.plan {
+        None => text("Reading the state of the packages..."),
+        Some(Err(e)) => text(e).style(style::Text::Danger),
+        Some(Ok(plan)) => text(format!(
+            "{} changes ({} in sync, {} missing, {} unsupported)",
+            plan.changes.len(),
+            plan.in_sync,
+            plan.missing.len(),
+            plan.unsupported.len()
+        )),
+    }
+    .width(Length::Units(350));
+
+    let result = match (&target.report, &target.plan) {
+        _ if target.applying => text("Applying..."),
+        (Some(report), _) if report.is_success() => {
+            text(format!("Done ({} changes)", report.results.len()))
+        }
+        (Some(report), _) => text(format!(
+            "{} failed, {} still different from the profile",
+            report.results.iter().filter(|r| r.error.is_some()).count(),
+            report.drift.len()
+        ))
+        .style(style::Text::Danger),
+        (None, Some(Ok(plan))) if plan.changes.is_empty() => text("Nothing to do"),
+        (None, _) => text(""),
+    };
+
+    row![device, plan, result].spacing(10).into()
+}
+
+fn change_row(change: &Change) -> Element<Message, Renderer<Theme>> {
+    let commands = change.commands().fold(column![], |col, c| {
+        col.push(text(c).style(style::Text::Commentary))
+    });
+
+    column![
+        text(format!(
+            "{} (user {}): {} -> {}",
+            change.package, change.user.id, change.current, change.target
+        )),
+        commands,
+    ]
+    .spacing(2)
+    .into()
+}
+
+fn plan_details(plan: &Plan) -> Element<Message, Renderer<Theme>> {
+    let mut col = column![].spacing(10);
+    if !plan.missing.is_empty() {
+        col = col.push(
+            text(format!(
+                "Not found on the device: {}",
+                plan.missing.join(", ")
+            ))
+            .style(style::Text::Danger),
+        );
+    }
+    if !plan.unsupported.is_empty() {
+        col = col.push(
+            text(format!(
+                "Disabling packages needs Android 6.0+. Skipped: {}",
+                plan.unsupported
+                    .iter()
+                    .map(|c| c.package.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ))
+            .style(style::Text::Danger),
+        );
+    }
+    plan.changes
+        .iter()
+        .fold(col, |col, change| col.push(change_row(change)))
+        .into()
+}
+
+fn report_view(report: &Report) -> Element<Message, Renderer<Theme>> {
+    let results = report.results.iter().fold(column![].spacing(6), |col, r| {
+        col.push(match &r.error {
+            None => text(format!(
+                "{} (user {}): {}",
+                r.change.package, r.change.user.id, r.change.target
+            )),
+            Some(e) => text(format!(
+                "{} (user {}): {}",
+                r.change.package, r.change.user.id, e
+            ))
+            .style(style::Text::Danger),
+        })
+    });
+
+    let drift = report.drift.iter().fold(
+        column![text("Still different from the profile:")].spacing(6),
+        |col, change| col.push(change_row(change)),
+    );
+
+    if report.drift.is_empty() {
+        results.into()
+    } else {
+        column![results, drift].spacing(15).into()
+    }
+}
+
+fn diff_view<'a>(name: &str, diff: &'a ProfileDiff) -> Element<'a, Message, Renderer<Theme>> {
+    let title = text(format!("Current selection -> `{}`", name)).size(20);
+
+    if diff.is_empty() {
+        return container(column![title, text("No difference")].spacing(10))
+            .padding(10)
+            .width(Length::Fill)
+            .style(style::Container::BorderedFrame)
+            .into();
+    }
+
+    let packages = |header: &str, packages: &'a [ProfilePackage]| {
+        packages.iter().fold(
+            column![text(format!("{} ({})", header, packages.len()))],
+            |col, p| col.push(text(format!("  {} ({})", p.name, p.target))),
+        )
+    };
+
+    let changed = diff.changed.iter().fold(
+        column![text(format!("Different target ({})", diff.changed.len()))],
+        |col, (package, from, to)| col.push(text(format!("  {}: {} -> {}", package, from, to))),
+    );
+
+    let content = column![
+        title,
+        packages("Only in the profile", &diff.added),
+        packages("Only in the current selection", &diff.removed),
+        changed,
+    ]
+    .spacing(10);
+
+    container(scrollable(content).style(style::Scrollable::Description))
+        .padding(10)
+        .height(Length::Units(200))
+        .width(Length::Fill)
+        .style(style::Container::BorderedFrame)
+        .into()
+}
@@ -0,0 +1,301 @@
+use crate::core::error::AdbError;
+use crate::core::recovery::{
+    attempt_recovery, delete_restore_point, list_restore_points, remove_from_restore_point,
+    restore_points_dir, RecoveryAttempt, RecoveryState, RestorePoint,
+};
+use crate::core::sync::Phone;
+use crate::core::theme::Theme;
+use crate::core::utils::open_url;
+use crate::gui::style;
+
+use iced::widget::{button, column, container, row, scrollable, text, Space};
+use iced::{Alignment, Command, Element, Length, Renderer};
+use std::time::Duration;
+
+// Time between two attempts of a recovery
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Default, Debug, Clone)]
+pub struct Recovery {
+    restore_points: Vec<RestorePoint>,
+    running: Option<RunningRecovery>,
+    pending_delete: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct RunningRecovery {
+    point: RestorePoint, // Only the packages not restored yet
+    state: Option<RecoveryState>,
+    attempts: usize,
+    restored: Vec<String>,
+    errors: Vec<(String, AdbError)>, // Of the last attempt
+}
+
+impl RunningRecovery {
+    fn is_done(&self) -> bool {
+        self.point.packages.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    LoadRestorePoints,
+    StartPressed(String),
+    Attempted(String, RecoveryAttempt),
+    StopPressed,
+    DeletePressed(String),
+    OpenFolderPressed,
+}
+
+impl Recovery {
+    pub fn update(&mut self, msg: Message) -> Command<Message> {
+        match msg {
+            Message::LoadRestorePoints => {
+                self.restore_points = list_restore_points();
+                self.pending_delete = None;
+            }
+            Message::StartPressed(device_id) => {
+                if let Some(point) = self
+                    .restore_points
+                    .iter()
+                    .find(|p| p.device_id == device_id)
+                {
+                    info!("Starting the recovery of {}", device_id);
+                    self.running = Some(RunningRecovery {
+                        point: point.clone(),
+                        state: None,
+                        attempts: 0,
+                        restored: vec![],
+                        errors: vec![],
+                    });
+                    return attempt(point, Duration::ZERO);
+                }
+            }
+            Message::Attempted(device_id, attempt_result) => {
+                // The recovery may have been stopped in the meantime
+                let running = match &mut self.running {
+                    Some(r) if r.point.device_id == device_id => r,
+                    _ => return Command::none(),
+                };
+                running.attempts += 1;
+                running.state = Some(attempt_result.state);
+                running.errors.clear();
+                let mut restored = vec![];
+                for (package, result) in attempt_result.results {
+                    match result {
+                        Ok(_) => restored.push(package),
+                        Err(e) => running.errors.push((package, e)),
+                    }
+                }
+                if !restored.is_empty() {
+                    running
+                        .point
+                        .packages
+                        .retain(|p| !restored.contains(&p.package));
+                    remove_from_restore_point(&device_id, &restored);
+                    running.restored.extend(restored);
+                    self.restore_points = list_restore_points();
+                }
+                if running.is_done() {
+                    info!("Recovery of {} done", device_id);
+                } else {
+                    return attempt(&running.point, RETRY_DELAY);
+                }
+            }
+            Message::StopPressed => self.running = None,
+            Message::DeletePressed(device_id) => {
+                // A first press asks for confirmation
+                if self.pending_delete.as_ref() != Some(&device_id) {
+                    self.pending_delete = Some(device_id);
+                    return Command::none();
+                }
+                self.pending_delete = None;
+                delete_restore_point(&device_id);
+                info!("Restore point of {} deleted", device_id);
+                self.restore_points = list_restore_points();
+            }
+            Message::OpenFolderPressed => open_url(restore_points_dir()),
+        }
+        Command::none()
+    }
+
+    /// `devices` are the devices known by ADB (whatever their state)
+    pub fn view(&self, devices: &[Phone]) -> Element<Message, Renderer<Theme>> {
+        let descr = text(
+            "Before removing an Expert or Unsafe package, UAD saves the commands restoring it. \
+            If the device doesn't boot anymore, plug it in and start the recovery: the commands \
+            are run as soon as `adb shell` is usable (early in the boot, before the lock screen). \
+            Each restore point is also saved as a shell script.",
+        )
+        .style(style::Text::Commentary);
+
+        let header = container(
+            row![
+                descr.width(Length::Fill),
+                button("Open folder")
+                    .on_press(Message::OpenFolderPressed)
+                    .padding(5)
+                    .style(style::Button::Primary),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+        )
+        .padding(10)
+        .width(Length::Fill)
+        .style(style::Container::Frame);
+
+        let mut content = column![header].spacing(15);
+        if let Some(running) = &self.running {
+            content = content.push(running_view(running));
+        }
+
+        let points = if self.restore_points.is_empty() {
+            column![text("No restore point").size(20)]
+        } else {
+            self.restore_points
+                .iter()
+                .fold(column![].spacing(10), |col, point| {
+                    col.push(self.restore_point_row(point, devices))
+                })
+        };
+        content = content.push(
+            scrollable(points)
+                .scrollbar_margin(2)
+                .height(Length::Fill)
+                .style(style::Scrollable::Packages),
+        );
+
+        container(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(10)
+            .into()
+    }
+
+    fn restore_point_row<'a>(
+        &self,
+        point: &'a RestorePoint,
+        devices: &[Phone],
+    ) -> Element<'a, Message, Renderer<Theme>> {
+        let device_state = match devices.iter().find(|d| d.adb_id == point.device_id) {
+            Some(phone) => phone.status.to_string(),
+            None => "not connected".to_string(),
+        };
+
+        let start_btn = if self.running.is_some() {
+            button("Start recovery")
+                .padding(5)
+                .style(style::Button::Unavailable)
+        } else {
+            button("Start recovery")
+                .on_press(Message::StartPressed(point.device_id.clone()))
+                .padding(5)
+                .style(style::Button::Primary)
+        };
+
+        let delete_label = if self.pending_delete.as_ref() == Some(&point.device_id) {
+            "Confirm deletion"
+        } else {
+            "Delete"
+        };
+
+        let header = row![
+            text(&point.model).size(20),
+            text(format!(
+                "{} | {} | {} packages | {}",
+                point.device_id,
+                device_state,
+                point.packages.len(),
+                point.date
+            ))
+            .style(style::Text::Commentary),
+            Space::new(Length::Fill, Length::Shrink),
+            start_btn,
+            button(delete_label)
+                .on_press(Message::DeletePressed(point.device_id.clone()))
+                .padding(5)
+                .style(style::Button::Primary),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center);
+
+        let packages = text(
+            point
+                .packages
+                .iter()
+                .map(|p| format!("{} [{}]", p.package, p.removal))
+                .collect::<Vec<String>>()
+                .join(", "),
+        );
+
+        container(column![header, packages].spacing(5))
+            .padding(10)
+            .width(Length::Fill)
+            .style(style::Container::BorderedFrame)
+            .into()
+    }
+}
+
+fn attempt(point: &RestorePoint, delay: Duration) -> Command<Message> {
+    let device_id = point.device_id.clone();
+    Command::perform(
+        attempt_recovery(device_id.clone(), point.packages.clone(), delay),
+        move |res| Message::Attempted(device_id, res),
+    )
+}
+
+fn running_view(running: &RunningRecovery) -> Element<Message, Renderer<Theme>> {
+    let state = match &running.state {
+        _ if running.is_done() => {
+            "Every package has been restored. The device should now boot normally (reboot it \
+            if needed)."
+                .to_string()
+        }
+        None => "Starting...".to_string(),
+        Some(RecoveryState::Waiting) => {
+            "Waiting for the device... Plug it in, it can take a few boot attempts to show up."
+                .to_string()
+        }
+        Some(RecoveryState::NotReady(status)) => {
+            format!("The device is {}: {}", status, status.guidance())
+        }
+        Some(RecoveryState::Booting) => {
+            "Android is starting, waiting for the package manager...".to_string()
+        }
+        Some(RecoveryState::Ready) => {
+            "Some packages could not be restored, retrying...".to_string()
+        }
+    };
+
+    let stop_btn = button(if running.is_done() { "Close" } else { "Stop" })
+        .on_press(Message::StopPressed)
+        .padding(5)
+        .style(style::Button::Primary);
+
+    let header = row![
+        text(format!(
+            "Recovery of {} ({})",
+            running.point.model, running.point.device_id
+        ))
+        .size(20),
+        text(format!("attempt {}", running.attempts)).style(style::Text::Commentary),
+        Space::new(Length::Fill, Length::Shrink),
+        stop_btn,
+    ]
+    .spacing(10)
+    .align_items(Alignment::Center);
+
+    let mut col = column![header, text(state)].spacing(5);
+    if !running.restored.is_empty() {
+        col = col.push(text(format!("Restored: {}", running.restored.join(", "))));
+    }
+    col = running.errors.iter().fold(col, |col, (package, e)| {
+        col.push(text(format!("{}: {}", package, e)).style(style::Text::Danger))
+    });
+
+    container(col)
+        .padding(10)
+        .width(Length::Fill)
+        .style(style::Container::Frame)
+        .into()
+}
@@ -1,6 +1,7 @@
 use crate::core::config::{Config, DeviceSettings, GeneralSettings};
 use crate::core::sync::Phone;
 use crate::core::theme::Theme;
+use crate::core::transport::use_native_transport;
 use crate::core::utils::{open_url, string_to_theme};
 use crate::gui::style;
 
@@ -27,7 +28,10 @@ impl Default for Settings {
 pub enum Message {
     LoadDeviceSettings,
     ExpertMode(bool),
+    NativeAdb(bool),
+    ThirdParty(bool),
     DisableMode(bool),
+    SuspendMode(bool),
     MultiUserMode(bool),
     ApplyTheme(Theme),
     UrlPressed(PathBuf),
@@ -41,9 +45,29 @@ impl Settings {
                 debug!("Config change: {:?}", self);
                 Config::save_changes(self, &phone.adb_id);
             }
+            Message::NativeAdb(toggled) => {
+                self.general.native_adb = toggled;
+                use_native_transport(toggled);
+                debug!("Config change: {:?}", self);
+                Config::save_changes(self, &phone.adb_id);
+            }
+            Message::ThirdParty(toggled) => {
+                self.general.third_party = toggled;
+                debug!("Config change: {:?}", self);
+                Config::save_changes(self, &phone.adb_id);
+            }
             Message::DisableMode(toggled) => {
                 if phone.android_sdk >= 23 {
                     self.device.disable_mode = toggled;
+                    self.device.suspend_mode &= !toggled;
+                    debug!("Config change: {:?}", self);
+                    Config::save_changes(self, &phone.adb_id);
+                }
+            }
+            Message::SuspendMode(toggled) => {
+                if phone.android_sdk >= 28 {
+                    self.device.suspend_mode = toggled;
+                    self.device.disable_mode &= !toggled;
                     debug!("Config change: {:?}", self);
                     Config::save_changes(self, &phone.adb_id);
                 }
@@ -68,13 +92,7 @@ impl Settings {
                     .find(|d| d.device_id == phone.adb_id)
                 {
                     Some(device) => self.device = device.clone(),
-                    None => {
-                        self.device = DeviceSettings {
-                            device_id: phone.adb_id.clone(),
-                            multi_user_mode: phone.android_sdk > 21,
-                            disable_mode: false,
-                        }
-                    }
+                    None => self.device = DeviceSettings::new(phone),
                 };
             }
         }
@@ -112,6 +130,32 @@ impl Settings {
                 .style(style::Text::Commentary)
                 .size(15);
 
+        let native_adb_checkbox = checkbox(
+            "Talk directly to the ADB server instead of using the `adb` binary",
+            self.general.native_adb,
+            Message::NativeAdb,
+        )
+        .style(style::CheckBox::SettingsEnabled);
+
+        let native_adb_descr =
+            text("The ADB server must already be running (e.g. started by `adb start-server`)")
+                .style(style::Text::Commentary)
+                .size(15);
+
+        let third_party_checkbox = checkbox(
+            "Also list the apps installed by the user",
+            self.general.third_party,
+            Message::ThirdParty,
+        )
+        .style(style::CheckBox::SettingsEnabled);
+
+        let third_party_descr = text(
+            "Carrier and OEM apps installed after the first boot are user apps too. \
+            Their installer and install date are shown in the list.",
+        )
+        .style(style::Text::Commentary)
+        .size(15);
+
         let warning_ctn = container(
             row![
                 text("The following settings only affect the currently selected device :")
@@ -179,11 +223,57 @@ impl Settings {
             .width(Length::Fill)
         };
 
-        let general_ctn = container(column![expert_mode_checkbox, expert_mode_descr].spacing(10))
-            .padding(10)
+        let suspend_mode_descr = text(
+            "A suspended app can't run and its notifications are hidden, but its data is kept. \
+            Easy to undo: perfect to test the removal of a package.",
+        )
+        .style(style::Text::Commentary)
+        .size(15);
+
+        // `pm suspend` appeared in Android Pie (9.0)
+        let suspend_mode_checkbox = checkbox(
+            "Suspend packages instead of uninstalling them",
+            self.device.suspend_mode,
+            Message::SuspendMode,
+        )
+        .style(if phone.android_sdk >= 28 {
+            style::CheckBox::SettingsEnabled
+        } else {
+            style::CheckBox::SettingsDisabled
+        });
+
+        let suspend_setting_row = if phone.android_sdk >= 28 {
+            row![
+                suspend_mode_checkbox,
+                Space::new(Length::Fill, Length::Shrink),
+            ]
             .width(Length::Fill)
-            .height(Length::Shrink)
-            .style(style::Container::Frame);
+        } else {
+            row![
+                suspend_mode_checkbox,
+                Space::new(Length::Fill, Length::Shrink),
+                button(text("Unavailable (Android 9+)").size(13))
+                    .height(Length::Units(22))
+                    .style(style::Button::Unavailable),
+            ]
+            .width(Length::Fill)
+        };
+
+        let general_ctn = container(
+            column![
+                expert_mode_checkbox,
+                expert_mode_descr,
+                native_adb_checkbox,
+                native_adb_descr,
+                third_party_checkbox,
+                third_party_descr,
+            ]
+            .spacing(10),
+        )
+        .padding(10)
+        .width(Length::Fill)
+        .height(Length::Shrink)
+        .style(style::Container::Frame);
 
         let device_specific_ctn = container(
             column![
@@ -191,6 +281,8 @@ impl Settings {
                 multi_user_mode_descr,
                 disable_setting_row,
                 disable_mode_descr,
+                suspend_setting_row,
+                suspend_mode_descr,
             ]
             .spacing(10),
         )
@@ -0,0 +1,295 @@
+use crate::core::config::{Config, WirelessDevice};
+use crate::core::error::AdbError;
+use crate::core::sync::Phone;
+use crate::core::theme::Theme;
+use crate::core::wireless::{
+    connect, disconnect, is_pairing_code, pair, parse_address, DEFAULT_PORT,
+};
+use crate::gui::style;
+
+use iced::widget::{button, column, container, row, scrollable, text, text_input, Space};
+use iced::{Alignment, Command, Element, Length, Renderer};
+
+#[derive(Debug, Clone)]
+pub struct Wireless {
+    pub known_devices: Vec<WirelessDevice>,
+    pair_address: String,
+    pairing_code: String,
+    address: String,
+    busy: bool,
+    status: String,
+}
+
+impl Default for Wireless {
+    fn default() -> Self {
+        Self {
+            known_devices: Config::load_configuration_file().wireless_devices,
+            pair_address: String::new(),
+            pairing_code: String::new(),
+            address: String::new(),
+            busy: false,
+            status: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    PairAddressChanged(String),
+    PairingCodeChanged(String),
+    AddressChanged(String),
+    PairPressed,
+    Paired(String, Result<(), AdbError>),
+    /// `None` connects to the address typed by the user
+    ConnectPressed(Option<String>),
+    Connected(String, Result<Phone, AdbError>),
+    DisconnectPressed(String),
+    Disconnected(String, Result<(), AdbError>),
+    ForgetPressed(String),
+}
+
+impl Wireless {
+    /// The selection of a connected device is done by UadGui update()
+    pub fn update(&mut self, msg: Message) -> Command<Message> {
+        match msg {
+            Message::PairAddressChanged(address) => self.pair_address = address,
+            Message::PairingCodeChanged(code) => self.pairing_code = code,
+            Message::AddressChanged(address) => self.address = address,
+            Message::PairPressed => {
+                match parse_address(&self.pair_address) {
+                    Some(address) if is_pairing_code(self.pairing_code.trim()) => {
+                        self.busy = true;
+                        self.status = format!("Pairing with {}...", address);
+                        return Command::perform(
+                            pair(address.clone(), self.pairing_code.trim().to_string()),
+                            move |res| Message::Paired(address, res),
+                        );
+                    }
+                    Some(_) => self.status = "The pairing code is made of 6 digits".to_string(),
+                    None => self.status = format!("Invalid address: `{}`", self.pair_address),
+                };
+            }
+            Message::Paired(address, res) => {
+                self.busy = false;
+                match res {
+                    Ok(_) => {
+                        self.pairing_code.clear();
+                        self.status = format!(
+                            "Paired with {}. Now connect to the IP address and port shown on the \
+                            `Wireless debugging` screen.",
+                            address
+                        );
+                    }
+                    Err(e) => {
+                        error!("Could not pair with {}: {}", address, e);
+                        self.status = format!("Could not pair with {}: {}", address, e);
+                    }
+                }
+            }
+            Message::ConnectPressed(address) => {
+                let address = address.unwrap_or_else(|| self.address.clone());
+                match parse_address(&address) {
+                    Some(address) => {
+                        self.busy = true;
+                        self.status = format!("Connecting to {}...", address);
+                        return Command::perform(connect(address.clone()), move |res| {
+                            Message::Connected(address, res)
+                        });
+                    }
+                    None => self.status = format!("Invalid address: `{}`", address),
+                }
+            }
+            Message::Connected(address, res) => {
+                self.busy = false;
+                match res {
+                    Ok(phone) => {
+                        self.address.clear();
+                        self.status = format!("Connected to {} ({})", phone.model, address);
+                        self.known_devices = Config::save_wireless_device(WirelessDevice {
+                            address,
+                            model: phone.model,
+                        });
+                    }
+                    Err(e) => {
+                        error!("Could not connect to {}: {}", address, e);
+                        self.status = format!("Could not connect to {}: {}", address, e);
+                    }
+                }
+            }
+            Message::DisconnectPressed(address) => {
+                return Command::perform(disconnect(address.clone()), move |res| {
+                    Message::Disconnected(address, res)
+                });
+            }
+            Message::Disconnected(address, res) => {
+                self.status = match res {
+                    Ok(_) => format!("Disconnected from {}", address),
+                    Err(e) => format!("Could not disconnect from {}: {}", address, e),
+                };
+            }
+            Message::ForgetPressed(address) => {
+                self.known_devices = Config::forget_wireless_device(&address);
+                self.status = format!("{} forgotten", address);
+            }
+        }
+        Command::none()
+    }
+
+    /// `devices` are the connected devices
+    pub fn view(&self, devices: &[Phone]) -> Element<Message, Renderer<Theme>> {
+        let pair_address_input = text_input(
+            "IP address & port (e.g. 192.168.1.20:37123)",
+            &self.pair_address,
+            Message::PairAddressChanged,
+        )
+        .padding(5)
+        .width(Length::Units(350));
+
+        let pairing_code_input = text_input(
+            "Pairing code",
+            &self.pairing_code,
+            Message::PairingCodeChanged,
+        )
+        .padding(5)
+        .width(Length::Units(150));
+
+        let pair_btn = if self.busy {
+            button("Pair").style(style::Button::Unavailable)
+        } else {
+            button("Pair")
+                .on_press(Message::PairPressed)
+                .style(style::Button::Primary)
+        };
+
+        let pair_descr = text(
+            "Android 11+: Developer options > Wireless debugging > Pair device with pairing code. \
+            Only needed once per computer.",
+        )
+        .style(style::Text::Commentary);
+
+        let pair_ctn = container(
+            column![
+                text("Pair a new device").size(20),
+                row![pair_address_input, pairing_code_input, pair_btn.padding(5)]
+                    .spacing(10)
+                    .align_items(Alignment::Center),
+                pair_descr,
+            ]
+            .spacing(10),
+        )
+        .padding(10)
+        .width(Length::Fill)
+        .style(style::Container::Frame);
+
+        let address_input = text_input(
+            "IP address & port (e.g. 192.168.1.20:41235)",
+            &self.address,
+            Message::AddressChanged,
+        )
+        .on_submit(Message::ConnectPressed(None))
+        .padding(5)
+        .width(Length::Units(350));
+
+        let connect_btn = if self.busy {
+            button("Connect").style(style::Button::Unavailable)
+        } else {
+            button("Connect")
+                .on_press(Message::ConnectPressed(None))
+                .style(style::Button::Primary)
+        };
+
+        let connect_descr = text(format!(
+            "Use the address shown on the `Wireless debugging` screen. Android 10 and older: \
+            run `adb tcpip {}` with the device plugged in, then use its IP address.",
+            DEFAULT_PORT
+        ))
+        .style(style::Text::Commentary);
+
+        let connect_ctn = container(
+            column![
+                text("Connect").size(20),
+                row![address_input, connect_btn.padding(5)]
+                    .spacing(10)
+                    .align_items(Alignment::Center),
+                connect_descr,
+            ]
+            .spacing(10),
+        )
+        .padding(10)
+        .width(Length::Fill)
+        .style(style::Container::Frame);
+
+        let known_devices = if self.known_devices.is_empty() {
+            column![text("No known wireless device")]
+        } else {
+            self.known_devices
+                .iter()
+                .fold(column![].spacing(10), |col, device| {
+                    col.push(self.device_row(device, devices))
+                })
+        };
+
+        let mut content = column![pair_ctn, connect_ctn].spacing(15);
+        if !self.status.is_empty() {
+            content = content.push(text(&self.status));
+        }
+        content = content.push(text("Known devices").size(20)).push(
+            scrollable(known_devices)
+                .scrollbar_margin(2)
+                .height(Length::Fill)
+                .style(style::Scrollable::Packages),
+        );
+
+        container(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(10)
+            .into()
+    }
+
+    fn device_row(
+        &self,
+        device: &WirelessDevice,
+        devices: &[Phone],
+    ) -> Element<Message, Renderer<Theme>> {
+        let connected = devices.iter().find(|d| d.adb_id == device.address);
+        let state = match connected {
+            Some(phone) => phone.status.to_string(),
+            None => "not connected".to_string(),
+        };
+
+        let action_btn = match connected {
+            Some(_) => button("Disconnect")
+                .on_press(Message::DisconnectPressed(device.address.clone()))
+                .padding(5)
+                .style(style::Button::Primary),
+            None if self.busy => button("Connect")
+                .padding(5)
+                .style(style::Button::Unavailable),
+            None => button("Connect")
+                .on_press(Message::ConnectPressed(Some(device.address.clone())))
+                .padding(5)
+                .style(style::Button::Primary),
+        };
+
+        container(
+            row![
+                text(&device.model).width(Length::Units(250)),
+                text(&device.address).width(Length::Units(200)),
+                text(state).style(style::Text::Commentary),
+                Space::new(Length::Fill, Length::Shrink),
+                action_btn,
+                button("Forget")
+                    .on_press(Message::ForgetPressed(device.address.clone()))
+                    .padding(5)
+                    .style(style::Button::Primary),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+        )
+        .padding(10)
+        .width(Length::Fill)
+        .style(style::Container::BorderedFrame)
+        .into()
+    }
+}
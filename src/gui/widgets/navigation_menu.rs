@@ -1,10 +1,11 @@
-pub use crate::core::sync::Phone;
+use crate::core::config::WirelessDevice;
+pub use crate::core::sync::{DeviceStatus, Phone, RebootTarget};
 use crate::core::theme::Theme;
 use crate::core::update::{SelfUpdateState, SelfUpdateStatus};
 pub use crate::gui::views::about::Message as AboutMessage;
 pub use crate::gui::views::list::{List as AppsView, LoadingState as ListLoadingState};
 use crate::gui::{style, Message};
-use iced::widget::{button, container, pick_list, row, text, Space, Text};
+use iced::widget::{button, column, container, pick_list, row, text, Space, Text};
 use iced::{alignment, Alignment, Element, Font, Length, Renderer};
 
 pub const ICONS: Font = Font::External {
@@ -14,6 +15,7 @@ pub const ICONS: Font = Font::External {
 
 pub fn nav_menu<'a>(
     device_list: &'a Vec<Phone>,
+    wireless_devices: &[WirelessDevice],
     selected_device: Option<Phone>,
     apps_view: &AppsView,
     self_update_state: &SelfUpdateState,
@@ -29,10 +31,9 @@ pub fn nav_menu<'a>(
     .padding(5)
     .style(style::Button::Refresh);
 
-    let reboot_btn = button("Reboot")
-        .on_press(Message::RebootButtonPressed)
-        .padding(5)
-        .style(style::Button::Refresh);
+    let reboot_btn = pick_list(RebootTarget::ALL.to_vec(), None, Message::RebootSelected)
+        .placeholder("Reboot")
+        .padding(5);
 
     let uad_version_text = if let Some(r) = &self_update_state.latest_release {
         if self_update_state.status == SelfUpdateStatus::Updating {
@@ -65,24 +66,78 @@ pub fn nav_menu<'a>(
         .padding(5)
         .style(style::Button::Primary);
 
+    let history_btn = button("History")
+        .on_press(Message::HistoryPressed)
+        .padding(5)
+        .style(style::Button::Primary);
+
+    let profiles_btn = button("Profiles")
+        .on_press(Message::ProfilesPressed)
+        .padding(5)
+        .style(style::Button::Primary);
+
+    let wireless_btn = button("Connect device")
+        .on_press(Message::WirelessPressed)
+        .padding(5)
+        .style(style::Button::Primary);
+
+    let recovery_btn = button("Recovery")
+        .on_press(Message::RecoveryPressed)
+        .padding(5)
+        .style(style::Button::Primary);
+
     let settings_btn = button("Settings")
         .on_press(Message::SettingsPressed)
         .padding(5)
         .style(style::Button::Primary);
 
+    // Known wireless devices that are not connected: selecting one connects to it
+    let mut ready_devices: Vec<Phone> = device_list
+        .iter()
+        .filter(|d| d.is_ready())
+        .cloned()
+        .collect();
+    ready_devices.extend(
+        wireless_devices
+            .iter()
+            .filter(|w| !device_list.iter().any(|d| d.adb_id == w.address))
+            .map(|w| Phone {
+                model: format!("{} (Wi-Fi, not connected)", w.model),
+                adb_id: w.address.clone(),
+                status: DeviceStatus::Offline,
+                ..Phone::default()
+            }),
+    );
+    let other_devices: Vec<&Phone> = device_list.iter().filter(|d| !d.is_ready()).collect();
+
     let device_list_text = match apps_view.loading_state {
         ListLoadingState::FindingPhones => text("finding connected phone..."),
+        ListLoadingState::Rebooting(target) => text(format!("rebooting into {}...", target)),
+        _ if !other_devices.is_empty() => text("no usable device (see below)"),
         _ => text("no devices/emulators found"),
     };
 
+    // Without a selected device, the picker is only useful to connect a known one
+    let wireless_picker: Element<Message, Renderer<Theme>> = if ready_devices.is_empty() {
+        Space::new(Length::Shrink, Length::Shrink).into()
+    } else {
+        pick_list(ready_devices.clone(), None, Message::DeviceSelected)
+            .placeholder("Wi-Fi devices...")
+            .into()
+    };
+
     let row = match selected_device {
         Some(phone) => row![
             apps_refresh_btn,
             reboot_btn,
-            pick_list(device_list, Some(phone), Message::DeviceSelected,),
+            pick_list(ready_devices, Some(phone), Message::DeviceSelected,),
             Space::new(Length::Fill, Length::Shrink),
             uad_version_text,
             apps_btn,
+            history_btn,
+            profiles_btn,
+            recovery_btn,
+            wireless_btn,
             about_btn,
             settings_btn,
         ]
@@ -93,9 +148,14 @@ pub fn nav_menu<'a>(
             reboot_btn,
             apps_refresh_btn,
             device_list_text,
+            wireless_picker,
             Space::new(Length::Fill, Length::Shrink),
             uad_version_text,
             apps_btn,
+            history_btn,
+            profiles_btn,
+            recovery_btn,
+            wireless_btn,
             about_btn,
             settings_btn,
         ]
@@ -104,7 +164,23 @@ pub fn nav_menu<'a>(
         .spacing(10),
     };
 
-    container(row)
+    // Devices that can't be used yet and what to do about it
+    let guidance = other_devices
+        .iter()
+        .fold(column![].spacing(5), |col, phone| {
+            col.push(
+                text(format!(
+                    "{} ({}) is {}: {}",
+                    phone.model,
+                    phone.adb_id,
+                    phone.status,
+                    phone.status.guidance()
+                ))
+                .style(style::Text::Danger),
+            )
+        });
+
+    container(column![row, guidance].spacing(5))
         .width(Length::Fill)
         .padding(10)
         .style(style::Container::Frame)
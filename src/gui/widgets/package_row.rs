@@ -4,8 +4,9 @@ use crate::core::uad_lists::{PackageState, Removal, UadList};
 use crate::gui::style;
 use crate::gui::views::settings::Settings;
 
-use iced::widget::{button, checkbox, row, text, Space};
+use iced::widget::{button, checkbox, column, container, image, pick_list, row, text, Space};
 use iced::{alignment, Alignment, Command, Element, Length, Renderer};
+use std::path::PathBuf;
 
 #[derive(Clone, Debug)]
 pub struct PackageRow {
@@ -16,6 +17,48 @@ pub struct PackageRow {
     pub removal: Removal,
     pub selected: bool,
     pub current: bool,
+    /// Third-party apps only
+    pub installer: Option<String>,
+    pub install_date: Option<String>,
+    /// Application label and launcher icon, once read from the APK
+    pub label: Option<String>,
+    pub icon: Option<PathBuf>,
+    /// State after the removal (`Uninstalled`, `Disabled` or `Suspended`) when it isn't
+    /// the one of the settings
+    pub target: Option<PackageState>,
+}
+
+/// Removal action picked for a package. `None` follows the settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RemovalTarget(pub Option<PackageState>);
+
+impl RemovalTarget {
+    /// Removal actions available on a device
+    pub fn available(android_sdk: u8) -> Vec<Self> {
+        let mut targets = vec![Self(None), Self(Some(PackageState::Uninstalled))];
+        if android_sdk >= 23 {
+            targets.push(Self(Some(PackageState::Disabled)));
+        }
+        if android_sdk >= 28 {
+            targets.push(Self(Some(PackageState::Suspended)));
+        }
+        targets
+    }
+}
+
+impl std::fmt::Display for RemovalTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self.0 {
+                None => "Default",
+                Some(PackageState::Disabled) => "Disable",
+                Some(PackageState::Suspended) => "Suspend",
+                Some(_) => "Uninstall",
+            }
+        )
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -23,6 +66,7 @@ pub enum Message {
     PackagePressed,
     ActionPressed,
     ToggleSelection(bool),
+    TargetSelected(RemovalTarget),
 }
 
 impl PackageRow {
@@ -43,6 +87,11 @@ impl PackageRow {
             removal,
             selected,
             current,
+            installer: None,
+            install_date: None,
+            label: None,
+            icon: None,
+            target: None,
         }
     }
 
@@ -50,7 +99,7 @@ impl PackageRow {
         Command::none()
     }
 
-    pub fn view(&self, settings: &Settings, _phone: &Phone) -> Element<Message, Renderer<Theme>> {
+    pub fn view(&self, settings: &Settings, phone: &Phone) -> Element<Message, Renderer<Theme>> {
         //let trash_svg = format!("{}/resources/assets/trash.svg", env!("CARGO_MANIFEST_DIR"));
         //let restore_svg = format!("{}/resources/assets/rotate.svg", env!("CARGO_MANIFEST_DIR"));
         let button_style;
@@ -60,7 +109,10 @@ impl PackageRow {
 
         match self.state {
             PackageState::Enabled => {
-                action_text = if settings.device.disable_mode {
+                let device = settings.device.with_target(self.target);
+                action_text = if device.suspend_mode {
+                    "Suspend"
+                } else if device.disable_mode {
                     "Disable"
                 } else {
                     "Uninstall"
@@ -75,6 +127,10 @@ impl PackageRow {
                 action_text = "Restore";
                 button_style = style::Button::RestorePackage;
             }
+            PackageState::Suspended => {
+                action_text = "Unsuspend";
+                button_style = style::Button::RestorePackage;
+            }
             PackageState::All => {
                 action_text = "Error";
                 button_style = style::Button::RestorePackage;
@@ -106,11 +162,62 @@ impl PackageRow {
             );
         }
 
+        // Where a user-installed app comes from (e.g. the Play Store or the carrier)
+        let origin = if self.uad_list == UadList::ThirdParty {
+            text(format!(
+                "{} | {}",
+                self.installer.as_deref().unwrap_or("unknown installer"),
+                self.install_date.as_deref().unwrap_or("unknown date")
+            ))
+            .style(style::Text::Commentary)
+            .width(Length::FillPortion(5))
+        } else {
+            text("").width(Length::Shrink)
+        };
+
+        // Same width with or without an icon: the names stay aligned
+        // There is nothing to choose before Android 6.0 or when the package is removed
+        let target: Element<Message, Renderer<Theme>> =
+            if self.state == PackageState::Enabled && phone.android_sdk >= 23 {
+                pick_list(
+                    RemovalTarget::available(phone.android_sdk),
+                    Some(RemovalTarget(self.target)),
+                    Message::TargetSelected,
+                )
+                .text_size(14)
+                .width(Length::Units(110))
+                .into()
+            } else {
+                Space::with_width(Length::Units(110)).into()
+            };
+
+        let icon: Element<Message, Renderer<Theme>> = match &self.icon {
+            Some(path) => image(image::Handle::from_path(path))
+                .width(Length::Units(24))
+                .height(Length::Units(24))
+                .into(),
+            None => Space::with_width(Length::Units(24)).into(),
+        };
+
+        let name: Element<Message, Renderer<Theme>> = match &self.label {
+            Some(label) => column![
+                text(label),
+                text(&self.name).size(14).style(style::Text::Commentary)
+            ]
+            .into(),
+            None => text(&self.name).into(),
+        };
+
         row![
             button(
                 row![
                     selection_checkbox,
-                    text(&self.name).width(Length::FillPortion(8)),
+                    icon,
+                    Space::with_width(Length::Units(8)),
+                    container(name).width(Length::FillPortion(8)),
+                    origin,
+                    target,
+                    Space::with_width(Length::Units(10)),
                     action_btn.style(button_style)
                 ]
                 .align_items(Alignment::Center)
@@ -3,6 +3,8 @@
 #[macro_use]
 extern crate log;
 
+use crate::core::config::Config;
+use crate::core::transport::use_native_transport;
 use crate::core::utils::setup_uad_dir;
 use fern::{
     colors::{Color, ColoredLevelConfig},
@@ -13,21 +15,68 @@ use static_init::dynamic;
 use std::path::PathBuf;
 use std::{fmt::Arguments, fs::OpenOptions};
 
+mod cli;
 mod core;
 mod gui;
 
+#[cfg(not(test))]
 #[dynamic]
 static CONFIG_DIR: PathBuf = setup_uad_dir(dirs::config_dir());
 
+#[cfg(not(test))]
 #[dynamic]
 static CACHE_DIR: PathBuf = setup_uad_dir(dirs::cache_dir());
 
+// The tests must not touch the profiles, journal, config and cache of the user
+#[cfg(test)]
+#[dynamic]
+static CONFIG_DIR: PathBuf = setup_uad_dir(Some(std::env::temp_dir().join("uad_tests/config")));
+
+#[cfg(test)]
+#[dynamic]
+static CACHE_DIR: PathBuf = setup_uad_dir(Some(std::env::temp_dir().join("uad_tests/cache")));
+
 fn main() {
-    setup_logger().expect("setup logging");
-    gui::UadGui::start();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    use_native_transport(Config::load_configuration_file().general.native_adb);
+    match cli::parse_args(&args) {
+        Some(parsed) => {
+            #[cfg(target_os = "windows")]
+            attach_console();
+            setup_logger(true).expect("setup logging");
+            let code = match parsed {
+                Ok((command, options)) => cli::run(command, &options),
+                Err(err) => {
+                    eprintln!("{}\n\n{}", err, cli::USAGE);
+                    cli::EXIT_USAGE
+                }
+            };
+            std::process::exit(code);
+        }
+        None => {
+            setup_logger(false).expect("setup logging");
+            gui::UadGui::start();
+        }
+    }
+}
+
+// UAD is built for the GUI subsystem: without the console of the terminal it was started
+// from, the output of the CLI would be lost
+#[cfg(target_os = "windows")]
+fn attach_console() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    // Fails if there is no parent console (e.g. started from the file explorer): nothing to do then
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
 }
 
-pub fn setup_logger() -> Result<(), fern::InitError> {
+// In headless mode, stdout is reserved for the machine-readable output
+pub fn setup_logger(headless: bool) -> Result<(), fern::InitError> {
     let colors = ColoredLevelConfig::new().info(Color::Green);
 
     let make_formatter = |use_colors: bool| {
@@ -64,8 +113,13 @@ pub fn setup_logger() -> Result<(), fern::InitError> {
     let stdout_dispatcher = fern::Dispatch::new()
         .format(make_formatter(true))
         .level(default_log_level)
-        .level_for("uad_gui", log::LevelFilter::Warn)
-        .chain(std::io::stdout());
+        .level_for("uad_gui", log::LevelFilter::Warn);
+
+    let stdout_dispatcher = if headless {
+        stdout_dispatcher.chain(std::io::stderr())
+    } else {
+        stdout_dispatcher.chain(std::io::stdout())
+    };
 
     fern::Dispatch::new()
         .chain(stdout_dispatcher)